@@ -1,93 +1,185 @@
-use std::sync::{Arc, Mutex};
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use minifb::{Key, KeyRepeat};
 use tracing::{info, warn};
 use crate::convolutions::*;
-use crate::convolutions::base::SingleColorConvolution;
-use crate::convolutions::fire::{IdkConvolution, IdkParConvolution};
-use crate::convolutions::time::TimeConvolution;
-
-pub fn run(width: usize, height: usize) {
-    let mut window = Window::new(
-        "Widget Hora: Fondo Procedural Example",
-        width,
-        height,
-    );
-
-    let convolutions = &mut window.convolutions;
-
-    // Fondo Negro de la app
-    convolutions.push((ConvolutionType::Simple(
-        Box::new(SingleColorConvolution {
-            red: 20,
-            green: 20,
-            blue: 20,
-        })
-    ), true));
-
-    // Backgrounds
-    convolutions.push((ConvolutionType::Advanced(
-        Box::new(IdkConvolution::new(width, height))
-    ), false));
-    convolutions.push((ConvolutionType::Advanced(
-        Box::new(IdkParConvolution::new(width, height))
-    ), false));
-    // End Backgrounds
-
-    // Time's layer
-    convolutions.push((ConvolutionType::Simple(
-        Box::new(TimeConvolution {f24: true, color: Option::from(Color::rgb(255, 255, 255 ))})
-        // Box::new(TimeConvolution {f24: false, color: None})
-    ), true));
-
-    window.run();
+use crate::convolutions::registry::Registry;
+use crate::bench;
+use crate::cli::{Options, TargetKind};
+use crate::clock::{Clock, SystemClock};
+use crate::pacing::{FramePacing, Pacer};
+use crate::pipeline::Pipeline;
+use crate::quality::QualityController;
+use crate::scene::{Scene, SceneError};
+use crate::target::headless::HeadlessTarget;
+use crate::target::window::MinifbTarget;
+use crate::target::{RenderTarget, TargetError};
+use crate::triple_buffer::{triple_buffer, FrameWriter};
+
+/// Scene used when no `--scene` is given
+const DEFAULT_SCENE: &str = include_str!("../scenes/default.toml");
+
+/// Times per second that the window handles its events and presents the
+/// latest frame, independent of the frame rate of the layers
+const EVENT_RATE: f64 = 240.0;
+
+/// Error that stops the app
+#[derive(Debug)]
+pub enum AppError {
+    /// The scene can't be loaded or built
+    Scene(SceneError),
+    /// The frames can't be presented or written
+    Target(TargetError),
 }
 
-// #[derive(Debug)]
-pub struct Window {
-    buffer: Arc<Mutex<Vec<u32>>>,
-    convolutions: Vec<(ConvolutionType, bool)>,
-    width: usize,
-    height: usize,
-    window: minifb::Window,
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Scene(error) => write!(f, "{}", error),
+            AppError::Target(error) => write!(f, "{}", error),
+        }
+    }
 }
 
-impl Window {
-    pub fn new(title: &str, width: usize, height: usize) -> Self {
-        let window = minifb::Window::new(
-            title,
-            width,
-            height,
-            minifb::WindowOptions {
-                borderless: true,
-                title: true,
-                resize: true,
-                scale: minifb::Scale::FitScreen,
-                scale_mode: minifb::ScaleMode::Stretch,
-                topmost: true,
-                transparency: false,
-                none: false,
-            },
-        ).expect("The window can't be created");
-
-        // window.set_target_fps(60);
-
-        let buffer = vec![0u32; width * height];
+impl std::error::Error for AppError {}
 
-        Window {
-            window,
-            buffer: Arc::new(Mutex::new(buffer)),
-            convolutions: Vec::new(),
-            width,
-            height
+impl From<SceneError> for AppError {
+    fn from(error: SceneError) -> Self {
+        AppError::Scene(error)
+    }
+}
+
+impl From<TargetError> for AppError {
+    fn from(error: TargetError) -> Self {
+        AppError::Target(error)
+    }
+}
+
+pub fn run(options: &Options) -> Result<(), AppError> {
+    let (width, height) = (options.width, options.height);
+
+    let scene = match &options.scene {
+        Some(path) => Scene::load(path)?,
+        None => Scene::from_toml(DEFAULT_SCENE)?,
+    };
+    let layers = scene.build(&Registry::builtin(), width, height)?;
+
+    let pool = build_pool(options.threads.or(scene.threads));
+    let mut renderer = Renderer::new(width, height, pool);
+    let mut window = match &options.target {
+        TargetKind::Window => {
+            renderer.pacer = Pacer::new(options.pacing);
+            Window::new(
+                "Widget Hora: Fondo Procedural Example",
+                width,
+                height,
+            )
+        }
+        TargetKind::Headless { frames, output, format } => Window::with_target(
+            Box::new(HeadlessTarget::new(*frames, output.clone(), *format)?),
+        ),
+    };
+
+    renderer.clock = options.clock.build();
+    renderer.quality = options.budget.map(QualityController::new);
+    renderer.pipeline.layers = layers;
+    renderer.pipeline.tiled = options.tiled || scene.tiled;
+    renderer.pipeline.set_scale(options.scale.or(scene.scale).unwrap_or(1.0));
+    renderer.pipeline.filter = match (options.filter, &scene.filter) {
+        (Some(filter), _) => filter,
+        (None, Some(filter)) => filter.parse().map_err(SceneError::Parse)?,
+        (None, None) => Filter::Nearest,
+    };
+
+    window.run(renderer)?;
+    Ok(())
+}
+
+/// Rayon pool of the app, `None` uses every core
+pub fn build_pool(threads: Option<usize>) -> rayon::ThreadPool {
+    let threads = threads.unwrap_or_else(bench::available_threads);
+    info!("Using {} threads", threads);
+
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .expect("The thread pool can't be created")
+}
+
+/// Order sent from the main thread to the render thread
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Toggles the visibility of the layer with that index
+    Toggle(usize),
+    ToggleLast,
+    /// Resets the active advanced layers
+    Reset,
+    Edit(Edit),
+    Resize(usize, usize),
+    Input(InputState),
+    Quit,
+}
+
+/// Keyboard edition of the layer parameters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edit {
+    NextLayer,
+    NextParam,
+    PreviousParam,
+    /// Decreases (-1) or increases (1) the selected parameter
+    Step(i32),
+}
+
+/// Renders the layer stack on its own thread
+///
+/// Owns the pipeline and everything needed to draw it; the main thread
+/// only sends [`Command`]s and receives status texts, so a slow layer
+/// never blocks the events of the window.
+pub struct Renderer {
+    pub pipeline: Pipeline,
+    pub clock: Arc<dyn Clock>,
+    /// The layers run inside this pool
+    pub pool: rayon::ThreadPool,
+    /// Frame pacing of the layers, unlimited by default
+    pub pacer: Pacer,
+    /// Adaptive quality of the layers, only with a frame budget
+    pub quality: Option<QualityController>,
+    ctx: FrameContext,
+    started: Instant,
+    last_frame: Instant,
+    selected_layer: usize,
+    selected_param: usize,
+}
+
+impl Renderer {
+    pub fn new(width: usize, height: usize, pool: rayon::ThreadPool) -> Self {
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let now = Instant::now();
+
+        Renderer {
+            pipeline: Pipeline::new(width, height),
+            ctx: FrameContext::new(clock.now()),
+            clock,
+            pool,
+            pacer: Pacer::new(FramePacing::Unlimited),
+            quality: None,
+            started: now,
+            last_frame: now,
+            selected_layer: 0,
+            selected_param: 0,
         }
     }
 
     fn reset_convolutions(&mut self) {
-        for (convolution, is_active) in self.convolutions.iter_mut() {
-            if !*is_active {
+        for layer in self.pipeline.layers.iter_mut() {
+            if !layer.active {
                 continue
             }
 
-            match convolution {
+            match &mut layer.convolution {
                 ConvolutionType::Simple(convolution) => {
                     let name = convolution.name();
                     warn!("{:?} hasn't reset", name);
@@ -101,83 +193,387 @@ impl Window {
         }
     }
 
-    #[allow(unused)]
-    fn transform_buffer(&mut self, convolution: &mut Box<dyn Convolution>) {
-        let mut screen = self.buffer
-            .lock()
-            .expect("The mutex is poisoned");
+    /// Applies a command, returns the status to show if there is one
+    fn handle(&mut self, command: Command) -> Option<String> {
+        match command {
+            Command::Toggle(index) => {
+                if let Some(layer) = self.pipeline.layers.get_mut(index) {
+                    layer.active = !layer.active;
+                    self.pipeline.invalidate();
+                }
+            }
+            Command::ToggleLast => {
+                if let Some(layer) = self.pipeline.layers.last_mut() {
+                    layer.active = !layer.active;
+                    self.pipeline.invalidate();
+                }
+            }
+            Command::Reset => {
+                self.reset_convolutions();
+                self.pipeline.invalidate();
+            }
+            Command::Edit(edit) => return self.edit_params(edit),
+            Command::Resize(width, height) => self.pipeline.resize(width, height),
+            Command::Input(input) => self.ctx.input = input,
+            Command::Quit => {}
+        }
+
+        None
+    }
+
+    /// Keyboard edition of the layer parameters: Tab selects the next layer,
+    /// Up/Down the parameter and Left/Right step its value
+    fn edit_params(&mut self, edit: Edit) -> Option<String> {
+        let layers = &mut self.pipeline.layers;
+        if layers.is_empty() {
+            return None;
+        }
+
+        if edit == Edit::NextLayer {
+            self.selected_layer = (self.selected_layer + 1) % layers.len();
+            self.selected_param = 0;
+        }
+
+        let layer = &mut layers[self.selected_layer];
+        let params = layer.convolution.params();
+        if params.is_empty() {
+            return (edit == Edit::NextLayer).then(|| format!("{}: no parameters", layer.convolution.name()));
+        }
+
+        match edit {
+            Edit::NextParam => self.selected_param += 1,
+            Edit::PreviousParam => self.selected_param += params.len() - 1,
+            _ => {}
+        }
+        self.selected_param %= params.len();
 
-        let mut new_buffer = screen.clone();
-        convolution.transform(&mut new_buffer, self.width, self.height);
+        let param = &params[self.selected_param];
+        let mut value = param.value.clone();
+        if let Edit::Step(direction) = edit {
+            value = param.spec.step(&param.value, direction);
+            if let Err(error) = layer.convolution.set_param(param.spec.name, value.clone()) {
+                warn!("{}", error);
+            }
+        }
+
+        let status = format!("{}: {} = {}", layer.convolution.name(), param.spec.name, value);
+        if let Edit::Step(_) = edit {
+            self.pipeline.invalidate();
+        }
 
-        screen.copy_from_slice(&*new_buffer);
+        Some(status)
     }
 
-    fn apply_convolutions(&mut self) {
-        for (convolution, is_active) in self.convolutions.iter_mut() {
-            if !*is_active {
-                continue
+    /// Lowers or restores the quality of the layers to keep the frames
+    /// inside the budget
+    fn adapt_quality(&mut self, frame_time: Duration) -> Option<String> {
+        let status = self.quality.as_mut()?.update(frame_time, &mut self.pipeline.layers)?;
+        self.pipeline.invalidate();
+        Some(status)
+    }
+
+    /// Samples the clock and the timers for the next frame
+    fn update_context(&mut self) {
+        let now = Instant::now();
+
+        self.ctx.dt = now - self.last_frame;
+        self.ctx.elapsed = now - self.started;
+        self.ctx.now = self.clock.now();
+        self.ctx.animation = self.clock.animation().unwrap_or(self.ctx.elapsed);
+        self.last_frame = now;
+    }
+
+    /// Loop of the render thread, it ends with [`Command::Quit`] or when
+    /// the main thread stops reading frames
+    pub fn run(mut self, commands: Receiver<Command>, status: Sender<String>, mut frames: FrameWriter) {
+        self.started = Instant::now();
+        self.last_frame = self.started;
+        self.pacer.start();
+        info!("Frame pacing: {}", self.pacer.pacing);
+
+        loop {
+            loop {
+                let command = match commands.try_recv() {
+                    Ok(Command::Quit) | Err(TryRecvError::Disconnected) => return,
+                    Ok(command) => command,
+                    Err(TryRecvError::Empty) => break,
+                };
+
+                if let Some(text) = self.handle(command) {
+                    let _ = status.send(text);
+                }
             }
 
-            let name = match convolution {
-                ConvolutionType::Simple(conv) => conv.name(),
-                ConvolutionType::Advanced(conv) => conv.name(),
-            };
+            self.update_context();
 
-            info!("Applying {:?} to buffer", name);
+            let start = Instant::now();
+            let (pipeline, ctx) = (&mut self.pipeline, &self.ctx);
+            let changed = self.pool.install(|| pipeline.render(ctx));
+            if changed {
+                if let Some(text) = self.adapt_quality(start.elapsed()) {
+                    let _ = status.send(text);
+                }
+            }
 
-            let mut screen = self.buffer
-                .lock()
-                .expect("The mutex is poisoned");
+            // Sin cambios no se publica nada, salvo si se graba cada frame
+            if changed || frames.lossless() {
+                let pipeline = &self.pipeline;
+                if !frames.publish(pipeline.frame(), pipeline.width(), pipeline.height()) {
+                    return;
+                }
 
-            let mut new_buffer = screen.clone();
-            match convolution {
-                ConvolutionType::Simple(conv) => conv.transform(&mut new_buffer, self.width, self.height),
-                ConvolutionType::Advanced(conv) => conv.transform(&mut new_buffer, self.width, self.height),
+                self.ctx.frame += 1;
+                self.pacer.frame_rendered();
             }
 
-            screen.copy_from_slice(&*new_buffer);
+            self.clock.tick();
+
+            self.pacer.wait();
         }
     }
+}
+
+/// Window of the app, it handles the events and presents the frames of
+/// the [`Renderer`], that runs on another thread
+pub struct Window {
+    target: Box<dyn RenderTarget>,
+    /// Last status text, shown next to the achieved frame rate
+    status: String,
+    /// Frames presented, to get the achieved frame rate
+    pacer: Pacer,
+    /// Size of the frame sent to the renderer
+    frame: (usize, usize),
+    /// Size of the target and of the frame when the app started
+    initial_size: Option<((usize, usize), (usize, usize))>,
+    /// Last input sent to the renderer
+    input: InputState,
+}
 
-    fn render(&mut self) {
-        let buffer = self.buffer.lock().expect("The mutex is poisoned");
-        self.window.update_with_buffer(&buffer, self.width, self.height).expect("The window can't be updated");
+impl Window {
+    pub fn new(title: &str, width: usize, height: usize) -> Self {
+        Self::with_target(Box::new(MinifbTarget::new(title, width, height)))
     }
 
-    pub fn run(&mut self) {
-        while self.window.is_open()
-            && !self.window.is_key_down(minifb::Key::Escape)
-            && !self.window.is_key_down(minifb::Key::Q)
+    pub fn with_target(target: Box<dyn RenderTarget>) -> Self {
+        Window {
+            target,
+            status: String::new(),
+            pacer: Pacer::new(FramePacing::Fixed(EVENT_RATE)),
+            frame: (0, 0),
+            initial_size: None,
+            input: InputState::default(),
+        }
+    }
+
+    /// Commands of the keyboard for the renderer
+    fn commands(&self) -> Vec<Command> {
+        let mut commands = Vec::new();
+
+        // Backgrounds visibility
+        for (i, key) in [
+            Key::Key1, Key::Key2, Key::Key3,
+            Key::Key4, Key::Key5, Key::Key6,
+            Key::Key7, Key::Key8, Key::Key9,
+        ]
+            .iter()
+            .enumerate()
         {
-            // Backgrounds visibility
-            for (i, key) in [
-                minifb::Key::Key1, minifb::Key::Key2
-            ]
-                .iter()
-                .enumerate()
-            {
-                if self.window.is_key_pressed(*key, minifb::KeyRepeat::No) && i+1<3 {
-                    if let Some((_, active)) = self.convolutions.get_mut(i+1) {
-                        *active = !*active;
-                    }
-                }
+            if self.target.is_key_pressed(*key, KeyRepeat::No) {
+                commands.push(Command::Toggle(i + 1));
             }
+        }
 
-            if self.window.is_key_down(minifb::Key::R) { // reset convolution active
-                self.reset_convolutions();
+        if self.target.is_key_down(Key::R) { // reset convolution active
+            commands.push(Command::Reset);
+        }
+
+        if self.target.is_key_released(Key::T) { // toggle clock
+            commands.push(Command::ToggleLast);
+        }
+
+        let edits = [
+            (Key::Tab, KeyRepeat::No, Edit::NextLayer),
+            (Key::Down, KeyRepeat::No, Edit::NextParam),
+            (Key::Up, KeyRepeat::No, Edit::PreviousParam),
+            (Key::Right, KeyRepeat::Yes, Edit::Step(1)),
+            (Key::Left, KeyRepeat::Yes, Edit::Step(-1)),
+        ];
+        for (key, repeat, edit) in edits {
+            if self.target.is_key_pressed(key, repeat) {
+                commands.push(Command::Edit(edit));
             }
+        }
+
+        commands
+    }
+
+    /// Resizes the frame when the target changes of size, keeping the
+    /// scale between the window and the frame of the start
+    fn follow_resize(&mut self) -> Option<Command> {
+        let (width, height) = self.target.size()?;
+
+        if width == 0 || height == 0 {
+            return None; // Ventana minimizada
+        }
+
+        let ((initial_width, initial_height), (frame_width, frame_height)) =
+            *self.initial_size.get_or_insert(((width, height), self.frame));
+
+        let frame = (
+            (frame_width * width / initial_width).max(1),
+            (frame_height * height / initial_height).max(1),
+        );
+        if frame == self.frame {
+            return None;
+        }
+
+        self.frame = frame;
+        Some(Command::Resize(frame.0, frame.1))
+    }
+
+    fn show_status(&mut self, status: String) {
+        info!("{}", status);
+        self.status = status;
+        self.update_title();
+    }
+
+    /// Shows the achieved frame rate and the last status on the target
+    fn update_title(&mut self) {
+        let fps = format!("{:.1} fps", self.pacer.fps());
+
+        if self.status.is_empty() {
+            self.target.set_status(&fps);
+        } else {
+            self.target.set_status(&format!("{} | {}", fps, self.status));
+        }
+    }
+
+    /// Starts the render thread and handles the events until the target is
+    /// closed, every new frame of the renderer is presented
+    pub fn run(&mut self, renderer: Renderer) -> Result<(), TargetError> {
+        self.frame = (renderer.pipeline.width(), renderer.pipeline.height());
+
+        // Si se graba cada frame el renderer espera a que se presente
+        let lossless = self.target.records_frames();
+        if lossless {
+            self.pacer = Pacer::new(FramePacing::Unlimited);
+        }
 
-            if self.window.is_key_released(minifb::Key::T) { // toggle clock
-                let last = self.convolutions.len() - 1;
-                if let Some((_, active)) = self.convolutions.get_mut(last) {
-                    *active = !*active;
+        let (writer, mut reader) = triple_buffer(lossless);
+        let (commands, receiver) = mpsc::channel();
+        let (status, statuses) = mpsc::channel();
+
+        let render = thread::Builder::new()
+            .name("render".to_string())
+            .spawn(move || renderer.run(receiver, status, writer))
+            .expect("The render thread can't be created");
+
+        let mut result = Ok(());
+        self.pacer.start();
+        while self.target.is_open() && !render.is_finished() {
+            let mut pending = self.commands();
+            pending.extend(self.follow_resize());
+
+            let input = self.target.input();
+            if input != self.input {
+                self.input = input.clone();
+                pending.push(Command::Input(input));
+            }
+
+            for command in pending {
+                let _ = commands.send(command);
+            }
+
+            while let Ok(status) = statuses.try_recv() {
+                self.show_status(status);
+            }
+
+            match reader.latest() {
+                Some(frame) => {
+                    if let Err(error) = self.target.present(&frame.pixels, frame.width, frame.height) {
+                        result = Err(error);
+                        break;
+                    }
+                    if self.pacer.frame_rendered() {
+                        self.update_title();
+                    }
                 }
+                None => self.target.poll(),
+            }
+
+            if self.pacer.wait() {
+                self.update_title();
             }
+        }
+
+        let _ = commands.send(Command::Quit);
+        drop(reader);
+        render.join().expect("The render thread panicked");
+
+        // En stderr, stdout queda para los reportes de bench y --list-layers
+        eprintln!(
+            "{} frames in {:.2} s ({:.1} fps)",
+            self.pacer.frames(),
+            self.pacer.elapsed().as_secs_f64(),
+            self.pacer.average_fps(),
+        );
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
 
-            self.apply_convolutions();
+    /// Renderer with the layers `names` and a pool of one thread
+    fn renderer(names: &[&str]) -> Renderer {
+        let mut renderer = Renderer::new(8, 8, build_pool(Some(1)));
+        let registry = Registry::builtin();
 
-            self.render();
+        for name in names {
+            let convolution = registry.create(name, &BTreeMap::new(), 8, 8).expect("Known layer");
+            renderer.pipeline.layers.push(Layer::new(convolution, true));
         }
+
+        renderer
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn edit_params_wraps_the_selection() {
+        let mut renderer = renderer(&["single_color", "to_green", "fire"]);
+
+        let status = renderer.edit_params(Edit::PreviousParam);
+        assert_eq!((renderer.selected_layer, renderer.selected_param), (0, 2));
+        assert_eq!(status.as_deref(), Some("ToColor: blue = 0"));
+
+        renderer.edit_params(Edit::NextParam);
+        assert_eq!((renderer.selected_layer, renderer.selected_param), (0, 0));
+
+        let status = renderer.edit_params(Edit::NextLayer);
+        assert_eq!(renderer.selected_layer, 1);
+        assert_eq!(status.as_deref(), Some("ToGreen: no parameters"));
+        assert_eq!(renderer.edit_params(Edit::NextParam), None);
+
+        renderer.edit_params(Edit::NextLayer);
+        renderer.edit_params(Edit::NextParam);
+        assert_eq!((renderer.selected_layer, renderer.selected_param), (2, 0));
+
+        renderer.edit_params(Edit::NextLayer);
+        assert_eq!((renderer.selected_layer, renderer.selected_param), (0, 0));
+
+        assert_eq!(Renderer::new(8, 8, build_pool(Some(1))).edit_params(Edit::NextLayer), None);
+    }
+
+    #[test]
+    fn edit_params_steps_the_selected_value() {
+        let mut renderer = renderer(&["single_color"]);
+
+        renderer.edit_params(Edit::NextParam);
+        assert_eq!(renderer.edit_params(Edit::Step(1)).as_deref(), Some("ToColor: green = 12"));
+
+        renderer.edit_params(Edit::Step(-1));
+        assert_eq!(renderer.edit_params(Edit::Step(-1)).as_deref(), Some("ToColor: green = 0"));
+    }
+}
@@ -0,0 +1,702 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use chrono::{Local, TimeZone};
+use crate::convolutions::effect::Executor;
+use crate::convolutions::params::ParamValue;
+use crate::convolutions::registry::Registry;
+use crate::convolutions::{Color, FrameContext};
+
+/// Output format of the benchmark report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Csv,
+    Markdown,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(ReportFormat::Csv),
+            "md" | "markdown" => Ok(ReportFormat::Markdown),
+            other => Err(format!("unknown report format {:?} (expected csv or markdown)", other)),
+        }
+    }
+}
+
+/// Options of the `bench` and `sweep` modes
+///
+/// ```text
+/// widget bench|sweep [--frames <n>] [--warmup <n>] [--sizes 100x100,360x360]
+///                    [--threads 1,2,4 | --max-threads <n>]
+///                    [--layers doom_fire,doom_fire_par,doom_fire@rayon_tiles]
+///                    [--format csv|markdown] [--output <file>]
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchOptions {
+    /// Measured frames of each case
+    pub frames: usize,
+    /// Frames rendered before measuring
+    pub warmup: usize,
+    pub sizes: Vec<(usize, usize)>,
+    pub threads: Vec<usize>,
+    pub layers: Vec<String>,
+    pub format: ReportFormat,
+    /// File of the report, stdout when it's `None`
+    pub output: Option<PathBuf>,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        BenchOptions {
+            frames: 60,
+            warmup: 5,
+            // Resoluciones de la tabla del README
+            sizes: vec![(100, 100), (360, 360), (360, 720), (1080, 720)],
+            threads: vec![available_threads()],
+            layers: vec!["doom_fire".to_string(), "doom_fire_par".to_string()],
+            format: ReportFormat::Markdown,
+            output: None,
+        }
+    }
+}
+
+impl BenchOptions {
+    /// Defaults of the `sweep` mode: every parallel strategy of the Doom
+    /// fire at the largest size of the README, from 1 to all the cores
+    pub fn sweep() -> Self {
+        BenchOptions {
+            sizes: vec![(1080, 720)],
+            threads: (1..=available_threads()).collect(),
+            layers: vec![
+                "doom_fire_par".to_string(),
+                "doom_fire@rayon_rows".to_string(),
+                "doom_fire@rayon_tiles".to_string(),
+                "doom_fire@scoped_threads".to_string(),
+            ],
+            ..BenchOptions::default()
+        }
+    }
+
+    /// Parses the arguments after `bench`
+    pub fn parse(args: &[String]) -> Result<BenchOptions, String> {
+        Self::parse_with(BenchOptions::default(), args)
+    }
+
+    /// Parses the arguments of a mode with its own defaults, `--max-threads N`
+    /// is a shortcut for `--threads 1,2,...,N`
+    pub fn parse_with(defaults: BenchOptions, args: &[String]) -> Result<BenchOptions, String> {
+        let mut options = defaults;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().map(|value| value.as_str()).ok_or_else(|| format!("missing value for {}", arg));
+
+            match arg.as_str() {
+                "--frames" => options.frames = parse_number(arg, value()?)?,
+                "--warmup" => options.warmup = parse_number(arg, value()?)?,
+                "--sizes" => options.sizes = value()?.split(',').map(parse_size).collect::<Result<_, _>>()?,
+                "--threads" => options.threads = value()?.split(',').map(|n| parse_number(arg, n)).collect::<Result<_, _>>()?,
+                "--max-threads" => options.threads = (1..=parse_number(arg, value()?)?).collect(),
+                "--layers" => options.layers = value()?.split(',').map(str::to_string).collect(),
+                "--format" => options.format = value()?.parse()?,
+                "--output" => options.output = Some(PathBuf::from(value()?)),
+                other => return Err(format!("unknown bench option {:?}", other)),
+            }
+        }
+
+        if options.frames == 0 {
+            return Err("--frames must be greater than 0".to_string());
+        }
+        if options.threads.is_empty() || options.threads.contains(&0) {
+            return Err("--threads must be greater than 0".to_string());
+        }
+
+        Ok(options)
+    }
+}
+
+pub fn available_threads() -> usize {
+    std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+fn parse_number(flag: &str, raw: &str) -> Result<usize, String> {
+    raw.trim().parse().map_err(|_| format!("invalid value {:?} for {}", raw, flag))
+}
+
+/// Parses a size written as `WIDTHxHEIGHT`
+pub fn parse_size(raw: &str) -> Result<(usize, usize), String> {
+    let invalid = || format!("invalid size {:?} (expected WIDTHxHEIGHT)", raw);
+    let (width, height) = raw.trim().split_once('x').ok_or_else(invalid)?;
+    let width: usize = width.parse().map_err(|_| invalid())?;
+    let height: usize = height.parse().map_err(|_| invalid())?;
+
+    if width == 0 || height == 0 {
+        return Err(invalid());
+    }
+
+    Ok((width, height))
+}
+
+/// Statistics of the frame times of a layer
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub layer: String,
+    pub width: usize,
+    pub height: usize,
+    pub threads: usize,
+    pub mean: f64,
+    pub p50: f64,
+    pub p95: f64,
+    /// Time of the sequential version divided by this time
+    pub speedup: Option<f64>,
+    /// Speedup divided by the number of threads
+    pub efficiency: Option<f64>,
+}
+
+impl Sample {
+    /// Builds the statistics from the frame times (in seconds)
+    pub fn new(layer: &str, (width, height): (usize, usize), threads: usize, times: &mut [f64]) -> Self {
+        times.sort_by(f64::total_cmp);
+
+        Sample {
+            layer: layer.to_string(),
+            width,
+            height,
+            threads,
+            mean: times.iter().sum::<f64>() / times.len() as f64,
+            p50: percentile(times, 50.0),
+            p95: percentile(times, 95.0),
+            speedup: None,
+            efficiency: None,
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted values
+pub fn percentile(sorted: &[f64], percent: f64) -> f64 {
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Layer measured by the benchmark, written as `name` or `name@executor`
+/// for the layers with an `executor` parameter
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    pub label: String,
+    pub name: String,
+    pub params: BTreeMap<String, ParamValue>,
+}
+
+impl Case {
+    pub fn parse(raw: &str) -> Case {
+        let mut params = BTreeMap::new();
+
+        let name = match raw.split_once('@') {
+            Some((name, executor)) => {
+                params.insert("executor".to_string(), ParamValue::Text(executor.to_string()));
+                name
+            }
+            None => raw,
+        };
+
+        Case {
+            label: raw.to_string(),
+            name: name.to_string(),
+            params,
+        }
+    }
+
+    fn executor(&self) -> Option<Executor> {
+        self.params.get("executor")?.as_str()?.parse().ok()
+    }
+}
+
+/// Renders `frames` frames of the layer and returns the time of each one
+pub fn measure(
+    registry: &Registry,
+    case: &Case,
+    (width, height): (usize, usize),
+    frames: usize,
+    warmup: usize,
+) -> Result<Vec<f64>, String> {
+    let mut convolution = registry.create(&case.name, &case.params, width, height)?;
+    let mut pixels = vec![Color::TRANSPARENT; width * height];
+
+    // Hora fija para que todas las corridas dibujen lo mismo
+    let start = Local.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).single().expect("Invalid start time");
+    let mut ctx = FrameContext::new(start);
+    let mut times = Vec::with_capacity(frames);
+
+    for frame in 0..warmup + frames {
+        let dt = Duration::from_millis(16);
+        ctx.frame = frame as u64;
+        ctx.dt = dt;
+        ctx.elapsed = dt * frame as u32;
+        ctx.animation = ctx.elapsed;
+        ctx.now = start + chrono::Duration::from_std(ctx.elapsed).expect("Invalid elapsed time");
+
+        pixels.fill(Color::TRANSPARENT);
+        let timer = Instant::now();
+        convolution.transform(&mut pixels, width, height, &ctx);
+        let elapsed = timer.elapsed().as_secs_f64();
+
+        if frame >= warmup {
+            times.push(elapsed);
+        }
+    }
+
+    Ok(times)
+}
+
+/// Runs every case of the benchmark, the sequential layers run only
+/// once per size since the number of threads doesn't change them
+pub fn run(options: &BenchOptions) -> Result<Vec<Sample>, String> {
+    let registry = Registry::builtin();
+    let mut samples: Vec<Sample> = Vec::new();
+
+    for &size in options.sizes.iter() {
+        for case in options.layers.iter().map(|layer| Case::parse(layer)) {
+            let info = registry.get(&case.name)
+                .ok_or_else(|| format!("unknown layer type {:?} (expected one of {:?})", case.name, registry.names()))?;
+
+            let parallel = case.executor().map_or(info.parallel, |executor| executor.is_parallel());
+            let threads = if parallel { options.threads.clone() } else { vec![1] };
+
+            for threads in threads {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .map_err(|error| error.to_string())?;
+
+                eprintln!("bench {} {}x{} threads={}", case.label, size.0, size.1, threads);
+                let mut times = pool.install(|| measure(&registry, &case, size, options.frames, options.warmup))?;
+                let mut sample = Sample::new(&case.label, size, threads, &mut times);
+
+                // El baseline de `capa@executor` es la capa con su executor por defecto
+                let baseline = info.baseline.unwrap_or(info.name);
+                if let Some(sequential) = samples.iter().find(|s| s.layer == baseline && (s.width, s.height) == size) {
+                    let speedup = sequential.mean / sample.mean;
+                    sample.speedup = Some(speedup);
+                    sample.efficiency = Some(speedup / threads as f64);
+                } else if !parallel {
+                    sample.speedup = Some(1.0);
+                    sample.efficiency = Some(1.0);
+                }
+
+                samples.push(sample);
+            }
+        }
+    }
+
+    Ok(samples)
+}
+
+/// Karp-Flatt metric: serial fraction measured with `threads` threads
+pub fn karp_flatt(speedup: f64, threads: usize) -> Option<f64> {
+    if threads < 2 {
+        return None;
+    }
+
+    let p = threads as f64;
+    Some((1.0 / speedup - 1.0 / p) / (1.0 - 1.0 / p))
+}
+
+/// Serial fraction `f` of Amdahl's law fitted by least squares to the
+/// speedups, using `1/S - 1/p = f * (1 - 1/p)`
+pub fn amdahl_fit(samples: &[&Sample]) -> Option<f64> {
+    let (mut numerator, mut denominator) = (0.0, 0.0);
+
+    for sample in samples.iter().filter(|sample| sample.threads > 1) {
+        let speedup = sample.speedup?;
+        let p = sample.threads as f64;
+        let a = 1.0 - 1.0 / p;
+        numerator += a * (1.0 / speedup - 1.0 / p);
+        denominator += a * a;
+    }
+
+    (denominator > 0.0).then(|| (numerator / denominator).clamp(0.0, 1.0))
+}
+
+/// Runs every parallel layer with each number of threads, the speedup and
+/// efficiency are relative to the same layer with one thread (strong scaling)
+pub fn sweep(options: &BenchOptions) -> Result<Vec<Sample>, String> {
+    let registry = Registry::builtin();
+    let mut samples: Vec<Sample> = Vec::new();
+    let mut threads = options.threads.clone();
+    threads.sort_unstable();
+    threads.dedup();
+
+    if threads[0] != 1 {
+        threads.insert(0, 1); // Referencia del speedup
+    }
+
+    for &size in options.sizes.iter() {
+        for case in options.layers.iter().map(|layer| Case::parse(layer)) {
+            let info = registry.get(&case.name)
+                .ok_or_else(|| format!("unknown layer type {:?} (expected one of {:?})", case.name, registry.names()))?;
+
+            if !case.executor().map_or(info.parallel, |executor| executor.is_parallel()) {
+                eprintln!("sweep: {} is sequential, skipped", case.label);
+                continue;
+            }
+
+            let mut reference = None;
+            for &threads in threads.iter() {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .map_err(|error| error.to_string())?;
+
+                eprintln!("sweep {} {}x{} threads={}", case.label, size.0, size.1, threads);
+                let mut times = pool.install(|| measure(&registry, &case, size, options.frames, options.warmup))?;
+                let mut sample = Sample::new(&case.label, size, threads, &mut times);
+
+                let single = *reference.get_or_insert(sample.mean);
+                let speedup = single / sample.mean;
+                sample.speedup = Some(speedup);
+                sample.efficiency = Some(speedup / threads as f64);
+                samples.push(sample);
+            }
+        }
+    }
+
+    Ok(samples)
+}
+
+/// Groups the samples of the same layer and size, keeping the order
+fn groups(samples: &[Sample]) -> Vec<Vec<&Sample>> {
+    let mut groups: Vec<Vec<&Sample>> = Vec::new();
+
+    for sample in samples {
+        match groups.iter_mut().find(|group| {
+            group[0].layer == sample.layer && (group[0].width, group[0].height) == (sample.width, sample.height)
+        }) {
+            Some(group) => group.push(sample),
+            None => groups.push(vec![sample]),
+        }
+    }
+
+    groups
+}
+
+pub fn sweep_to_csv(samples: &[Sample]) -> String {
+    let mut text = String::from("layer,width,height,threads,mean_s,speedup,efficiency,karp_flatt,amdahl_serial_fraction\n");
+
+    for group in groups(samples) {
+        let fit = amdahl_fit(&group);
+
+        for s in group {
+            let speedup = s.speedup.unwrap_or_default();
+            let _ = writeln!(
+                text,
+                "{},{},{},{},{:.6},{:.4},{},{},{}",
+                s.layer, s.width, s.height, s.threads, s.mean, speedup,
+                optional(s.efficiency), optional(karp_flatt(speedup, s.threads)), optional(fit),
+            );
+        }
+    }
+
+    text
+}
+
+pub fn sweep_to_markdown(samples: &[Sample]) -> String {
+    let mut text = String::new();
+
+    for group in groups(samples) {
+        let fit = amdahl_fit(&group);
+        let first = group[0];
+
+        let _ = writeln!(text, "### {} ({}x{})\n", first.layer, first.width, first.height);
+        text.push_str(
+            "| Hilos | Media (s) | SpeedUp | Eficiencia | Fracción serial (Karp-Flatt) |\n\
+             |-------|-----------|---------|------------|------------------------------|\n",
+        );
+
+        for s in group {
+            let speedup = s.speedup.unwrap_or_default();
+            let _ = writeln!(
+                text,
+                "| {} | {:.6} | {:.4} | {} | {} |",
+                s.threads, s.mean, speedup, optional(s.efficiency), optional(karp_flatt(speedup, s.threads)),
+            );
+        }
+
+        match fit {
+            Some(fit) if fit > 0.0 => {
+                let _ = writeln!(text, "\nFracción serial (ajuste de Amdahl): {:.4}, speedup máximo estimado: {:.2}\n", fit, 1.0 / fit);
+            }
+            Some(fit) => {
+                let _ = writeln!(text, "\nFracción serial (ajuste de Amdahl): {:.4}\n", fit);
+            }
+            None => text.push('\n'),
+        }
+    }
+
+    text
+}
+
+fn optional(value: Option<f64>) -> String {
+    value.map(|value| format!("{:.4}", value)).unwrap_or_default()
+}
+
+pub fn to_csv(samples: &[Sample]) -> String {
+    let mut text = String::from("layer,width,height,threads,mean_s,p50_s,p95_s,speedup,efficiency\n");
+
+    for s in samples {
+        let _ = writeln!(
+            text,
+            "{},{},{},{},{:.6},{:.6},{:.6},{},{}",
+            s.layer, s.width, s.height, s.threads, s.mean, s.p50, s.p95,
+            optional(s.speedup), optional(s.efficiency),
+        );
+    }
+
+    text
+}
+
+pub fn to_markdown(samples: &[Sample]) -> String {
+    let mut text = String::from(
+        "| Capa | Píxeles | Hilos | Media (s) | p50 (s) | p95 (s) | SpeedUp | Eficiencia |\n\
+         |------|---------|-------|-----------|---------|---------|---------|------------|\n",
+    );
+
+    for s in samples {
+        let _ = writeln!(
+            text,
+            "| {} | {}x{} | {} | {:.6} | {:.6} | {:.6} | {} | {} |",
+            s.layer, s.width, s.height, s.threads, s.mean, s.p50, s.p95,
+            optional(s.speedup), optional(s.efficiency),
+        );
+    }
+
+    text
+}
+
+/// Runs the benchmark and writes the report
+pub fn report(options: &BenchOptions) -> Result<(), String> {
+    let samples = run(options)?;
+
+    let text = match options.format {
+        ReportFormat::Csv => to_csv(&samples),
+        ReportFormat::Markdown => to_markdown(&samples),
+    };
+
+    write_report(options, text)
+}
+
+/// Runs the scaling sweep and writes the report
+pub fn sweep_report(options: &BenchOptions) -> Result<(), String> {
+    let samples = sweep(options)?;
+
+    let text = match options.format {
+        ReportFormat::Csv => sweep_to_csv(&samples),
+        ReportFormat::Markdown => sweep_to_markdown(&samples),
+    };
+
+    write_report(options, text)
+}
+
+fn write_report(options: &BenchOptions, text: String) -> Result<(), String> {
+    match &options.output {
+        Some(path) => fs::write(path, text).map_err(|error| format!("can't write {:?}: {}", path, error)),
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(raw: &str) -> Vec<String> {
+        raw.split_whitespace().map(str::to_string).collect()
+    }
+
+    fn sample(layer: &str, threads: usize, mean: f64, speedup: Option<f64>) -> Sample {
+        Sample {
+            layer: layer.to_string(),
+            width: 100,
+            height: 50,
+            threads,
+            mean,
+            p50: mean,
+            p95: mean,
+            speedup,
+            efficiency: speedup.map(|speedup| speedup / threads as f64),
+        }
+    }
+
+    /// Speedup ideal de la ley de Amdahl con fracción serial `f`
+    fn amdahl(f: f64, threads: usize) -> f64 {
+        1.0 / (f + (1.0 - f) / threads as f64)
+    }
+
+    #[test]
+    fn percentiles() {
+        let values: Vec<f64> = (1..=20).map(f64::from).collect();
+
+        let cases = [(50.0, 10.0), (95.0, 19.0), (100.0, 20.0), (0.0, 1.0), (1.0, 1.0), (96.0, 20.0)];
+        for (percent, expected) in cases {
+            assert_eq!(percentile(&values, percent), expected, "p{}", percent);
+        }
+
+        assert_eq!(percentile(&[7.0], 95.0), 7.0);
+
+        let mut times = vec![0.4, 0.1, 0.3, 0.2];
+        let stats = Sample::new("layer", (10, 10), 1, &mut times);
+        assert_eq!((stats.p50, stats.p95), (0.2, 0.4));
+        assert!((stats.mean - 0.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn karp_flatt_recovers_the_serial_fraction() {
+        assert_eq!(karp_flatt(1.0, 1), None);
+        assert!((karp_flatt(2.0, 2).unwrap()).abs() < 1e-12); // Speedup perfecto
+        assert!((karp_flatt(1.0, 4).unwrap() - 1.0).abs() < 1e-12); // Sin speedup
+
+        for f in [0.0, 0.1, 0.25, 0.5] {
+            for threads in [2, 4, 8, 16] {
+                let measured = karp_flatt(amdahl(f, threads), threads).unwrap();
+                assert!((measured - f).abs() < 1e-9, "f = {}, {} threads: {}", f, threads, measured);
+            }
+        }
+    }
+
+    #[test]
+    fn amdahl_fit_recovers_the_serial_fraction() {
+        let ideal: Vec<Sample> = [1, 2, 4, 8].iter().map(|&p| sample("fire", p, 1.0, Some(amdahl(0.1, p)))).collect();
+        let fit = amdahl_fit(&ideal.iter().collect::<Vec<_>>()).unwrap();
+        assert!((fit - 0.1).abs() < 1e-9, "{}", fit);
+
+        // Un solo hilo no da información, y un speedup que falta invalida el ajuste
+        assert_eq!(amdahl_fit(&[&ideal[0]]), None);
+        let missing = sample("fire", 4, 1.0, None);
+        assert_eq!(amdahl_fit(&[&ideal[1], &missing]), None);
+
+        // Speedups imposibles se acotan a 0..=1
+        let superlinear = sample("fire", 2, 1.0, Some(4.0));
+        assert_eq!(amdahl_fit(&[&superlinear]), Some(0.0));
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("1080x720"), Ok((1080, 720)));
+        assert_eq!(parse_size(" 100x100 "), Ok((100, 100)));
+
+        for raw in ["", "100", "100x", "x100", "0x100", "100x0", "100*100", "-1x5", "ax2"] {
+            assert!(parse_size(raw).is_err(), "{:?} should be rejected", raw);
+        }
+    }
+
+    #[test]
+    fn parses_options() {
+        let options = BenchOptions::parse(&args(
+            "--frames 10 --warmup 0 --sizes 100x100,360x720 --threads 1,4 --layers a,b@rayon_rows --format csv --output out.csv",
+        )).unwrap();
+
+        assert_eq!(options, BenchOptions {
+            frames: 10,
+            warmup: 0,
+            sizes: vec![(100, 100), (360, 720)],
+            threads: vec![1, 4],
+            layers: vec!["a".to_string(), "b@rayon_rows".to_string()],
+            format: ReportFormat::Csv,
+            output: Some(PathBuf::from("out.csv")),
+        });
+
+        let defaults = BenchOptions::parse(&[]).unwrap();
+        assert_eq!(defaults, BenchOptions::default());
+
+        let sweep = BenchOptions::parse_with(BenchOptions::sweep(), &args("--max-threads 3")).unwrap();
+        assert_eq!(sweep.threads, vec![1, 2, 3]);
+        assert_eq!(sweep.sizes, vec![(1080, 720)]);
+
+        for raw in ["--frames 0", "--threads 0", "--threads 2,0", "--frames", "--sizes 10", "--format xml", "--fast"] {
+            assert!(BenchOptions::parse(&args(raw)).is_err(), "{:?} should be rejected", raw);
+        }
+    }
+
+    #[test]
+    fn cases_with_executor() {
+        let case = Case::parse("wave_fire@rayon_tiles");
+        assert_eq!((case.label.as_str(), case.name.as_str()), ("wave_fire@rayon_tiles", "wave_fire"));
+        assert_eq!(case.executor(), Some(Executor::RayonTiles));
+
+        let plain = Case::parse("time");
+        assert!(plain.params.is_empty());
+        assert_eq!(plain.executor(), None);
+    }
+
+    #[test]
+    fn writes_the_reports() {
+        let samples = vec![
+            sample("fire", 1, 0.2, Some(1.0)),
+            sample("fire_par", 4, 0.05, Some(4.0)),
+            sample("time", 1, 0.001, None),
+        ];
+
+        assert_eq!(to_csv(&samples), "\
+layer,width,height,threads,mean_s,p50_s,p95_s,speedup,efficiency
+fire,100,50,1,0.200000,0.200000,0.200000,1.0000,1.0000
+fire_par,100,50,4,0.050000,0.050000,0.050000,4.0000,1.0000
+time,100,50,1,0.001000,0.001000,0.001000,,
+");
+
+        let markdown = to_markdown(&samples);
+        let lines: Vec<&str> = markdown.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[3], "| fire_par | 100x50 | 4 | 0.050000 | 0.050000 | 0.050000 | 4.0000 | 1.0000 |");
+        assert_eq!(lines[4], "| time | 100x50 | 1 | 0.001000 | 0.001000 | 0.001000 |  |  |");
+    }
+
+    #[test]
+    fn writes_the_sweep_reports() {
+        let samples: Vec<Sample> = [1, 2, 4].iter().map(|&p| sample("fire", p, 1.0 / amdahl(0.1, p), Some(amdahl(0.1, p)))).collect();
+
+        let csv = sweep_to_csv(&samples);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[1], "fire,100,50,1,1.000000,1.0000,1.0000,,0.1000");
+        assert_eq!(lines[3], "fire,100,50,4,0.325000,3.0769,0.7692,0.1000,0.1000");
+
+        let markdown = sweep_to_markdown(&samples);
+        assert!(markdown.starts_with("### fire (100x50)\n"));
+        assert!(markdown.contains("| 4 | 0.325000 | 3.0769 | 0.7692 | 0.1000 |"));
+        assert!(markdown.contains("Fracción serial (ajuste de Amdahl): 0.1000, speedup máximo estimado: 10.00"));
+    }
+
+    #[test]
+    fn speedup_is_relative_to_the_sequential_layer() {
+        let options = BenchOptions {
+            frames: 2,
+            warmup: 0,
+            sizes: vec![(16, 12)],
+            threads: vec![2],
+            layers: vec!["wave_fire".to_string(), "wave_fire_par".to_string(), "wave_fire@rayon_rows".to_string()],
+            ..BenchOptions::default()
+        };
+
+        let samples = run(&options).unwrap();
+        assert_eq!(samples.len(), 3);
+
+        let (sequential, parallel, rows) = (&samples[0], &samples[1], &samples[2]);
+        assert_eq!((sequential.threads, parallel.threads, rows.threads), (1, 2, 2));
+        assert_eq!(sequential.speedup, Some(1.0));
+
+        for sample in [parallel, rows] {
+            let speedup = sample.speedup.unwrap();
+            assert!((speedup - sequential.mean / sample.mean).abs() < 1e-9);
+            assert!((sample.efficiency.unwrap() - speedup / 2.0).abs() < 1e-9);
+        }
+
+        assert!(run(&BenchOptions { layers: vec!["sparkles".to_string()], ..options }).is_err());
+    }
+}
@@ -0,0 +1,223 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::bench::BenchOptions;
+use crate::clock::{self, ClockOptions};
+use crate::convolutions::resample::{self, Filter};
+use crate::pacing::{FramePacing, DEFAULT_FPS};
+use crate::target::headless::ImageFormat;
+
+pub const WIDTH: usize = 480;
+pub const HEIGHT: usize = 360;
+
+/// Where the frames are going to be presented
+#[derive(Debug, Clone, PartialEq)]
+pub enum TargetKind {
+    /// MiniFB window
+    Window,
+    /// Frames written to `output` without a display
+    Headless {
+        frames: usize,
+        output: PathBuf,
+        format: ImageFormat,
+    },
+}
+
+/// What the program is going to do
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Show the widget
+    Run,
+    /// Print the available layers
+    ListLayers,
+    /// Measure the layers and print a report
+    Bench(BenchOptions),
+    /// Measure the parallel layers from 1 to N threads
+    Sweep(BenchOptions),
+}
+
+/// Options of the command line
+///
+/// ```text
+/// widget [width] [height] [--headless <frames>] [--output <dir>] [--format ppm|png]
+///        [--time <HH:MM:SS>] [--offset <seconds>] [--speed <factor>] [--scene <file>]
+///        [--threads <n>] [--tiled] [--fps <n|unlimited>] [--on-change]
+///        [--adaptive] [--budget <ms>] [--scale <factor>] [--filter <name>]
+/// widget --list-layers
+/// widget bench [options]
+/// widget sweep [options]
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub width: usize,
+    pub height: usize,
+    pub target: TargetKind,
+    pub clock: ClockOptions,
+    /// TOML or JSON file with the layer stack
+    pub scene: Option<PathBuf>,
+    /// Threads of the rayon pool, it has priority over the scene
+    pub threads: Option<usize>,
+    /// Render the frame by tiles, each tile runs the whole layer stack
+    pub tiled: bool,
+    /// Frame rate of the window, the headless mode is always unlimited
+    pub pacing: FramePacing,
+    /// Time budget of each frame for the adaptive quality, `None` if the
+    /// quality of the layers isn't changed
+    pub budget: Option<Duration>,
+    /// Internal resolution of the whole stack, it has priority over the scene
+    pub scale: Option<f32>,
+    pub filter: Option<Filter>,
+    pub command: Command,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let command = match args.get(1).map(|arg| arg.as_str()) {
+            Some("bench") => Some(Command::Bench(BenchOptions::parse(&args[2..])?)),
+            Some("sweep") => Some(Command::Sweep(BenchOptions::parse_with(BenchOptions::sweep(), &args[2..])?)),
+            _ => None,
+        };
+
+        if let Some(command) = command {
+            let mut options = Options::parse(&args[..1])?;
+            options.command = command;
+            return Ok(options);
+        }
+
+        let mut positional = Vec::new();
+        let mut frames = None;
+        let mut output = PathBuf::from("frames");
+        let mut format = ImageFormat::Ppm;
+        let mut clock = ClockOptions::default();
+        let mut scene = None;
+        let mut threads = None;
+        let mut tiled = false;
+        let mut fps = None;
+        let mut on_change = false;
+        let mut adaptive = false;
+        let mut budget = None;
+        let mut scale = None;
+        let mut filter = None;
+        let mut command = Command::Run;
+
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => frames = Some(parse_value(arg, args.next())?),
+                "--output" => output = PathBuf::from(value(arg, args.next())?),
+                "--format" => format = value(arg, args.next())?.parse()?,
+                "--time" => clock.start = Some(clock::parse_time(value(arg, args.next())?)?),
+                "--offset" => clock.offset = parse_value(arg, args.next())?,
+                "--speed" => clock.speed = Some(parse_value(arg, args.next())?),
+                "--scene" => scene = Some(PathBuf::from(value(arg, args.next())?)),
+                "--threads" => threads = Some(parse_value(arg, args.next())?),
+                "--tiled" => tiled = true,
+                "--fps" => fps = Some(value(arg, args.next())?.parse()?),
+                "--on-change" => on_change = true,
+                "--adaptive" => adaptive = true,
+                "--scale" => scale = Some(resample::check_scale(parse_value(arg, args.next())?)?),
+                "--filter" => filter = Some(value(arg, args.next())?.parse()?),
+                "--budget" => budget = Some(parse_value::<f64>(arg, args.next())?),
+                "--list-layers" => command = Command::ListLayers,
+                flag if flag.starts_with("--") => return Err(format!("unknown option {:?}", flag)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let width = match positional.first() {
+            Some(width) => width.parse().map_err(|_| format!("invalid width {:?}", width))?,
+            None => WIDTH,
+        };
+
+        let height = match positional.get(1) {
+            Some(height) => height.parse().map_err(|_| format!("invalid height {:?}", height))?,
+            None => HEIGHT,
+        };
+
+        if width == 0 || height == 0 {
+            return Err("the width and height must be greater than 0".to_string());
+        }
+
+        let target = match frames {
+            Some(frames) => TargetKind::Headless { frames, output, format },
+            None => TargetKind::Window,
+        };
+
+        if clock.offset.abs() > clock::MAX_OFFSET {
+            return Err(format!("the clock offset must be between -{0} and {0} seconds", clock::MAX_OFFSET));
+        }
+
+        if clock.speed.is_some_and(|speed: f64| !speed.is_finite() || speed.abs() > clock::MAX_SPEED) {
+            return Err(format!("the clock speed must be a number between -{0} and {0}", clock::MAX_SPEED));
+        }
+
+        if threads == Some(0) {
+            return Err("the number of threads must be greater than 0".to_string());
+        }
+
+        let pacing = match (fps.unwrap_or_default(), on_change) {
+            (FramePacing::Fixed(fps), true) => FramePacing::OnChange(fps),
+            (FramePacing::Unlimited, true) => {
+                return Err("--on-change needs a frame rate to check the changes".to_string());
+            }
+            (pacing, _) => pacing,
+        };
+
+        // Sin ventana el reloj avanza un periodo por frame, así los frames
+        // no dependen de cuánto tarda cada uno
+        if matches!(target, TargetKind::Headless { .. }) {
+            clock.step = pacing.period().or_else(|| FramePacing::default().period());
+        }
+
+        // Sin --budget el presupuesto es el periodo de los frames
+        let budget = match budget {
+            Some(ms) if ms.is_finite() && ms > 0.0 => Some(Duration::from_secs_f64(ms / 1000.0)),
+            Some(_) => return Err("the frame budget must be a positive number of milliseconds".to_string()),
+            None if adaptive => Some(pacing.period().unwrap_or(Duration::from_secs_f64(1.0 / DEFAULT_FPS))),
+            None => None,
+        };
+
+        Ok(Options { width, height, target, clock, scene, threads, tiled, pacing, budget, scale, filter, command })
+    }
+}
+
+fn value<'a>(flag: &str, value: Option<&'a String>) -> Result<&'a str, String> {
+    value
+        .map(|value| value.as_str())
+        .ok_or_else(|| format!("missing value for {}", flag))
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, raw: Option<&String>) -> Result<T, String> {
+    let raw = value(flag, raw)?;
+    raw.parse().map_err(|_| format!("invalid value {:?} for {}", raw, flag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, String> {
+        let args: Vec<String> = std::iter::once("widget").chain(args.split_whitespace()).map(String::from).collect();
+        Options::parse(&args)
+    }
+
+    #[test]
+    fn parses_the_clock_options() {
+        let options = parse("--offset -3600 --speed 60").expect("Valid options");
+        assert_eq!((options.clock.offset, options.clock.speed), (-3600, Some(60.0)));
+
+        let limits = format!("--offset {} --speed -{}", clock::MAX_OFFSET, clock::MAX_SPEED);
+        assert!(parse(&limits).is_ok());
+    }
+
+    #[test]
+    fn rejects_clocks_out_of_range() {
+        for args in ["--offset 99999999999999", "--offset -3153600001", "--offset 1.5"] {
+            assert!(parse(args).is_err(), "{:?} should be rejected", args);
+        }
+
+        for args in ["--speed 1e18", "--speed -10001", "--speed NaN", "--speed inf"] {
+            let error = parse(args).expect_err(args);
+            assert!(error.contains("speed"), "{:?}: {}", args, error);
+        }
+    }
+}
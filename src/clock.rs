@@ -0,0 +1,342 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+
+/// Largest `--offset` in seconds, forwards or backwards (100 years)
+pub const MAX_OFFSET: i64 = 100 * 365 * 24 * 3600;
+
+/// Largest `--speed`, forwards or backwards, a day passes in less than
+/// 9 seconds
+pub const MAX_SPEED: f64 = 10_000.0;
+
+/// Source of the wall-clock time shown by the layers
+///
+/// Every layer that needs the hour asks the shared clock instead of
+/// calling `chrono::Local::now()`, so a frame can be rendered for any
+/// instant and replayed in a deterministic way.
+pub trait Clock: Send + Sync {
+    /// Function to get the current time of the clock
+    fn now(&self) -> DateTime<Local>;
+
+    /// Function called after each rendered frame, for the clocks that
+    /// advance with the frames instead of the real time
+    fn tick(&self) {}
+
+    /// Function to get the time of the animations since the first frame,
+    /// `None` when they follow the real time
+    fn animation(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Real time of the system
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+}
+
+/// Clock stopped at a specific instant
+pub struct FixedClock {
+    pub time: DateTime<Local>,
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Local> {
+        self.time
+    }
+}
+
+/// Clock shifted a constant amount from another clock
+pub struct OffsetClock {
+    pub inner: Arc<dyn Clock>,
+    pub offset: chrono::Duration,
+}
+
+impl Clock for OffsetClock {
+    fn now(&self) -> DateTime<Local> {
+        shift(self.inner.now(), self.offset)
+    }
+
+    fn tick(&self) {
+        self.inner.tick();
+    }
+
+    fn animation(&self) -> Option<Duration> {
+        self.inner.animation()
+    }
+}
+
+/// Clock that starts at `start` and advances `speed` times faster
+/// than the real time, e.g. a speed of 1440 replays a day in a minute
+pub struct AcceleratedClock {
+    start: DateTime<Local>,
+    origin: Instant,
+    speed: f64,
+}
+
+impl AcceleratedClock {
+    pub fn new(start: DateTime<Local>, speed: f64) -> Self {
+        AcceleratedClock {
+            start,
+            origin: Instant::now(),
+            speed,
+        }
+    }
+
+    /// Time of the clock when `real` time has passed since it was created
+    pub fn at(&self, real: Duration) -> DateTime<Local> {
+        let elapsed = real.as_secs_f64() * self.speed;
+        shift(self.start, chrono::Duration::microseconds((elapsed * 1_000_000.0) as i64))
+    }
+}
+
+impl Clock for AcceleratedClock {
+    fn now(&self) -> DateTime<Local> {
+        self.at(self.origin.elapsed())
+    }
+}
+
+/// `time + delta`, limited to the dates that chrono can represent
+fn shift(time: DateTime<Local>, delta: chrono::Duration) -> DateTime<Local> {
+    time.checked_add_signed(delta).unwrap_or_else(|| {
+        let limit = if delta < chrono::Duration::zero() { NaiveDateTime::MIN } else { NaiveDateTime::MAX };
+        Local.from_utc_datetime(&limit)
+    })
+}
+
+/// Clock that advances `step` times `speed` on each rendered frame, no
+/// matter how long the frame took. Used without a window so the same
+/// options always render the same frames, the animations also advance
+/// `step` on each frame
+pub struct SteppedClock {
+    start: DateTime<Local>,
+    step: Duration,
+    speed: f64,
+    frames: AtomicU64,
+}
+
+impl SteppedClock {
+    pub fn new(start: DateTime<Local>, step: Duration, speed: f64) -> Self {
+        SteppedClock {
+            start,
+            step,
+            speed,
+            frames: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Clock for SteppedClock {
+    fn now(&self) -> DateTime<Local> {
+        let frames = self.frames.load(Ordering::Relaxed) as f64;
+        let elapsed = self.step.as_secs_f64() * frames * self.speed;
+        shift(self.start, chrono::Duration::microseconds((elapsed * 1_000_000.0).round() as i64))
+    }
+
+    fn tick(&self) {
+        self.frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn animation(&self) -> Option<Duration> {
+        Some(self.step.mul_f64(self.frames.load(Ordering::Relaxed) as f64))
+    }
+}
+
+/// Options to build the clock of the app
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClockOptions {
+    /// Instant where the clock starts, `None` for the system time
+    pub start: Option<DateTime<Local>>,
+    /// Seconds added to the time
+    pub offset: i64,
+    /// Speed factor, `None` for real speed
+    pub speed: Option<f64>,
+    /// Time that passes on each frame, `None` to follow the real time.
+    /// A fixed time without speed stays fixed
+    pub step: Option<Duration>,
+}
+
+impl ClockOptions {
+    pub fn build(&self) -> Arc<dyn Clock> {
+        let clock: Arc<dyn Clock> = match (self.start, self.speed, self.step) {
+            // La hora queda fija pero las animaciones avanzan con los frames
+            (Some(time), None, Some(step)) => Arc::new(SteppedClock::new(time, step, 0.0)),
+            (Some(time), None, None) => Arc::new(FixedClock { time }),
+            (start, speed, Some(step)) => Arc::new(SteppedClock::new(start.unwrap_or_else(Local::now), step, speed.unwrap_or(1.0))),
+            (start, Some(speed), None) => Arc::new(AcceleratedClock::new(start.unwrap_or_else(Local::now), speed)),
+            (None, None, None) => Arc::new(SystemClock),
+        };
+
+        if self.offset == 0 {
+            return clock;
+        }
+
+        Arc::new(OffsetClock {
+            inner: clock,
+            offset: chrono::Duration::seconds(self.offset),
+        })
+    }
+}
+
+/// Parses `HH:MM[:SS]` (today) or `YYYY-MM-DDTHH:MM:SS` as a local time
+pub fn parse_time(raw: &str) -> Result<DateTime<Local>, String> {
+    let naive = if let Ok(datetime) = NaiveDateTime::parse_from_str(raw, "%Y-%m-%dT%H:%M:%S") {
+        datetime
+    } else {
+        let time = NaiveTime::parse_from_str(raw, "%H:%M:%S")
+            .or_else(|_| NaiveTime::parse_from_str(raw, "%H:%M"))
+            .map_err(|_| format!("invalid time {:?} (expected HH:MM:SS or YYYY-MM-DDTHH:MM:SS)", raw))?;
+        let today: NaiveDate = Local::now().date_naive();
+        today.and_time(time)
+    };
+
+    Local.from_local_datetime(&naive)
+        .earliest()
+        .ok_or_else(|| format!("the time {:?} doesn't exist in the local timezone", raw))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Timelike;
+    use super::*;
+
+    fn instant(raw: &str) -> DateTime<Local> {
+        parse_time(raw).expect("Valid time")
+    }
+
+    #[test]
+    fn parses_times() {
+        let time = instant("12:34:56");
+        assert_eq!((time.hour(), time.minute(), time.second()), (12, 34, 56));
+        assert_eq!(time.date_naive(), Local::now().date_naive());
+
+        let time = instant("07:05");
+        assert_eq!((time.hour(), time.minute(), time.second()), (7, 5, 0));
+
+        let time = instant("2024-01-01T23:59:30");
+        assert_eq!(time.naive_local().to_string(), "2024-01-01 23:59:30");
+
+        for raw in ["", "25:00", "12:60:00", "noon", "2024-13-01T00:00:00", "2024-01-01 12:00:00"] {
+            assert!(parse_time(raw).is_err(), "{:?} should be rejected", raw);
+        }
+    }
+
+    #[test]
+    fn fixed_clock_doesnt_move() {
+        let clock = FixedClock { time: instant("2024-01-01T12:00:00") };
+        let first = clock.now();
+
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(clock.now(), first);
+        assert_eq!(first, instant("2024-01-01T12:00:00"));
+    }
+
+    #[test]
+    fn offset_clock_shifts_the_inner_clock() {
+        let inner: Arc<dyn Clock> = Arc::new(FixedClock { time: instant("2024-01-01T23:59:30") });
+
+        let later = OffsetClock { inner: inner.clone(), offset: chrono::Duration::seconds(45) };
+        assert_eq!(later.now(), instant("2024-01-02T00:00:15"));
+
+        let earlier = OffsetClock { inner, offset: chrono::Duration::seconds(-3600) };
+        assert_eq!(earlier.now(), instant("2024-01-01T22:59:30"));
+    }
+
+    #[test]
+    fn accelerated_clock_scales_the_real_time() {
+        let start = instant("2024-01-01T00:00:00");
+
+        // Un día por minuto: 10 s reales son 4 horas
+        let clock = AcceleratedClock::new(start, 1440.0);
+        assert_eq!(clock.at(Duration::ZERO), start);
+        assert_eq!(clock.at(Duration::from_secs(10)), instant("2024-01-01T04:00:00"));
+        assert_eq!(clock.at(Duration::from_millis(2500)), instant("2024-01-01T01:00:00"));
+
+        let slow = AcceleratedClock::new(start, 0.5);
+        assert_eq!(slow.at(Duration::from_secs(60)), instant("2024-01-01T00:00:30"));
+
+        let backwards = AcceleratedClock::new(start, -60.0);
+        assert_eq!(backwards.at(Duration::from_secs(1)), instant("2023-12-31T23:59:00"));
+
+        // El reloj real avanza desde el inicio
+        let now = clock.now();
+        assert!(now >= start && now < instant("2024-01-01T01:00:00"));
+    }
+
+    #[test]
+    fn options_build_the_expected_clock() {
+        let start = instant("2024-01-01T12:00:00");
+
+        let fixed = ClockOptions { start: Some(start), ..ClockOptions::default() }.build();
+        assert_eq!(fixed.now(), start);
+
+        let shifted = ClockOptions { start: Some(start), offset: 90, ..ClockOptions::default() }.build();
+        assert_eq!(shifted.now(), instant("2024-01-01T12:01:30"));
+
+        let accelerated = ClockOptions { start: Some(start), speed: Some(1.0), ..ClockOptions::default() }.build();
+        let now = accelerated.now();
+        assert!(now >= start && now < instant("2024-01-01T12:01:00"));
+
+        // Con un paso por frame solo avanza con los frames, la hora fija
+        // sigue fija
+        let step = Some(Duration::from_millis(500));
+        let stepped = ClockOptions { start: Some(start), offset: 30, speed: Some(2.0), step }.build();
+        stepped.tick();
+        stepped.tick();
+        assert_eq!(stepped.now(), instant("2024-01-01T12:00:32"));
+
+        let fixed = ClockOptions { start: Some(start), step, ..ClockOptions::default() }.build();
+        fixed.tick();
+        assert_eq!(fixed.now(), start);
+
+        // Sin opciones es la hora del sistema
+        let system = ClockOptions::default().build().now();
+        assert!((system - Local::now()).num_seconds().abs() < 5);
+    }
+
+    #[test]
+    fn far_times_saturate() {
+        let start = instant("2024-01-01T00:00:00");
+
+        let fast = AcceleratedClock::new(start, MAX_SPEED);
+        assert!(fast.at(Duration::from_secs(u32::MAX as u64 * 1000)) > start);
+
+        let backwards = AcceleratedClock::new(start, -MAX_SPEED);
+        assert!(backwards.at(Duration::from_secs(u32::MAX as u64 * 1000)) < start);
+
+        let end = ClockOptions { start: Some(start), offset: MAX_OFFSET, speed: None, step: None }.build().now();
+        let last = OffsetClock { inner: Arc::new(FixedClock { time: end }), offset: chrono::Duration::MAX };
+        assert!(last.now() > end);
+    }
+
+    #[test]
+    fn stepped_clock_follows_the_frames() {
+        let start = instant("2024-01-01T23:59:59");
+        let clock = SteppedClock::new(start, Duration::from_secs_f64(1.0 / 60.0), 1.0);
+
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(clock.now(), start);
+
+        (0..60).for_each(|_| clock.tick());
+        assert_eq!(clock.now(), instant("2024-01-02T00:00:00"));
+
+        // Un segundo de frames a 1440x son 24 minutos
+        let fast = SteppedClock::new(start, Duration::from_millis(40), 1440.0);
+        (0..25).for_each(|_| fast.tick());
+        assert_eq!(fast.now(), instant("2024-01-02T00:23:59"));
+
+        // Las animaciones avanzan un paso por frame sin acelerarse
+        assert_eq!(fast.animation(), Some(Duration::from_secs(1)));
+        assert_eq!(SystemClock.animation(), None);
+
+        // Con una hora fija la hora no cambia, las animaciones sí
+        let shifted = ClockOptions { start: Some(start), offset: 60, speed: None, step: Some(Duration::from_millis(40)) }.build();
+        (0..25).for_each(|_| shifted.tick());
+        assert_eq!(shifted.now(), instant("2024-01-02T00:00:59"));
+        assert_eq!(shifted.animation(), Some(Duration::from_secs(1)));
+    }
+}
@@ -1,4 +1,5 @@
-use crate::convolutions::{Color, Convolution};
+use crate::convolutions::params::{self, Param, ParamSpec, ParamValue};
+use crate::convolutions::{Color, Convolution, Damage, FrameContext};
 
 pub struct ToGreenConvolution;
 
@@ -7,11 +8,23 @@ impl Convolution for ToGreenConvolution {
         "ToGreen"
     }
 
-    fn transform(&mut self, pixels: &mut [u32], _width: usize, _height: usize) {
-        for i in 0..pixels.len() {
-            pixels[i] = Color::rgb(0, 0xFF, 0);
+    fn transform(&mut self, pixels: &mut [u32], _width: usize, _height: usize, _ctx: &FrameContext) {
+        for pixel in pixels.iter_mut() {
+            *pixel = Color::rgb(0, 0xFF, 0);
         }
     }
+
+    fn damage(&self, _width: usize, _height: usize, _ctx: &FrameContext) -> Damage {
+        Damage::None
+    }
+
+    fn prepare_tiles(&mut self, _width: usize, _height: usize, _ctx: &FrameContext) -> bool {
+        true
+    }
+
+    fn transform_tile(&self, pixels: &mut [u32], _top: usize, _width: usize, _height: usize) {
+        pixels.fill(Color::rgb(0, 0xFF, 0));
+    }
 }
 
 
@@ -21,14 +34,58 @@ pub struct SingleColorConvolution {
     pub blue: u8,
 }
 
+impl SingleColorConvolution {
+    pub fn schema() -> Vec<ParamSpec> {
+        vec![
+            ParamSpec::int("red", 0, 0, 255, "Red channel"),
+            ParamSpec::int("green", 0, 0, 255, "Green channel"),
+            ParamSpec::int("blue", 0, 0, 255, "Blue channel"),
+        ]
+    }
+}
+
 impl Convolution for SingleColorConvolution {
     fn name(&self) -> &str {
         "ToColor"
     }
 
-    fn transform(&mut self, pixels: &mut [u32], _width: usize, _height: usize) {
-        for i in 0..pixels.len() {
-            pixels[i] = Color::rgb(self.red, self.green, self.blue)
+    fn transform(&mut self, pixels: &mut [u32], _width: usize, _height: usize, _ctx: &FrameContext) {
+        for pixel in pixels.iter_mut() {
+            *pixel = Color::rgb(self.red, self.green, self.blue)
         }
     }
-}
\ No newline at end of file
+
+    fn damage(&self, _width: usize, _height: usize, _ctx: &FrameContext) -> Damage {
+        Damage::None
+    }
+
+    fn prepare_tiles(&mut self, _width: usize, _height: usize, _ctx: &FrameContext) -> bool {
+        true
+    }
+
+    fn transform_tile(&self, pixels: &mut [u32], _top: usize, _width: usize, _height: usize) {
+        pixels.fill(Color::rgb(self.red, self.green, self.blue));
+    }
+
+    fn params(&self) -> Vec<Param> {
+        params::with_values(Self::schema(), [
+            ParamValue::Int(self.red as i64),
+            ParamValue::Int(self.green as i64),
+            ParamValue::Int(self.blue as i64),
+        ])
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        let value = params::validate(&Self::schema(), name, &value)?
+            .as_i64()
+            .unwrap_or_default() as u8;
+
+        match name {
+            "red" => self.red = value,
+            "green" => self.green = value,
+            _ => self.blue = value,
+        }
+
+        Ok(())
+    }
+}
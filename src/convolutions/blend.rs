@@ -0,0 +1,181 @@
+use std::str::FromStr;
+
+/// How the color of a layer is mixed with the layers below
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    /// The layer covers the frame
+    #[default]
+    Normal,
+    /// Sum of both colors, saturated to white
+    Add,
+    /// Product of both colors, always darker
+    Multiply,
+    /// Inverse of the product of the inverses, always lighter
+    Screen,
+    /// Multiply on the dark zones of the frame and screen on the light ones
+    Overlay,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 5] = [
+        BlendMode::Normal,
+        BlendMode::Add,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+        BlendMode::Overlay,
+    ];
+
+    pub fn name(&self) -> &str {
+        match self {
+            BlendMode::Normal => "normal",
+            BlendMode::Add => "add",
+            BlendMode::Multiply => "multiply",
+            BlendMode::Screen => "screen",
+            BlendMode::Overlay => "overlay",
+        }
+    }
+
+    /// Mixes one channel of the frame (`dst`) with the layer (`src`)
+    fn channel(&self, dst: u32, src: u32) -> u32 {
+        match self {
+            BlendMode::Normal => src,
+            BlendMode::Add => (dst + src).min(255),
+            BlendMode::Multiply => dst * src / 255,
+            BlendMode::Screen => 255 - (255 - dst) * (255 - src) / 255,
+            BlendMode::Overlay => if dst < 128 {
+                2 * dst * src / 255
+            } else {
+                255 - 2 * (255 - dst) * (255 - src) / 255
+            },
+        }
+    }
+
+    /// Composites a layer pixel in ARGB format over an opaque frame pixel
+    pub fn blend(&self, dst: u32, src: u32, opacity: u32) -> u32 {
+        let alpha = (src >> 24) * opacity / 255;
+
+        if alpha == 0 {
+            return dst;
+        }
+
+        if alpha == 255 && *self == BlendMode::Normal {
+            return src;
+        }
+
+        let mut out = 0xFF00_0000;
+        for shift in [16, 8, 0] {
+            let d = (dst >> shift) & 0xFF;
+            let s = (src >> shift) & 0xFF;
+            let mixed = self.channel(d, s);
+            // d + (mixed - d) * alpha, interpolación sin signo
+            let value = (d * (255 - alpha) + mixed * alpha + 127) / 255;
+            out |= value << shift;
+        }
+
+        out
+    }
+}
+
+impl FromStr for BlendMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BlendMode::ALL
+            .into_iter()
+            .find(|mode| mode.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown blend mode {:?} (expected normal, add, multiply, screen or overlay)", s))
+    }
+}
+
+/// Converts an opacity between 0 and 1 to the integer scale used by [`BlendMode::blend`]
+pub fn opacity_to_u32(opacity: f32) -> u32 {
+    (opacity.clamp(0.0, 1.0) * 255.0).round() as u32
+}
+
+/// Composites the RGBA `surface` of a layer over the opaque `frame`
+pub fn composite(frame: &mut [u32], surface: &[u32], opacity: f32, mode: BlendMode) {
+    let opacity = opacity_to_u32(opacity);
+
+    if opacity == 0 {
+        return;
+    }
+
+    frame.iter_mut()
+        .zip(surface.iter())
+        .for_each(|(dst, src)| *dst = mode.blend(*dst, *src, opacity));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frame (64, 128, 192) and layer (200, 100, 32), the expected pixels
+    /// are computed by hand with the formulas of each mode
+    const DST: u32 = 0xFF40_80C0;
+    const SRC: u32 = 0xFFC8_6420;
+
+    #[test]
+    fn opaque_modes() {
+        let expected = [
+            (BlendMode::Normal, 0xFFC8_6420),   // La capa tapa el frame
+            (BlendMode::Add, 0xFFFF_E4E0),      // 64 + 200 se satura a 255
+            (BlendMode::Multiply, 0xFF32_3218), // 64 * 200 / 255 = 50
+            (BlendMode::Screen, 0xFFD6_B2C8),   // 255 - 191 * 55 / 255 = 214
+            (BlendMode::Overlay, 0xFF64_6591),  // 2 * 64 * 200 / 255 = 100
+        ];
+
+        for (mode, pixel) in expected {
+            assert_eq!(mode.blend(DST, SRC, 255), pixel, "{}", mode.name());
+        }
+    }
+
+    #[test]
+    fn opacity_interpolates_with_the_frame() {
+        // Opacidad 0.5 = 128: (d * 127 + mixed * 128 + 127) / 255
+        let opacity = opacity_to_u32(0.5);
+        assert_eq!(opacity, 128);
+
+        let expected = [
+            (BlendMode::Normal, 0xFF84_7270),
+            (BlendMode::Add, 0xFFA0_B2D0),
+            (BlendMode::Multiply, 0xFF39_596C),
+            (BlendMode::Screen, 0xFF8B_99C4),
+            (BlendMode::Overlay, 0xFF52_72A8),
+        ];
+
+        for (mode, pixel) in expected {
+            assert_eq!(mode.blend(DST, SRC, opacity), pixel, "{}", mode.name());
+
+            // El alfa de la capa pesa igual que la opacidad
+            let translucent = (SRC & 0x00FF_FFFF) | 0x8000_0000;
+            assert_eq!(mode.blend(DST, translucent, 255), pixel, "{} with alpha", mode.name());
+        }
+    }
+
+    #[test]
+    fn transparent_layers_keep_the_frame() {
+        for mode in BlendMode::ALL {
+            assert_eq!(mode.blend(DST, SRC & 0x00FF_FFFF, 255), DST);
+            assert_eq!(mode.blend(DST, SRC, 0), DST);
+        }
+
+        let mut frame = [DST; 4];
+        composite(&mut frame, &[SRC; 4], 0.0, BlendMode::Normal);
+        assert_eq!(frame, [DST; 4]);
+
+        composite(&mut frame, &[SRC, 0, SRC, 0], 1.0, BlendMode::Normal);
+        assert_eq!(frame, [SRC, DST, SRC, DST]);
+    }
+
+    #[test]
+    fn parses_the_names() {
+        for mode in BlendMode::ALL {
+            assert_eq!(mode.name().parse::<BlendMode>(), Ok(mode));
+            assert_eq!(mode.name().to_uppercase().parse::<BlendMode>(), Ok(mode));
+        }
+
+        assert!("darken".parse::<BlendMode>().is_err());
+        assert_eq!(opacity_to_u32(-1.0), 0);
+        assert_eq!(opacity_to_u32(2.0), 255);
+    }
+}
@@ -0,0 +1,277 @@
+use crate::convolutions::effect::Executor;
+use crate::convolutions::kernel::{EdgeMode, Kernel};
+use crate::convolutions::params::{self, Param, ParamSpec, ParamValue};
+use crate::convolutions::{Color, Convolution, Damage, Filter, FrameContext, Resampler};
+
+/// Largest number of scales of the glow, each one is half of the previous
+pub const MAX_LEVELS: usize = 6;
+
+/// Glow of the bright parts of the frame below: the pixels brighter than a
+/// threshold are blurred at several scales and added to the frame. Every
+/// pass runs row by row through the [`Executor`]
+pub struct BloomConvolution {
+    pub intensity: f32, // Brillo del resplandor sumado al frame
+    pub threshold: f32, // Luminancia a partir de la cual un píxel brilla, entre 0 y 1
+    pub radius: usize,  // Radio del desenfoque en cada escala
+    pub levels: usize,  // Escalas del resplandor, cada una mide la mitad de la anterior
+    pub executor: Executor,
+    kernel: Kernel,
+    bright: Vec<u32>,      // Partes brillantes del frame
+    scales: Vec<Vec<u32>>, // Partes brillantes reducidas a cada escala
+    blurred: Vec<u32>,     // Escala desenfocada
+    temp: Vec<[f32; 3]>,   // Primera pasada del desenfoque
+    glow: Vec<Glow>,       // Resplandor de cada escala sumado al de las menores
+    resampler: Resampler,
+}
+
+impl BloomConvolution {
+    pub fn new(intensity: f32, threshold: f32, radius: usize, levels: usize, executor: Executor) -> Self {
+        BloomConvolution {
+            intensity,
+            threshold,
+            radius,
+            levels,
+            executor,
+            kernel: Kernel::gaussian_blur(radius),
+            bright: Vec::new(),
+            scales: Vec::new(),
+            blurred: Vec::new(),
+            temp: Vec::new(),
+            glow: Vec::new(),
+            resampler: Resampler::default(),
+        }
+    }
+
+    pub fn schema(executor: Executor) -> Vec<ParamSpec> {
+        vec![
+            ParamSpec::float("intensity", 1.0, 0.0, 4.0, "Brightness of the glow added to the frame"),
+            ParamSpec::float("threshold", 0.7, 0.0, 0.99, "Luminance from which a pixel glows, between 0 and 1"),
+            ParamSpec::int("radius", 4, 1, 15, "Radius of the blur at each scale"),
+            ParamSpec::int("levels", 4, 1, MAX_LEVELS as i64, "Scales of the glow, each one is half of the previous"),
+            Executor::spec(executor),
+        ]
+    }
+
+    /// Keeps the part of each pixel over the threshold, the color is scaled
+    /// by how far its luminance is over the threshold
+    fn extract(&mut self, pixels: &[u32], width: usize) {
+        let threshold = self.threshold;
+
+        self.bright.resize(pixels.len(), Color::TRANSPARENT);
+        self.executor.run_rows(&mut self.bright, width, |y, line| {
+            for (bright, &pixel) in line.iter_mut().zip(&pixels[y * width..]) {
+                let [red, green, blue] = [16, 8, 0].map(|shift| ((pixel >> shift) & 0xFF) as f32);
+                let luminance = (0.2126 * red + 0.7152 * green + 0.0722 * blue) / 255.0;
+                let weight = ((luminance - threshold) / (1.0 - threshold)).clamp(0.0, 1.0);
+
+                *bright = Color::rgb((red * weight) as u8, (green * weight) as u8, (blue * weight) as u8);
+            }
+        });
+    }
+}
+
+impl Convolution for BloomConvolution {
+    fn name(&self) -> &str {
+        "Bloom"
+    }
+
+    fn transform(&mut self, pixels: &mut [u32], width: usize, height: usize, _ctx: &FrameContext) {
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        self.extract(pixels, width);
+        self.scales.resize_with(self.levels, Vec::new);
+        self.glow.resize_with(self.levels, Glow::default);
+
+        // Cada escala se reduce desde la anterior y se desenfoca
+        let (mut source, mut source_width, mut source_height) = (&self.bright, width, height);
+
+        for (scale, glow) in self.scales.iter_mut().zip(self.glow.iter_mut()) {
+            let (scale_width, scale_height) = ((source_width / 2).max(1), (source_height / 2).max(1));
+
+            scale.resize(scale_width * scale_height, Color::TRANSPARENT);
+            self.resampler.resample(Filter::Bilinear, source, (source_width, source_height), scale, (scale_width, scale_height));
+
+            self.blurred.resize(scale.len(), Color::TRANSPARENT);
+            self.kernel.apply(scale, &mut self.blurred, &mut self.temp, (scale_width, scale_height), EdgeMode::Clamp, self.executor);
+            glow.load(&self.blurred, scale_width, scale_height, self.executor);
+
+            (source, source_width, source_height) = (scale, scale_width, scale_height);
+        }
+
+        // De la escala menor a la mayor, cada una suma la anterior ampliada,
+        // así solo la última se amplía al tamaño del frame
+        for level in (1..self.levels).rev() {
+            let (larger, smaller) = self.glow.split_at_mut(level);
+            larger[level - 1].add(&smaller[0], self.executor);
+        }
+
+        // La suma de las escalas se añade al frame, como luz
+        let (glow, intensity) = (&self.glow[0], self.intensity);
+
+        self.executor.run_rows(pixels, width, |y, row| {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let light = glow.sample(x, y, width, height);
+                let [red, green, blue] = [0, 1, 2].map(|c| {
+                    let channel = ((*pixel >> (16 - 8 * c)) & 0xFF) as f32;
+                    (channel + light[c] * intensity).round().min(255.0) as u8
+                });
+
+                *pixel = Color::rgba(red, green, blue, Color::alpha(*pixel));
+            }
+        });
+    }
+
+    fn reads_frame(&self) -> bool {
+        true
+    }
+
+    // Como los kernels, solo cambia con el frame de abajo
+    fn damage(&self, _width: usize, _height: usize, _ctx: &FrameContext) -> Damage {
+        Damage::None
+    }
+
+    fn params(&self) -> Vec<Param> {
+        params::with_values(Self::schema(self.executor), [
+            ParamValue::Float(self.intensity as f64),
+            ParamValue::Float(self.threshold as f64),
+            ParamValue::Int(self.radius as i64),
+            ParamValue::Int(self.levels as i64),
+            ParamValue::Text(self.executor.name().to_string()),
+        ])
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        let value = params::validate(&Self::schema(self.executor), name, &value)?;
+
+        match name {
+            "intensity" => self.intensity = value.as_f64().unwrap_or_default() as f32,
+            "threshold" => self.threshold = value.as_f64().unwrap_or_default() as f32,
+            "radius" => {
+                self.radius = value.as_i64().unwrap_or_default() as usize;
+                self.kernel = Kernel::gaussian_blur(self.radius);
+            }
+            "levels" => self.levels = value.as_i64().unwrap_or_default() as usize,
+            _ => self.executor = value.as_str().unwrap_or_default().parse()?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Glow of one scale, the channels are kept as floats so the scales can be
+/// added without saturating
+#[derive(Default)]
+struct Glow {
+    values: Vec<[f32; 3]>,
+    width: usize,
+    height: usize,
+}
+
+impl Glow {
+    fn load(&mut self, pixels: &[u32], width: usize, height: usize, executor: Executor) {
+        (self.width, self.height) = (width, height);
+        self.values.resize(pixels.len(), [0.0; 3]);
+        executor.run_rows(&mut self.values, width, |y, line| {
+            for (value, &pixel) in line.iter_mut().zip(&pixels[y * width..]) {
+                *value = [16, 8, 0].map(|shift| ((pixel >> shift) & 0xFF) as f32);
+            }
+        });
+    }
+
+    /// Adds `other` resampled to the size of this scale
+    fn add(&mut self, other: &Glow, executor: Executor) {
+        let (width, height) = (self.width, self.height);
+
+        executor.run_rows(&mut self.values, width, |y, row| {
+            for (x, value) in row.iter_mut().enumerate() {
+                let light = other.sample(x, y, width, height);
+                (0..3).for_each(|c| value[c] += light[c]);
+            }
+        });
+    }
+
+    /// Bilinear sample at the pixel `(x, y)` of a frame of `width x height`,
+    /// the centers of the pixels are aligned and the borders are repeated
+    fn sample(&self, x: usize, y: usize, width: usize, height: usize) -> [f32; 3] {
+        let axis = |i: usize, size: usize, own: usize| {
+            let center = ((i as f32 + 0.5) * own as f32 / size as f32 - 0.5).clamp(0.0, (own - 1) as f32);
+            let first = center as usize;
+            (first, (first + 1).min(own - 1), center - first as f32)
+        };
+
+        let (x0, x1, tx) = axis(x, width, self.width);
+        let (y0, y1, ty) = axis(y, height, self.height);
+        let at = |x: usize, y: usize| self.values[y * self.width + x];
+        let (a, b, c, d) = (at(x0, y0), at(x1, y0), at(x0, y1), at(x1, y1));
+
+        [0, 1, 2].map(|i| {
+            let top = a[i] + (b[i] - a[i]) * tx;
+            let bottom = c[i] + (d[i] - c[i]) * tx;
+            top + (bottom - top) * ty
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Local;
+    use super::*;
+
+    fn bloom(pixels: &[u32], (width, height): (usize, usize), threshold: f32, executor: Executor) -> Vec<u32> {
+        let mut bloom = BloomConvolution::new(1.5, threshold, 2, 3, executor);
+        let mut pixels = pixels.to_vec();
+        bloom.transform(&mut pixels, width, height, &FrameContext::new(Local::now()));
+        pixels
+    }
+
+    fn red(pixel: u32) -> i32 {
+        ((pixel >> 16) & 0xFF) as i32
+    }
+
+    #[test]
+    fn dark_frames_dont_change() {
+        let (width, height) = (40, 30);
+        // Luminancia como mucho 0.5, por debajo del umbral
+        let frame: Vec<u32> = (0..width * height)
+            .map(|i| Color::rgba((i % 128) as u8, (i * 7 % 128) as u8, (i * 3 % 128) as u8, (i % 256) as u8))
+            .collect();
+
+        for executor in Executor::ALL {
+            assert!(bloom(&frame, (width, height), 0.6, executor) == frame, "{} changed the frame", executor.name());
+        }
+    }
+
+    #[test]
+    fn a_bright_spot_glows_symmetrically() {
+        let size = 33;
+        let center = size / 2;
+        let mut frame = vec![Color::rgb(0, 0, 0); size * size];
+        // Un punto de 3x3, al reducir a la mitad el filtro bilineal no
+        // muestrea todos los píxeles
+        for y in center - 1..=center + 1 {
+            frame[y * size + center - 1..=y * size + center + 1].fill(Color::rgb(255, 255, 255));
+        }
+
+        let expected = bloom(&frame, (size, size), 0.5, Executor::Sequential);
+        let at = |x: usize, y: usize| red(expected[y * size + x]);
+
+        // El resplandor llega a los vecinos y se apaga con la distancia
+        assert!(at(center + 2, center) > 0);
+        assert!(at(center + 2, center) >= at(center + 4, center));
+        assert!(at(center + 4, center) >= at(center + 12, center));
+
+        // Simétrico respecto a los dos ejes y a la diagonal, salvo redondeo
+        for y in 0..size {
+            for x in 0..size {
+                let mirrors = [at(size - 1 - x, y), at(x, size - 1 - y), at(y, x)];
+                assert!(mirrors.iter().all(|&mirror| (mirror - at(x, y)).abs() <= 1), "asymmetric glow at {:?}", (x, y));
+            }
+        }
+
+        for executor in Executor::ALL {
+            assert!(bloom(&frame, (size, size), 0.5, executor) == expected, "{} differs from sequential", executor.name());
+        }
+    }
+}
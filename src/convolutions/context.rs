@@ -0,0 +1,59 @@
+use std::time::Duration;
+use chrono::{DateTime, Local};
+use minifb::{Key, MouseButton};
+
+/// Keyboard and mouse state sampled at the start of the frame
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InputState {
+    /// Keys held down
+    pub keys: Vec<Key>,
+    /// Mouse position in buffer coordinates, `None` outside of the window
+    pub mouse: Option<(f32, f32)>,
+    /// Left, middle and right buttons
+    pub buttons: [bool; 3],
+}
+
+impl InputState {
+    pub fn is_key_down(&self, key: Key) -> bool {
+        self.keys.contains(&key)
+    }
+
+    pub fn is_button_down(&self, button: MouseButton) -> bool {
+        match button {
+            MouseButton::Left => self.buttons[0],
+            MouseButton::Middle => self.buttons[1],
+            MouseButton::Right => self.buttons[2],
+        }
+    }
+}
+
+/// Information of the frame shared by every layer
+#[derive(Debug, Clone)]
+pub struct FrameContext {
+    /// Index of the frame, starting at 0
+    pub frame: u64,
+    /// Time since the previous frame
+    pub dt: Duration,
+    /// Wall-clock time given by the app clock
+    pub now: DateTime<Local>,
+    /// Monotonic time since the first frame
+    pub elapsed: Duration,
+    /// Time of the animations since the first frame, the same as `elapsed`
+    /// unless the clock advances with the frames (without a window)
+    pub animation: Duration,
+    pub input: InputState,
+}
+
+impl FrameContext {
+    /// Context of the first frame at the given time
+    pub fn new(now: DateTime<Local>) -> Self {
+        FrameContext {
+            frame: 0,
+            dt: Duration::ZERO,
+            now,
+            elapsed: Duration::ZERO,
+            animation: Duration::ZERO,
+            input: InputState::default(),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::ops::Range;
+
+/// Rectangle of the frame, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Rect { x, y, width, height }
+    }
+
+    /// Smallest rectangle that contains both
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+
+        Rect::new(x, y, right - x, bottom - y)
+    }
+
+    /// Rows covered by the rectangle inside a frame of `height` rows
+    pub fn rows(&self, height: usize) -> Range<usize> {
+        self.y.min(height)..(self.y + self.height).min(height)
+    }
+}
+
+/// Part of a layer that changed since the last frame
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Damage {
+    /// Same pixels as the last frame
+    None,
+    /// The whole layer must be drawn again
+    #[default]
+    Full,
+    /// Only the pixels inside the rectangles changed
+    Rects(Vec<Rect>),
+}
+
+impl Damage {
+    pub fn is_none(&self) -> bool {
+        matches!(self, Damage::None)
+    }
+
+    /// Damage of two layers of the same frame
+    pub fn merge(self, other: Damage) -> Damage {
+        match (self, other) {
+            (Damage::Full, _) | (_, Damage::Full) => Damage::Full,
+            (Damage::None, damage) | (damage, Damage::None) => damage,
+            (Damage::Rects(mut rects), Damage::Rects(other)) => {
+                rects.extend(other);
+                Damage::Rects(rects)
+            }
+        }
+    }
+
+    /// Sorted and merged ranges of rows covered by the rectangles,
+    /// empty for `None` and every row for `Full`
+    pub fn rows(&self, height: usize) -> Vec<Range<usize>> {
+        let rects = match self {
+            Damage::None => return Vec::new(),
+            Damage::Full => return std::iter::once(0..height).collect(),
+            Damage::Rects(rects) => rects,
+        };
+
+        let mut ranges: Vec<Range<usize>> = rects.iter()
+            .map(|rect| rect.rows(height))
+            .filter(|rows| !rows.is_empty())
+            .collect();
+        ranges.sort_by_key(|rows| rows.start);
+
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+        for rows in ranges {
+            match merged.last_mut() {
+                Some(last) if rows.start <= last.end => last.end = last.end.max(rows.end),
+                _ => merged.push(rows),
+            }
+        }
+
+        merged
+    }
+}
@@ -0,0 +1,358 @@
+use std::str::FromStr;
+use rayon::prelude::*;
+use crate::convolutions::params::{self, Param, ParamSpec, ParamValue};
+use crate::convolutions::{Convolution, ConvolutionAdvanced, FrameContext};
+
+/// Effect written once as a function of each pixel, the [`Executor`]
+/// decides how the pixels are distributed between threads
+pub trait PixelEffect: Send + Sync {
+    /// Function to get the effect name
+    fn name(&self) -> &str;
+
+    /// Function to update the state shared by every pixel of the frame,
+    /// it runs on a single thread before the pixels
+    fn prepare(&mut self, _width: usize, _height: usize, _ctx: &FrameContext) {}
+
+    /// Function to get the color (RGBA) of the pixel `(x, y)`
+    fn pixel(&self, x: usize, y: usize, width: usize, height: usize) -> u32;
+
+    fn params(&self) -> Vec<Param> {
+        Vec::new()
+    }
+
+    fn set_param(&mut self, name: &str, _value: ParamValue) -> Result<(), String> {
+        Err(format!("{} has no parameter {:?}", self.name(), name))
+    }
+
+    fn resize(&mut self, _width: usize, _height: usize) {}
+
+    fn reset(&mut self) {}
+}
+
+/// Side of the square tiles of [`Executor::RayonTiles`]
+const TILE_SIZE: usize = 64;
+
+/// Strategy used to run a [`PixelEffect`] over the frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Executor {
+    /// One thread, pixel by pixel
+    #[default]
+    Sequential,
+    /// Rayon, each pixel is a task
+    RayonPixels,
+    /// Rayon, each task is a group of rows
+    RayonRows,
+    /// Rayon, each task is a square tile of the frame
+    RayonTiles,
+    /// Standard scoped threads, one block of rows per thread
+    ScopedThreads,
+}
+
+impl Executor {
+    pub const ALL: [Executor; 5] = [
+        Executor::Sequential,
+        Executor::RayonPixels,
+        Executor::RayonRows,
+        Executor::RayonTiles,
+        Executor::ScopedThreads,
+    ];
+
+    pub const NAMES: [&'static str; 5] = ["sequential", "rayon_pixels", "rayon_rows", "rayon_tiles", "scoped_threads"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Executor::Sequential => Self::NAMES[0],
+            Executor::RayonPixels => Self::NAMES[1],
+            Executor::RayonRows => Self::NAMES[2],
+            Executor::RayonTiles => Self::NAMES[3],
+            Executor::ScopedThreads => Self::NAMES[4],
+        }
+    }
+
+    pub fn is_parallel(&self) -> bool {
+        *self != Executor::Sequential
+    }
+
+    /// Schema of the `executor` parameter, `default` is the executor of the layer
+    pub fn spec(default: Executor) -> ParamSpec {
+        ParamSpec::choice("executor", default.name(), &Executor::NAMES, "Strategy to distribute the pixels between threads")
+    }
+
+    /// Fills `pixels` with the effect
+    pub fn run<E: PixelEffect + ?Sized>(&self, effect: &E, pixels: &mut [u32], width: usize, height: usize) {
+        let pixel = |i: usize| effect.pixel(i % width, i / width, width, height);
+
+        match self {
+            Executor::Sequential => {
+                pixels.iter_mut().enumerate().for_each(|(i, p)| *p = pixel(i));
+            }
+            Executor::RayonPixels => {
+                pixels.par_iter_mut().enumerate().for_each(|(i, p)| *p = pixel(i));
+            }
+            Executor::RayonRows => {
+                // Unos 4 grupos de filas por hilo para balancear la carga
+                let rows = height.div_ceil(rayon::current_num_threads() * 4).max(1);
+
+                pixels.par_chunks_mut(rows * width).enumerate().for_each(|(chunk, block)| {
+                    let offset = chunk * rows * width;
+                    block.iter_mut().enumerate().for_each(|(i, p)| *p = pixel(offset + i));
+                });
+            }
+            Executor::RayonTiles => {
+                // Cada franja de TILE_SIZE filas se corta en columnas, cada
+                // baldosa es la lista de sus segmentos de fila
+                let mut tiles: Vec<(usize, usize, Vec<&mut [u32]>)> = Vec::new();
+
+                for (band, rows) in pixels.chunks_mut(TILE_SIZE * width).enumerate() {
+                    let first = tiles.len();
+                    for (row, line) in rows.chunks_mut(width).enumerate() {
+                        for (column, segment) in line.chunks_mut(TILE_SIZE).enumerate() {
+                            if row == 0 {
+                                tiles.push((band * TILE_SIZE, column * TILE_SIZE, Vec::with_capacity(TILE_SIZE)));
+                            }
+                            tiles[first + column].2.push(segment);
+                        }
+                    }
+                }
+
+                tiles.into_par_iter().for_each(|(top, left, segments)| {
+                    for (row, segment) in segments.into_iter().enumerate() {
+                        let y = top + row;
+                        for (column, p) in segment.iter_mut().enumerate() {
+                            *p = effect.pixel(left + column, y, width, height);
+                        }
+                    }
+                });
+            }
+            Executor::ScopedThreads => {
+                let threads = rayon::current_num_threads().max(1);
+                let rows = height.div_ceil(threads).max(1);
+
+                std::thread::scope(|scope| {
+                    for (chunk, block) in pixels.chunks_mut(rows * width).enumerate() {
+                        let pixel = &pixel;
+                        scope.spawn(move || {
+                            let offset = chunk * rows * width;
+                            block.iter_mut().enumerate().for_each(|(i, p)| *p = pixel(offset + i));
+                        });
+                    }
+                });
+            }
+        }
+    }
+
+    /// Fills `out` row by row with `row(y, line)`, for the effects that need
+    /// a whole row at once. A row is the smallest task, so `RayonPixels`
+    /// gives a task to each row and `RayonTiles` to each band of rows
+    pub fn run_rows<T, F>(&self, out: &mut [T], width: usize, row: F)
+    where
+        T: Send,
+        F: Fn(usize, &mut [T]) + Sync,
+    {
+        let width = width.max(1);
+        let height = out.len() / width;
+
+        // Bloques de `rows` filas, cada uno es una tarea
+        let blocks = |block: usize, lines: &mut [T], rows: usize| {
+            for (y, line) in lines.chunks_mut(width).enumerate() {
+                row(block * rows + y, line);
+            }
+        };
+
+        match self {
+            Executor::Sequential => blocks(0, out, height),
+            Executor::RayonPixels => {
+                out.par_chunks_mut(width).enumerate().for_each(|(y, line)| row(y, line));
+            }
+            Executor::RayonRows => {
+                let rows = height.div_ceil(rayon::current_num_threads() * 4).max(1);
+                out.par_chunks_mut(rows * width).enumerate().for_each(|(block, lines)| blocks(block, lines, rows));
+            }
+            Executor::RayonTiles => {
+                out.par_chunks_mut(TILE_SIZE * width).enumerate().for_each(|(block, lines)| blocks(block, lines, TILE_SIZE));
+            }
+            Executor::ScopedThreads => {
+                let rows = height.div_ceil(rayon::current_num_threads().max(1)).max(1);
+
+                std::thread::scope(|scope| {
+                    for (block, lines) in out.chunks_mut(rows * width).enumerate() {
+                        let blocks = &blocks;
+                        scope.spawn(move || blocks(block, lines, rows));
+                    }
+                });
+            }
+        }
+    }
+}
+
+impl FromStr for Executor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Executor::ALL
+            .into_iter()
+            .find(|executor| executor.name() == s)
+            .ok_or_else(|| format!("unknown executor {:?} (expected one of {:?})", s, Executor::NAMES))
+    }
+}
+
+/// Layer that runs a [`PixelEffect`] with an [`Executor`] chosen at runtime
+/// through its `executor` parameter
+pub struct EffectConvolution<E: PixelEffect> {
+    pub effect: E,
+    pub executor: Executor,
+}
+
+impl<E: PixelEffect> EffectConvolution<E> {
+    pub fn new(effect: E, executor: Executor) -> Self {
+        EffectConvolution { effect, executor }
+    }
+}
+
+impl<E: PixelEffect> Convolution for EffectConvolution<E> {
+    fn name(&self) -> &str {
+        self.effect.name()
+    }
+
+    fn transform(&mut self, pixels: &mut [u32], width: usize, height: usize, ctx: &FrameContext) {
+        self.effect.prepare(width, height, ctx);
+        self.executor.run(&self.effect, pixels, width, height);
+    }
+
+    // Con el renderizado por tiles el executor no se usa, cada tile ya
+    // corre en un hilo del pool
+    fn prepare_tiles(&mut self, width: usize, height: usize, ctx: &FrameContext) -> bool {
+        self.effect.prepare(width, height, ctx);
+        true
+    }
+
+    fn transform_tile(&self, pixels: &mut [u32], top: usize, width: usize, height: usize) {
+        for (y, row) in pixels.chunks_mut(width).enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = self.effect.pixel(x, top + y, width, height);
+            }
+        }
+    }
+
+    fn resize(&mut self, width: usize, height: usize) {
+        self.effect.resize(width, height);
+    }
+
+    fn params(&self) -> Vec<Param> {
+        let executor = Param::new(Executor::spec(self.executor), ParamValue::Text(self.executor.name().to_string()));
+
+        let mut params = self.effect.params();
+        params.push(executor);
+        params
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        if name != "executor" {
+            return self.effect.set_param(name, value);
+        }
+
+        let value = params::validate(&[Executor::spec(self.executor)], name, &value)?;
+        self.executor = value.as_str().unwrap_or_default().parse()?;
+        Ok(())
+    }
+}
+
+impl<E: PixelEffect> ConvolutionAdvanced for EffectConvolution<E> {
+    fn reset(&mut self) {
+        self.effect.reset();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::time::Duration;
+    use chrono::{Local, TimeZone};
+    use super::*;
+    use crate::convolutions::Color;
+
+    /// Draws `frames` frames of 16 ms with every executor and checks that
+    /// they give the same pixels as [`Executor::Sequential`]. `layer` builds
+    /// the layer with the given executor, the frames are drawn on the same
+    /// buffer so the layers can depend on the last frame.
+    pub(crate) fn assert_executors_agree<C, F>(width: usize, height: usize, frames: usize, layer: F)
+    where
+        C: Convolution,
+        F: Fn(Executor) -> C,
+    {
+        let start = Local.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+
+        let render = |executor: Executor| {
+            let mut layer = layer(executor);
+            let mut ctx = FrameContext::new(start);
+            let mut pixels = vec![Color::TRANSPARENT; width * height];
+            let mut drawn = Vec::with_capacity(frames);
+
+            for frame in 0..frames {
+                ctx.frame = frame as u64;
+                ctx.dt = Duration::from_millis(16);
+                ctx.elapsed = ctx.dt * frame as u32;
+                ctx.animation = ctx.elapsed;
+                ctx.now = start + chrono::Duration::from_std(ctx.elapsed).unwrap();
+
+                layer.transform(&mut pixels, width, height, &ctx);
+                drawn.push(pixels.clone());
+            }
+
+            drawn
+        };
+
+        let expected = render(Executor::Sequential);
+        assert!(
+            expected.iter().flatten().any(|&pixel| pixel != Color::TRANSPARENT),
+            "the sequential frames are empty",
+        );
+
+        for executor in Executor::ALL {
+            for (frame, (pixels, expected)) in render(executor).iter().zip(expected.iter()).enumerate() {
+                assert!(pixels == expected, "{} differs from sequential on frame {}", executor.name(), frame);
+            }
+        }
+    }
+
+    /// Gradient that depends on the position, to check that every
+    /// executor visits each pixel with its own coordinates
+    struct Gradient;
+
+    impl PixelEffect for Gradient {
+        fn name(&self) -> &str {
+            "Gradient"
+        }
+
+        fn pixel(&self, x: usize, y: usize, width: usize, height: usize) -> u32 {
+            Color::rgba((x * 255 / width) as u8, (y * 255 / height) as u8, (x ^ y) as u8, 255)
+        }
+    }
+
+    #[test]
+    fn executors_agree_on_every_pixel() {
+        // Tamaños que no son múltiplos de las baldosas ni de los hilos
+        for (width, height) in [(1, 1), (7, 3), (TILE_SIZE + 3, TILE_SIZE * 2 + 1)] {
+            assert_executors_agree(width, height, 1, |executor| EffectConvolution::new(Gradient, executor));
+        }
+    }
+
+    #[test]
+    fn executor_names_round_trip() {
+        for executor in Executor::ALL {
+            assert_eq!(executor.name().parse(), Ok(executor));
+        }
+        assert!("threads".parse::<Executor>().is_err());
+    }
+
+    #[test]
+    fn executor_param_changes_the_executor() {
+        let mut layer = EffectConvolution::new(Gradient, Executor::Sequential);
+
+        layer.set_param("executor", ParamValue::Text("rayon_tiles".to_string())).expect("Valid executor");
+        assert_eq!(layer.executor, Executor::RayonTiles);
+
+        assert!(layer.set_param("executor", ParamValue::Text("gpu".to_string())).is_err());
+        assert!(layer.set_param("speed", ParamValue::Float(1.0)).is_err());
+        assert_eq!(layer.executor, Executor::RayonTiles);
+    }
+}
@@ -1,18 +1,35 @@
-use std::time::Instant;
-use rayon::prelude::*;
+use std::time::Duration;
 use rand::Rng;
-use crate::convolutions::{Color, Convolution, ConvolutionAdvanced, Palette};
+use crate::convolutions::effect::{Executor, PixelEffect};
+use crate::convolutions::params::{self, Param, ParamSet, ParamSpec, ParamValue};
+use crate::convolutions::{Color, Convolution, ConvolutionAdvanced, FrameContext, Palette};
 
 pub struct FireConvolution {
     pub intensity: f32,  // Un parámetro que controla la "intensidad" del fuego.
 }
 
+impl FireConvolution {
+    pub fn schema() -> Vec<ParamSpec> {
+        vec![
+            ParamSpec::float("intensity", 1.0, 0.01, 1.0, "Maximum brightness of the flames"),
+        ]
+    }
+}
+
 impl Convolution for FireConvolution {
     fn name(&self) -> &str {
         "Fire"
     }
 
-    fn transform(&mut self, pixels: &mut [u32], _width: usize, _height: usize) {
+    fn transform(&mut self, pixels: &mut [u32], width: usize, height: usize, _ctx: &FrameContext) {
+        self.transform_tile(pixels, 0, width, height);
+    }
+
+    fn prepare_tiles(&mut self, _width: usize, _height: usize, _ctx: &FrameContext) -> bool {
+        true
+    }
+
+    fn transform_tile(&self, pixels: &mut [u32], _top: usize, _width: usize, _height: usize) {
         // Usamos un generador de números aleatorios para crear variabilidad en el color del fuego
         let mut rng = rand::thread_rng();
         // Iterar sobre todos los píxeles y aplicar el efecto de fuego
@@ -28,57 +45,143 @@ impl Convolution for FireConvolution {
             *pixel = Color::rgb(red, green, blue);
         }
     }
+
+    fn params(&self) -> Vec<Param> {
+        params::with_values(Self::schema(), [ParamValue::Float(self.intensity as f64)])
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        let value = params::validate(&Self::schema(), name, &value)?;
+        self.intensity = value.as_f64().unwrap_or_default() as f32;
+        Ok(())
+    }
 }
 
 
+/// Fire simulated with a heat map: the bottom row is heated on every frame,
+/// the heat rises spreading to the neighbours and cools on the way up
 pub struct HeatFireConvolution {
-    base_intensity: f32,  // La intensidad base del fuego en la parte inferior
-    falloff: f32,         // La "caída" o disminución de la intensidad a medida que subimos
-    heat_buffer: Vec<f32>, // Buffer de calor para acumular la intensidad del fuego
+    pub base_intensity: f32, // Calor de la fila inferior, entre 0 y 1
+    pub cooling: f32,        // Enfriamiento al subir, más alto = llamas más bajas
+    pub diffusion: f32,      // Parte del calor que se reparte con los vecinos
+    heat_buffer: Vec<f32>,   // Calor de cada píxel, entre 0 y 1
+    next_buffer: Vec<f32>,   // Calor del siguiente paso, se intercambia con `heat_buffer`
+    cooling_map: Vec<f32>,   // Ruido suave que sube con el fuego, da forma a las llamas
+    offset: usize,           // Desplazamiento vertical del mapa de enfriamiento
+    palette: Palette,
+    width: usize,
+    height: usize,
 }
 
 impl HeatFireConvolution {
-    pub fn new(base_intensity: f32, falloff: f32, width: usize, height: usize) -> Self {
+    pub fn new(base_intensity: f32, cooling: f32, diffusion: f32, width: usize, height: usize) -> Self {
         HeatFireConvolution {
             base_intensity,
-            falloff,
+            cooling,
+            diffusion,
             heat_buffer: vec![0.0; width * height], // Inicializa el buffer de calor
+            next_buffer: vec![0.0; width * height],
+            cooling_map: Self::gen_cooling_map(width, height),
+            offset: 0,
+            palette: gen_palette(),
+            width,
+            height,
+        }
+    }
+
+    pub fn schema() -> Vec<ParamSpec> {
+        vec![
+            ParamSpec::float("base_intensity", 1.0, 0.01, 1.0, "Heat of the bottom row"),
+            ParamSpec::float("cooling", 0.3, 0.05, 1.0, "Cooling of the heat while it rises, higher makes shorter flames"),
+            ParamSpec::float("diffusion", 0.6, 0.0, 1.0, "Part of the heat that spreads to the neighbours"),
+        ]
+    }
+
+    /// Smooth noise between 0 and 2 (average 1), some passes of a box
+    /// blur over white noise
+    fn gen_cooling_map(width: usize, height: usize) -> Vec<f32> {
+        let mut rng = rand::thread_rng();
+        let mut map: Vec<f32> = (0..width * height).map(|_| rng.gen()).collect();
+        let mut blurred = map.clone();
+
+        for _ in 0..4 {
+            for y in 0..height {
+                for x in 0..width {
+                    let mut sum = 0.0;
+                    for (dx, dy) in [(0, 0), (-1, 0), (1, 0), (0, -1), (0, 1)] {
+                        let sx = (x as isize + dx).rem_euclid(width as isize) as usize;
+                        let sy = (y as isize + dy).rem_euclid(height as isize) as usize;
+                        sum += map[sy * width + sx];
+                    }
+                    blurred[y * width + x] = sum / 5.0;
+                }
+            }
+            std::mem::swap(&mut map, &mut blurred);
         }
+
+        // Normaliza al rango 0..2
+        let min = map.iter().copied().fold(f32::MAX, f32::min);
+        let max = map.iter().copied().fold(f32::MIN, f32::max);
+        let range = (max - min).max(f32::EPSILON);
+        map.iter_mut().for_each(|value| *value = (*value - min) / range * 2.0);
+
+        map
     }
 
     // function to update the heat in the heat_buffer
-    fn update_heat_buffer(&mut self, width: usize, height: usize) {
+    fn update_heat_buffer(&mut self) {
+        let (width, height) = (self.width, self.height);
+        if width == 0 || height == 0 {
+            return;
+        }
+
         let mut rng = rand::thread_rng(); // Generador de números aleatorios
 
-        for y in 0..height {
-            for x in 0..width {
-                let index = y * width + x;
+        // La fila inferior es la fuente de calor, con un poco de parpadeo
+        let bottom = (height - 1) * width;
+        for heat in self.heat_buffer[bottom..].iter_mut() {
+            *heat = self.base_intensity * rng.gen_range(0.3..=1.0);
+        }
 
-                // Genera un valor aleatorio de fuego en la base (color rojo), ajusta según la posición (altura)
-                let intensity = self.base_intensity * (1.0 - self.falloff * (y as f32 / height as f32));
-                let flame_intensity = rng.gen_range(0.0..intensity); // Aleatorio en el rango [0.0, intensity)
+        // Enfriamiento por fila, relativo a la altura para que las llamas
+        // tengan la misma forma en cualquier resolución
+        let cooling = self.cooling * 8.0 / height as f32;
 
-                // Acumulamos el calor en el heat_buffer
-                self.heat_buffer[index] += flame_intensity; // Incrementa la intensidad de calor
+        for y in 0..height - 1 {
+            for x in 0..width {
+                let below = |dx: isize, dy: usize| {
+                    let sx = (x as isize + dx).clamp(0, width as isize - 1) as usize;
+                    let sy = (y + dy).min(height - 1);
+                    self.heat_buffer[sy * width + sx]
+                };
+
+                // El calor sube desde la fila de abajo con un pequeño desvío
+                // aleatorio (lenguas de fuego) y se reparte con los vecinos
+                let drift = rng.gen_range(-1..=1);
+                let center = below(drift, 1);
+                let neighbours = (below(drift - 1, 1) + center + below(drift + 1, 1) + below(drift, 2)) / 4.0;
+                let heat = center * (1.0 - self.diffusion) + neighbours * self.diffusion;
+
+                // El mapa de enfriamiento sube con el calor
+                let map = self.cooling_map[((y + self.offset) % height) * width + x];
+                self.next_buffer[y * width + x] = (heat - cooling * map).clamp(0.0, 1.0);
             }
         }
+
+        self.next_buffer[bottom..].copy_from_slice(&self.heat_buffer[bottom..]);
+        std::mem::swap(&mut self.heat_buffer, &mut self.next_buffer);
+        self.offset = (self.offset + 1) % height;
     }
 
     // Function to mix heat with color
-    fn apply_heat_to_pixels(&self, pixels: &mut [u32], width: usize, height: usize) {
-        for y in 0..height {
-            for x in 0..width {
-                let index = y * width + x;
-                let heat = self.heat_buffer[index];
-
-                // Ajuste del color del fuego dependiendo de la intensidad de calor
-                let red = (heat * 255.0) as u8;
-                let green = (heat * 150.0) as u8;
-                let blue = (heat * 50.0) as u8;
-
-                // Aplicamos el color calculado a cada píxel
-                pixels[index] = Color::rgb(red, green, blue);
-            }
+    fn apply_heat_to_pixels(&self, pixels: &mut [u32]) {
+        for (pixel, heat) in pixels.iter_mut().zip(self.heat_buffer.iter()) {
+            // Sin calor queda transparente para ver las capas de abajo
+            *pixel = if *heat < 1.0 / 255.0 {
+                Color::TRANSPARENT
+            } else {
+                *self.palette.get((heat * 254.0) as usize).expect("Color not found in pallete")
+            };
         }
     }
 }
@@ -88,224 +191,618 @@ impl Convolution for HeatFireConvolution {
         "HeatFire"
     }
 
-    fn transform(&mut self, pixels: &mut [u32], width: usize, height: usize) {
+    fn transform(&mut self, pixels: &mut [u32], width: usize, height: usize, _ctx: &FrameContext) {
+        if (width, height) != (self.width, self.height) {
+            self.resize(width, height);
+        }
+
         // Primero actualizamos el buffer de calor
-        self.update_heat_buffer(width, height);
+        self.update_heat_buffer();
 
         // Luego combinamos el calor con el buffer de píxeles
-        self.apply_heat_to_pixels(pixels, width, height);
+        self.apply_heat_to_pixels(pixels);
+    }
+
+    fn resize(&mut self, width: usize, height: usize) {
+        self.heat_buffer = vec![0.0; width * height];
+        self.next_buffer = vec![0.0; width * height];
+        self.cooling_map = Self::gen_cooling_map(width, height);
+        self.offset = 0;
+        self.width = width;
+        self.height = height;
+    }
+
+    fn params(&self) -> Vec<Param> {
+        params::with_values(Self::schema(), [
+            ParamValue::Float(self.base_intensity as f64),
+            ParamValue::Float(self.cooling as f64),
+            ParamValue::Float(self.diffusion as f64),
+        ])
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        let value = params::validate(&Self::schema(), name, &value)?
+            .as_f64()
+            .unwrap_or_default() as f32;
+
+        match name {
+            "base_intensity" => self.base_intensity = value,
+            "cooling" => self.cooling = value,
+            _ => self.diffusion = value,
+        }
+
+        Ok(())
     }
 }
 
 impl ConvolutionAdvanced for HeatFireConvolution {
     fn reset(&mut self) {
-        for pixel in self.heat_buffer.iter_mut() {
-            *pixel = 0f32;
-        }
+        self.heat_buffer.fill(0.0);
+        self.next_buffer.fill(0.0);
+        self.offset = 0;
     }
 }
 
 
-pub struct IdkConvolution {
-    local_buffer: Vec<u32>,
-    palette: Palette,
+/// Highest heat of the Doom fire, last index of the palette
+const MAX_HEAT: u32 = 254;
+
+/// Parameters of the Doom fire, shared by the sequential and the parallel version
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DoomParams {
+    pub wind: f32,  // Empuje lateral de las llamas, negativo hacia la izquierda
+    pub decay: f32, // Enfriamiento al subir, más alto = llamas más bajas
+    pub seed: f32,  // Calor de la línea de semilla en la fila inferior
 }
 
-impl IdkConvolution {
-    pub fn new(width: usize, height: usize) -> Self {
-        IdkConvolution {
-            local_buffer: vec![0; width*height],
-            palette: Self::gen_palette(),
+impl Default for DoomParams {
+    fn default() -> Self {
+        DoomParams {
+            wind: 0.0,
+            decay: 0.5,
+            seed: 1.0,
         }
     }
+}
 
-    fn gen_palette() -> Palette {
-        let mut palette = Palette::new();
-        let colors = &mut palette;
+impl DoomParams {
+    pub fn schema() -> Vec<ParamSpec> {
+        vec![
+            ParamSpec::float("wind", 0.0, -1.0, 1.0, "Side push of the flames, negative to the left"),
+            ParamSpec::float("decay", 0.5, 0.0, 0.95, "Cooling of the heat while it rises, higher makes shorter flames"),
+            ParamSpec::float("seed", 1.0, 0.0, 1.0, "Heat of the seed line at the bottom, 0 puts the fire out"),
+        ]
+    }
 
-        for i in 1..=85u8 {
-            colors.add_color(
-                Color::rgb(i * 3,0,0)
-            );
+    pub fn from_params(params: &ParamSet) -> Self {
+        DoomParams {
+            wind: params.float("wind") as f32,
+            decay: params.float("decay") as f32,
+            seed: params.float("seed") as f32,
         }
+    }
 
-        for i in 1..=85u8 {
-            colors.add_color(
-                Color::rgb(255,i * 3,0)
-            );
-        }
+    pub fn params(&self) -> Vec<Param> {
+        params::with_values(Self::schema(), [
+            ParamValue::Float(self.wind as f64),
+            ParamValue::Float(self.decay as f64),
+            ParamValue::Float(self.seed as f64),
+        ])
+    }
 
-        for i in 1..=85u8 {
-            colors.add_color(
-                Color::rgb(255,255,i*3)
-            );
+    pub fn set(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        let value = params::validate(&Self::schema(), name, &value)?
+            .as_f64()
+            .unwrap_or_default() as f32;
+
+        match name {
+            "wind" => self.wind = value,
+            "decay" => self.decay = value,
+            _ => self.seed = value,
         }
 
-        palette
+        Ok(())
     }
+}
 
-    fn calculation(&mut self, width: usize, height: usize) {
-        let local_buffer = &mut self.local_buffer;
+/// Random number of the pixel `(x, y)` in the step `step`, a hash instead
+/// of a generator so every executor gets the same fire
+fn hash(x: usize, y: usize, step: u32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ step.wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^ (h >> 16)
+}
 
-        let time = chrono::Local::now();
-        let time = time.timestamp_subsec_micros() as f32 / 1_000_000.0;
+/// Number between 0 and 1 from 10 bits of a hash
+fn unit(h: u32, shift: u32) -> f32 {
+    ((h >> shift) & 0x3FF) as f32 / 1024.0
+}
 
-        // Paralelizamos el cálculo de cada píxel usando su índice lineal
-        local_buffer.iter_mut().enumerate().for_each(|(i, pixel)| {
-            let x = (i % width) as f32;
-            let y = (i / width) as f32;
+/// One step of the fire: each pixel takes the heat of a pixel of the row
+/// below, moved by the drift and the wind, and loses a random part of it
+struct Spread<'a> {
+    heat: &'a [u32],
+    fire: DoomParams,
+    step: u32,
+}
+
+impl PixelEffect for Spread<'_> {
+    fn name(&self) -> &str {
+        "Spread"
+    }
 
-            // Parametrización de la onda
-            let speed = 10f32; // Velocidad de la animación de la onda
-            let amplitude = 170f32; // Amplitud máxima (0 a 254)
-            let frequency_x = (5.0 * time).clamp(1f32, 4f32) * speed * std::f32::consts::PI / width as f32;  // Frecuencia en x
-            let frequency_y = (5.0 * time).clamp(1f32, 4f32) * speed * std::f32::consts::PI / height as f32; // Frecuencia en y
+    fn pixel(&self, x: usize, y: usize, width: usize, height: usize) -> u32 {
+        let DoomParams { wind, decay, seed } = self.fire;
 
-            // Movimiento de la onda
-            let wave_x = (x * frequency_x + time * speed).sin(); // Onda senoidal en la dirección x
-            let wave_y = (y * frequency_y + time * speed).sin();
+        // La fila inferior es la línea de semilla
+        if y == height - 1 {
+            return (seed * MAX_HEAT as f32).round() as u32;
+        }
 
-            // Combinación de las ondas en ambas direcciones (x, y)
-            let wave = (wave_x * wave_y) * amplitude;
+        let h = hash(x, y, self.step);
 
-            // Margin
-            let mx = width as f32 * 0.025;
-            let my = height as f32 * 0.025;
+        // Desvío aleatorio de -1 a 1 más el empuje del viento con
+        // probabilidad |wind|, los bordes se unen
+        let mut shift = (h % 3) as isize - 1;
+        if unit(h, 2) < wind.abs() {
+            shift -= wind.signum() as isize;
+        }
+        let sx = (x as isize + shift).rem_euclid(width as isize) as usize;
 
-            if x > mx && x < width as f32 - mx && y > my && y < height as f32 - my {
-                let value = wave.clamp(10f32, 254f32) as u32;
+        // Enfriamiento medio por fila relativo a la altura, así las llamas
+        // llegan a `1 - decay` del frame en cualquier resolución. Sigue una
+        // distribución exponencial, la variación entre píxeles forma las
+        // lenguas de fuego en lugar de un degradado
+        let average = MAX_HEAT as f32 / (height as f32 * (1.0 - decay));
+        let cooling = (-(1.0 - unit(h, 12)).ln() * average + unit(h, 22)) as u32;
 
-                *pixel = value;
-            }
-        });
+        self.heat[(y + 1) * width + sx].saturating_sub(cooling)
     }
 }
 
-impl Convolution for IdkConvolution {
+/// Maps the heat to the palette, without heat it's transparent
+struct Colors<'a> {
+    heat: &'a [u32],
+    palette: &'a Palette,
+}
+
+impl PixelEffect for Colors<'_> {
     fn name(&self) -> &str {
-        "RisingFire"
+        "Colors"
     }
 
-    fn transform(&mut self, pixels: &mut [u32], width: usize, height: usize) {
-        let timer = Instant::now();
-        // self.gen_base(width, height);
-        self.calculation(width, height);
+    fn pixel(&self, x: usize, y: usize, width: usize, _height: usize) -> u32 {
+        match self.heat[y * width + x] {
+            0 => Color::TRANSPARENT,
+            heat => *self.palette.get(heat as usize).expect("Color not found in pallete"),
+        }
+    }
+}
 
-        pixels.iter_mut().enumerate().for_each(|(i,pixel) | {
-            if *pixel != 0 {
-                *pixel = *self.palette.get(self.local_buffer[i] as usize)
-                    .expect("Color not found in pallete");
-            }
-        });
-        println!("{:.4},", timer.elapsed().as_secs_f64());
+/// Classic Doom fire: a seed line at the bottom and a heat map where each
+/// row copies the row below with a random drift and decay. Both passes run
+/// through an [`Executor`], the result doesn't depend on it
+pub struct DoomFire {
+    pub fire: DoomParams,
+    pub executor: Executor,
+    heat: Vec<u32>, // Índice de la paleta de cada píxel, de 0 a MAX_HEAT
+    next: Vec<u32>, // Calor del siguiente paso, se intercambia con `heat`
+    step: u32,      // Paso de la simulación, semilla de los números aleatorios
+    palette: Palette,
+    width: usize,
+    height: usize,
+}
+
+impl DoomFire {
+    pub fn new(fire: DoomParams, executor: Executor, width: usize, height: usize) -> Self {
+        DoomFire {
+            fire,
+            executor,
+            heat: vec![0; width * height],
+            next: vec![0; width * height],
+            step: 0,
+            palette: gen_palette(),
+            width,
+            height,
+        }
     }
 }
 
-impl ConvolutionAdvanced for IdkConvolution {
+impl Convolution for DoomFire {
+    fn name(&self) -> &str {
+        "DoomFire"
+    }
+
+    fn transform(&mut self, pixels: &mut [u32], width: usize, height: usize, _ctx: &FrameContext) {
+        if (width, height) != (self.width, self.height) {
+            self.resize(width, height);
+        }
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        let spread = Spread { heat: &self.heat, fire: self.fire, step: self.step };
+        self.executor.run(&spread, &mut self.next, width, height);
+        std::mem::swap(&mut self.heat, &mut self.next);
+        self.step = self.step.wrapping_add(1);
+
+        let colors = Colors { heat: &self.heat, palette: &self.palette };
+        self.executor.run(&colors, pixels, width, height);
+    }
+
+    fn resize(&mut self, width: usize, height: usize) {
+        self.heat = vec![0; width * height];
+        self.next = vec![0; width * height];
+        self.width = width;
+        self.height = height;
+    }
+
+    fn params(&self) -> Vec<Param> {
+        let mut params = self.fire.params();
+        params.push(Param::new(Executor::spec(self.executor), ParamValue::Text(self.executor.name().to_string())));
+        params
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        if name != "executor" {
+            return self.fire.set(name, value);
+        }
+
+        let value = params::validate(&[Executor::spec(self.executor)], name, &value)?;
+        self.executor = value.as_str().unwrap_or_default().parse()?;
+        Ok(())
+    }
+}
+
+impl ConvolutionAdvanced for DoomFire {
     fn reset(&mut self) {
-        self.local_buffer = vec![0; self.local_buffer.len()];
+        self.heat.fill(0);
+        self.next.fill(0);
+        self.step = 0;
     }
 }
 
-pub struct IdkParConvolution {
-    local_buffer: Vec<u32>,
+
+/// Parameters of the fire waves, shared by the sequential and the parallel version
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WaveParams {
+    pub speed: f32, // Velocidad de la animación de la onda
+    pub amplitude: f32, // Amplitud máxima (0 a 254)
+    pub frequency: f32, // Crecimiento de la frecuencia durante cada segundo
+    pub margin: f32, // Margen relativo al tamaño del frame
+    pub rate: f32, // Velocidad del tiempo de la animación, negativa la reproduce hacia atrás
+    pub paused: bool, // Detiene el tiempo de la animación
+}
+
+impl Default for WaveParams {
+    fn default() -> Self {
+        WaveParams {
+            speed: 10.0,
+            amplitude: 170.0,
+            frequency: 5.0,
+            margin: 0.025,
+            rate: 1.0,
+            paused: false,
+        }
+    }
+}
+
+impl WaveParams {
+    pub fn schema() -> Vec<ParamSpec> {
+        vec![
+            ParamSpec::float("speed", 10.0, 0.0, 40.0, "Speed of the animation of the waves"),
+            ParamSpec::float("amplitude", 170.0, 10.0, 254.0, "Maximum value of the waves in the palette"),
+            ParamSpec::float("frequency", 5.0, 0.0, 20.0, "Growth of the frequency during each second, it swings between 1x and 4x"),
+            ParamSpec::float("margin", 0.025, 0.0, 0.25, "Margin without fire, relative to the frame size"),
+            ParamSpec::float("rate", 1.0, -4.0, 4.0, "Speed of the animation time, negative plays it backwards"),
+            ParamSpec::bool("paused", false, "Stops the animation time"),
+        ]
+    }
+
+    pub fn from_params(params: &ParamSet) -> Self {
+        WaveParams {
+            speed: params.float("speed") as f32,
+            amplitude: params.float("amplitude") as f32,
+            frequency: params.float("frequency") as f32,
+            margin: params.float("margin") as f32,
+            rate: params.float("rate") as f32,
+            paused: params.bool("paused"),
+        }
+    }
+
+    pub fn params(&self) -> Vec<Param> {
+        params::with_values(Self::schema(), [
+            ParamValue::Float(self.speed as f64),
+            ParamValue::Float(self.amplitude as f64),
+            ParamValue::Float(self.frequency as f64),
+            ParamValue::Float(self.margin as f64),
+            ParamValue::Float(self.rate as f64),
+            ParamValue::Bool(self.paused),
+        ])
+    }
+
+    pub fn set(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        let value = params::validate(&Self::schema(), name, &value)?;
+
+        if name == "paused" {
+            self.paused = value.as_bool().unwrap_or_default();
+            return Ok(());
+        }
+
+        let value = value.as_f64().unwrap_or_default() as f32;
+        match name {
+            "speed" => self.speed = value,
+            "amplitude" => self.amplitude = value,
+            "frequency" => self.frequency = value,
+            "margin" => self.margin = value,
+            _ => self.rate = value,
+        }
+
+        Ok(())
+    }
+}
+
+/// Waves of fire, product of two sine waves mapped to the fire palette.
+/// It's written once as a [`PixelEffect`], the sequential and parallel
+/// versions only change the [`Executor`](crate::convolutions::effect::Executor)
+pub struct WaveFire {
     palette: Palette,
+    pub wave: WaveParams,
+    time: f64,              // Tiempo de la animación, avanza de forma continua según `rate`
+    last: Option<Duration>, // Tiempo de las animaciones en el último frame dibujado
+    phase: f32,             // Desplazamiento de las ondas en el frame actual
+    growth: f32,            // Factor de la frecuencia en el frame actual, de 1 a 4
 }
 
-impl IdkParConvolution {
-    pub fn new(width: usize, height: usize) -> Self {
-        IdkParConvolution {
-            local_buffer: vec![0; width*height],
-            palette: Self::gen_palette(),
+impl WaveFire {
+    pub fn new() -> Self {
+        WaveFire {
+            palette: gen_palette(),
+            wave: WaveParams::default(),
+            time: 0.0,
+            last: None,
+            phase: 0.0,
+            growth: 1.0,
         }
     }
+}
+
+impl Default for WaveFire {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-    fn gen_palette() -> Palette {
-        let mut palette = Palette::new();
-        let colors = &mut palette;
+/// Palette of 255 colors from black to red, yellow and white
+pub fn gen_palette() -> Palette {
+    let mut palette = Palette::new();
+    let colors = &mut palette;
 
-        let red_colors: Vec<u32> = (1..=85u8)
-            .into_par_iter()
-            .map(|i| Color::rgb(i * 3, 0, 0)) // Obtener el valor RGB
-            .collect();
+    for i in 1..=85u8 {
+        colors.add_color(
+            Color::rgb(i * 3,0,0)
+        );
+    }
 
-        let green_colors: Vec<u32> = (1..=85u8)
-            .into_par_iter()
-            .map(|i| Color::rgb(255, i * 3, 0)) // Obtener el valor RGB
-            .collect();
+    for i in 1..=85u8 {
+        colors.add_color(
+            Color::rgb(255,i * 3,0)
+        );
+    }
 
-        let blue_colors: Vec<u32> = (1..=85u8)
-            .into_par_iter()
-            .map(|i| Color::rgb(255, 255, i * 3)) // Obtener el valor RGB
-            .collect();
+    for i in 1..=85u8 {
+        colors.add_color(
+            Color::rgb(255,255,i*3)
+        );
+    }
 
-        colors.add_colors(red_colors);
-        colors.add_colors(green_colors);
-        colors.add_colors(blue_colors);
+    palette
+}
 
-        palette
+impl PixelEffect for WaveFire {
+    fn name(&self) -> &str {
+        "WaveFire"
     }
 
+    fn prepare(&mut self, _width: usize, _height: usize, ctx: &FrameContext) {
+        // El tiempo avanza lo que pasó desde el último frame dibujado, así
+        // no salta aunque la capa se dibuje cada varios frames
+        let delta = self.last.map_or(0.0, |last| ctx.animation.saturating_sub(last).as_secs_f64());
+        self.last = Some(ctx.animation);
 
-    fn calculation(&mut self, width: usize, height: usize) {
-        let local_buffer = &mut self.local_buffer;
+        if !self.wave.paused {
+            self.time += delta * self.wave.rate as f64;
+        }
 
-        let time = chrono::Local::now();
-        let time = time.timestamp_subsec_micros() as f32 / 1_000_000.0;
+        // Los productos con el tiempo se reducen a un periodo en f64 para
+        // no perder precisión en f32 después de horas de animación
+        let WaveParams { speed, frequency, .. } = self.wave;
+        self.phase = (self.time * speed as f64).rem_euclid(std::f64::consts::TAU) as f32;
 
-        // Paralelizamos el cálculo de cada píxel usando su índice lineal
-        local_buffer.par_iter_mut().enumerate().for_each(|(i, pixel)| {
-            let x = (i % width) as f32;
-            let y = (i / width) as f32;
+        // La frecuencia oscila suavemente entre 1x y 4x, su crecimiento
+        // máximo por segundo es `frequency`
+        let swing = (self.time * frequency as f64 * 2.0 / 3.0).rem_euclid(std::f64::consts::TAU) as f32;
+        self.growth = 2.5 - 1.5 * swing.cos();
+    }
 
-            // Parametrización de la onda
-            let speed = 10f32; // Velocidad de la animación de la onda
-            let amplitude = 170f32; // Amplitud máxima (0 a 254)
-            let frequency_x = (5.0 * time).clamp(1f32, 4f32) * speed * std::f32::consts::PI / width as f32;  // Frecuencia en x
-            let frequency_y = (5.0 * time).clamp(1f32, 4f32) * speed * std::f32::consts::PI / height as f32; // Frecuencia en y
+    fn pixel(&self, x: usize, y: usize, width: usize, height: usize) -> u32 {
+        let WaveParams { speed, amplitude, margin, .. } = self.wave;
+        let (x, y) = (x as f32, y as f32);
 
-            // Movimiento de la onda
-            let wave_x = (x * frequency_x + time * speed).sin(); // Onda senoidal en la dirección x
-            let wave_y = (y * frequency_y + time * speed).sin();
+        // Margin, queda transparente
+        let mx = width as f32 * margin;
+        let my = height as f32 * margin;
 
-            // Combinación de las ondas en ambas direcciones (x, y)
-            let wave = (wave_x * wave_y) * amplitude;
+        if !(x > mx && x < width as f32 - mx && y > my && y < height as f32 - my) {
+            return Color::TRANSPARENT;
+        }
 
-            // Margin
-            let mx = width as f32 * 0.025;
-            let my = height as f32 * 0.025;
+        // Parametrización de la onda
+        let frequency_x = self.growth * speed * std::f32::consts::PI / width as f32;  // Frecuencia en x
+        let frequency_y = self.growth * speed * std::f32::consts::PI / height as f32; // Frecuencia en y
 
-            if x > mx && x < width as f32 - mx && y > my && y < height as f32 - my {
-                let value = wave.clamp(10f32, 254f32) as u32;
+        // Movimiento de la onda
+        let wave_x = (x * frequency_x + self.phase).sin(); // Onda senoidal en la dirección x
+        let wave_y = (y * frequency_y + self.phase).sin();
 
-                *pixel = value;
-            }
-        });
+        // Combinación de las ondas en ambas direcciones (x, y)
+        let wave = (wave_x * wave_y) * amplitude;
+        let value = wave.clamp(10f32, 254f32) as usize;
+
+        *self.palette.get(value).expect("Color not found in pallete")
+    }
+
+    fn params(&self) -> Vec<Param> {
+        self.wave.params()
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        self.wave.set(name, value)
+    }
+
+    fn reset(&mut self) {
+        self.time = 0.0;
+        self.last = None;
     }
 }
 
-impl Convolution for IdkParConvolution {
-    fn name(&self) -> &str {
-        "RisingFire"
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convolutions::effect::tests::assert_executors_agree;
+    use crate::convolutions::effect::EffectConvolution;
+
+    fn mean(values: &[f32]) -> f32 {
+        values.iter().sum::<f32>() / values.len() as f32
+    }
+
+    /// Runs `steps` steps of a Doom fire, returns its last frame
+    fn doom_frames(fire: &mut DoomFire, steps: usize) -> Vec<u32> {
+        let (width, height) = (fire.width, fire.height);
+        let ctx = FrameContext::new(chrono::Local::now());
+        let mut pixels = vec![0; width * height];
+
+        for _ in 0..steps {
+            fire.transform(&mut pixels, width, height, &ctx);
+        }
+
+        pixels
+    }
+
+    #[test]
+    fn every_executor_gives_the_same_doom_fire() {
+        let fire = DoomParams { wind: 0.4, decay: 0.3, seed: 0.9 };
+        assert_executors_agree(37, 29, 50, |executor| DoomFire::new(fire, executor, 37, 29));
+    }
+
+    #[test]
+    fn doom_fire_goes_dark_without_seed() {
+        let (width, height) = (24, 16);
+        let mut fire = DoomFire::new(DoomParams::default(), Executor::RayonRows, width, height);
+        assert!(doom_frames(&mut fire, height).iter().any(|&pixel| pixel != Color::TRANSPARENT));
+
+        // Sin semilla el calor se apaga como mucho en la altura del frame
+        fire.set_param("seed", ParamValue::Float(0.0)).expect("Seed not set");
+        assert!(doom_frames(&mut fire, height).iter().all(|&pixel| pixel == Color::TRANSPARENT));
+        assert!(fire.heat.iter().all(|&heat| heat == 0));
     }
 
-    fn transform(&mut self, pixels: &mut [u32], width: usize, height: usize) {
-        let timer = Instant::now();
-        // self.gen_base(width, height);
-        self.calculation(width, height);
+    #[test]
+    fn heat_stays_in_range_and_settles() {
+        let (width, height) = (32, 24);
+
+        for (base_intensity, cooling, diffusion) in [(1.0, 0.05, 1.0), (1.0, 0.05, 0.0), (0.01, 1.0, 1.0), (1.0, 1.0, 0.0), (0.5, 0.3, 0.6)] {
+            let mut fire = HeatFireConvolution::new(base_intensity, cooling, diffusion, width, height);
+            let mut averages = Vec::new();
 
-        pixels.par_iter_mut().enumerate().for_each(|(i, pixel)| {
-            if *pixel != 0 {
-                *pixel = *self.palette.get(self.local_buffer[i] as usize)
-                    .expect("Color not found in pallete");
+            for _ in 0..600 {
+                fire.update_heat_buffer();
+                assert!(fire.heat_buffer.iter().all(|heat| (0.0..=1.0).contains(heat)), "heat out of range with {:?}", (base_intensity, cooling, diffusion));
+                averages.push(mean(&fire.heat_buffer));
             }
-        });
-        println!("{:.4},", timer.elapsed().as_secs_f64());
+
+            // Pasado el arranque el calor medio no crece ni se apaga
+            let (early, late) = (mean(&averages[200..400]), mean(&averages[400..]));
+            assert!((early - late).abs() <= 0.05 * early.max(0.01), "the heat drifts from {} to {} with {:?}", early, late, (base_intensity, cooling, diffusion));
+            assert!(late > 0.0);
+        }
     }
-}
 
-impl ConvolutionAdvanced for IdkParConvolution {
-    fn reset(&mut self) {
-        self.local_buffer = vec![0; self.local_buffer.len()];
+    #[test]
+    fn every_executor_gives_the_same_wave_fire() {
+        assert_executors_agree(53, 37, 5, |executor| EffectConvolution::new(WaveFire::new(), executor));
+    }
+
+    /// Prepares `wave` for a frame at `animation` seconds of the app
+    fn wave_at(wave: &mut WaveFire, ctx: &mut FrameContext, animation: f64) {
+        ctx.animation = Duration::from_secs_f64(animation);
+        wave.prepare(8, 8, ctx);
+    }
+
+    #[test]
+    fn wave_time_is_continuous() {
+        let mut wave = WaveFire::new();
+        let mut ctx = FrameContext::new(chrono::Local::now());
+
+        // Sin saltos al pasar de un segundo al siguiente, a diferencia del
+        // tiempo anterior que volvía a 0 con cada segundo del reloj
+        let mut previous = (wave.phase, wave.growth);
+        for frame in 0..=120 {
+            wave_at(&mut wave, &mut ctx, 0.9 + frame as f64 / 600.0);
+            let phase = (wave.phase - previous.0).rem_euclid(std::f32::consts::TAU);
+            assert!(phase.min(std::f32::consts::TAU - phase) < 0.2, "the phase jumps on frame {}", frame);
+            assert!((wave.growth - previous.1).abs() < 0.1, "the frequency jumps on frame {}", frame);
+            assert!((1.0..=4.0).contains(&wave.growth));
+            previous = (wave.phase, wave.growth);
+        }
+
+        // Empieza a contar desde el primer frame dibujado
+        assert!((wave.time - 0.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn wave_rate_scales_the_time() {
+        let mut wave = WaveFire::new();
+        let mut ctx = FrameContext::new(chrono::Local::now());
+
+        wave_at(&mut wave, &mut ctx, 1.0);
+        wave.set_param("rate", ParamValue::Float(2.0)).expect("Rate not set");
+        wave_at(&mut wave, &mut ctx, 2.0);
+        assert!((wave.time - 2.0).abs() < 1e-9);
+
+        // Negativa la animación va hacia atrás desde donde estaba
+        wave.set_param("rate", ParamValue::Float(-0.5)).expect("Rate not set");
+        wave_at(&mut wave, &mut ctx, 4.0);
+        assert!((wave.time - 1.0).abs() < 1e-9);
+
+        assert!(wave.set_param("rate", ParamValue::Float(5.0)).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn paused_waves_keep_their_time() {
+        let mut wave = WaveFire::new();
+        let mut ctx = FrameContext::new(chrono::Local::now());
+
+        wave_at(&mut wave, &mut ctx, 0.0);
+        wave_at(&mut wave, &mut ctx, 1.5);
+        wave.set_param("paused", ParamValue::Bool(true)).expect("Paused not set");
+        let phase = wave.phase;
+        wave_at(&mut wave, &mut ctx, 3.0);
+        assert_eq!((wave.time, wave.phase), (1.5, phase));
+
+        // Al reanudar no recupera el tiempo pausado
+        wave.set_param("paused", ParamValue::Bool(false)).expect("Paused not set");
+        wave_at(&mut wave, &mut ctx, 3.5);
+        assert!((wave.time - 2.0).abs() < 1e-9);
+
+        // Reiniciar vuelve a empezar desde el siguiente frame
+        wave.reset();
+        wave_at(&mut wave, &mut ctx, 10.0);
+        assert_eq!(wave.time, 0.0);
+    }
+}
@@ -0,0 +1,527 @@
+use std::str::FromStr;
+use crate::convolutions::effect::Executor;
+use crate::convolutions::params::{self, Param, ParamSet, ParamSpec, ParamValue};
+use crate::convolutions::{Color, Convolution, Damage, FrameContext};
+
+/// Largest side of a kernel, the blur radius goes up to `MAX_SIZE / 2`
+pub const MAX_SIZE: usize = 31;
+
+/// Kernel of the layer, `Custom` takes the weights of the `matrix` parameter
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KernelKind {
+    BoxBlur,
+    #[default]
+    GaussianBlur,
+    Sharpen,
+    /// Magnitude of the horizontal and vertical Sobel gradients
+    Sobel,
+    Emboss,
+    Custom,
+}
+
+impl KernelKind {
+    pub const ALL: [KernelKind; 6] = [
+        KernelKind::BoxBlur,
+        KernelKind::GaussianBlur,
+        KernelKind::Sharpen,
+        KernelKind::Sobel,
+        KernelKind::Emboss,
+        KernelKind::Custom,
+    ];
+
+    pub const NAMES: [&'static str; 6] = ["box_blur", "gaussian_blur", "sharpen", "sobel", "emboss", "custom"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KernelKind::BoxBlur => Self::NAMES[0],
+            KernelKind::GaussianBlur => Self::NAMES[1],
+            KernelKind::Sharpen => Self::NAMES[2],
+            KernelKind::Sobel => Self::NAMES[3],
+            KernelKind::Emboss => Self::NAMES[4],
+            KernelKind::Custom => Self::NAMES[5],
+        }
+    }
+}
+
+impl FromStr for KernelKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        KernelKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| format!("unknown kernel {:?} (expected one of {:?})", s, KernelKind::NAMES))
+    }
+}
+
+/// Pixels read by the kernel outside of the frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EdgeMode {
+    /// The nearest pixel of the border
+    #[default]
+    Clamp,
+    /// The pixel of the opposite side, the frame is tiled
+    Wrap,
+    /// The frame reflected on the border, without repeating it
+    Mirror,
+    /// Black
+    Zero,
+}
+
+impl EdgeMode {
+    pub const ALL: [EdgeMode; 4] = [EdgeMode::Clamp, EdgeMode::Wrap, EdgeMode::Mirror, EdgeMode::Zero];
+
+    pub const NAMES: [&'static str; 4] = ["clamp", "wrap", "mirror", "zero"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgeMode::Clamp => Self::NAMES[0],
+            EdgeMode::Wrap => Self::NAMES[1],
+            EdgeMode::Mirror => Self::NAMES[2],
+            EdgeMode::Zero => Self::NAMES[3],
+        }
+    }
+
+    /// Index inside `0..size` read for the coordinate `i`, `None` if the
+    /// pixel is black
+    pub fn index(&self, i: isize, size: usize) -> Option<usize> {
+        let last = size as isize - 1;
+
+        if (0..=last).contains(&i) {
+            return Some(i as usize);
+        }
+
+        match self {
+            EdgeMode::Clamp => Some(i.clamp(0, last) as usize),
+            EdgeMode::Wrap => Some(i.rem_euclid(size as isize) as usize),
+            EdgeMode::Mirror if last == 0 => Some(0),
+            EdgeMode::Mirror => {
+                let period = 2 * last;
+                let i = i.rem_euclid(period);
+                Some(if i > last { period - i } else { i } as usize)
+            }
+            EdgeMode::Zero => None,
+        }
+    }
+}
+
+impl FromStr for EdgeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        EdgeMode::ALL
+            .into_iter()
+            .find(|mode| mode.name() == s)
+            .ok_or_else(|| format!("unknown edge mode {:?} (expected one of {:?})", s, EdgeMode::NAMES))
+    }
+}
+
+/// Weights of a 2D convolution
+#[derive(Debug, Clone, PartialEq)]
+pub enum Kernel {
+    /// Matrix of `size` x `size` weights by rows, `size` is odd
+    Matrix { size: usize, weights: Vec<f32> },
+    /// The same weights applied to the rows and then to the columns, it's
+    /// the matrix product of the weights with themselves in two passes
+    Separable { weights: Vec<f32> },
+    /// Magnitude of the response to two matrices, e.g. the gradient
+    Gradient { size: usize, x: Vec<f32>, y: Vec<f32> },
+}
+
+impl Kernel {
+    /// Average of the `2 * radius + 1` pixels of each side
+    pub fn box_blur(radius: usize) -> Kernel {
+        let size = 2 * radius + 1;
+        Kernel::Separable { weights: vec![1.0 / size as f32; size] }
+    }
+
+    /// Gaussian of sigma `radius / 2`, normalized
+    pub fn gaussian_blur(radius: usize) -> Kernel {
+        let sigma = radius as f32 / 2.0;
+        let weights: Vec<f32> = (-(radius as isize)..=radius as isize)
+            .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+            .collect();
+        let sum: f32 = weights.iter().sum();
+
+        Kernel::Separable { weights: weights.iter().map(|weight| weight / sum).collect() }
+    }
+
+    pub fn sharpen() -> Kernel {
+        Kernel::Matrix { size: 3, weights: vec![0.0, -1.0, 0.0, -1.0, 5.0, -1.0, 0.0, -1.0, 0.0] }
+    }
+
+    pub fn sobel() -> Kernel {
+        Kernel::Gradient {
+            size: 3,
+            x: vec![-1.0, 0.0, 1.0, -2.0, 0.0, 2.0, -1.0, 0.0, 1.0],
+            y: vec![-1.0, -2.0, -1.0, 0.0, 0.0, 0.0, 1.0, 2.0, 1.0],
+        }
+    }
+
+    pub fn emboss() -> Kernel {
+        Kernel::Matrix { size: 3, weights: vec![-2.0, -1.0, 0.0, -1.0, 1.0, 1.0, 0.0, 1.0, 2.0] }
+    }
+
+    /// Kernel from the rows of a matrix already validated: a single row is
+    /// separable, with `normalize` the weights are divided by their sum
+    pub fn custom(rows: &[Vec<f64>], normalize: bool) -> Kernel {
+        let mut weights: Vec<f32> = rows.iter().flatten().map(|weight| *weight as f32).collect();
+        let sum: f32 = weights.iter().sum();
+
+        if normalize && sum.abs() > f32::EPSILON {
+            weights.iter_mut().for_each(|weight| *weight /= sum);
+        }
+
+        match rows.len() {
+            1 => Kernel::Separable { weights },
+            size => Kernel::Matrix { size, weights },
+        }
+    }
+
+    fn from_params(kind: KernelKind, radius: usize, matrix: &[Vec<f64>], normalize: bool) -> Kernel {
+        match kind {
+            KernelKind::BoxBlur => Kernel::box_blur(radius),
+            KernelKind::GaussianBlur => Kernel::gaussian_blur(radius),
+            KernelKind::Sharpen => Kernel::sharpen(),
+            KernelKind::Sobel => Kernel::sobel(),
+            KernelKind::Emboss => Kernel::emboss(),
+            KernelKind::Custom => Kernel::custom(matrix, normalize),
+        }
+    }
+
+    /// Convolves the RGB channels of `src` into `dst`, the alpha of each
+    /// pixel is kept. `temp` keeps the first pass of the separable kernels
+    pub fn apply(
+        &self,
+        src: &[u32],
+        dst: &mut [u32],
+        temp: &mut Vec<[f32; 3]>,
+        (width, height): (usize, usize),
+        edges: EdgeMode,
+        executor: Executor,
+    ) {
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        let sample = |x: isize, y: isize| -> [f32; 3] {
+            match (edges.index(x, width), edges.index(y, height)) {
+                (Some(x), Some(y)) => channels(src[y * width + x]),
+                _ => [0.0; 3],
+            }
+        };
+
+        match self {
+            Kernel::Matrix { size, weights } => {
+                executor.run_rows(dst, width, |y, line| {
+                    for (x, pixel) in line.iter_mut().enumerate() {
+                        let value = convolve(*size, weights, |dx, dy| sample(x as isize + dx, y as isize + dy));
+                        *pixel = pack(value, src[y * width + x]);
+                    }
+                });
+            }
+            Kernel::Separable { weights } => {
+                let radius = (weights.len() / 2) as isize;
+
+                // Primera pasada: las filas, sin redondear
+                temp.clear();
+                temp.resize(width * height, [0.0; 3]);
+                executor.run_rows(temp, width, |y, line| {
+                    for (x, value) in line.iter_mut().enumerate() {
+                        for (i, weight) in weights.iter().enumerate() {
+                            let color = sample(x as isize + i as isize - radius, y as isize);
+                            (0..3).for_each(|c| value[c] += color[c] * weight);
+                        }
+                    }
+                });
+
+                // Segunda pasada: las columnas de la primera
+                let temp = &*temp;
+                executor.run_rows(dst, width, |y, line| {
+                    for (x, pixel) in line.iter_mut().enumerate() {
+                        let mut value = [0.0; 3];
+                        for (i, weight) in weights.iter().enumerate() {
+                            if let Some(sy) = edges.index(y as isize + i as isize - radius, height) {
+                                let color = temp[sy * width + x];
+                                (0..3).for_each(|c| value[c] += color[c] * weight);
+                            }
+                        }
+                        *pixel = pack(value, src[y * width + x]);
+                    }
+                });
+            }
+            Kernel::Gradient { size, x: kernel_x, y: kernel_y } => {
+                executor.run_rows(dst, width, |y, line| {
+                    for (x, pixel) in line.iter_mut().enumerate() {
+                        let at = |dx: isize, dy: isize| sample(x as isize + dx, y as isize + dy);
+                        let gx = convolve(*size, kernel_x, at);
+                        let gy = convolve(*size, kernel_y, at);
+                        let value = [0, 1, 2].map(|c| (gx[c] * gx[c] + gy[c] * gy[c]).sqrt());
+                        *pixel = pack(value, src[y * width + x]);
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// RGB channels of a pixel
+fn channels(color: u32) -> [f32; 3] {
+    [(color >> 16) & 0xFF, (color >> 8) & 0xFF, color & 0xFF].map(|channel| channel as f32)
+}
+
+/// Pixel with the rounded RGB channels and the alpha of `original`
+fn pack(value: [f32; 3], original: u32) -> u32 {
+    let [red, green, blue] = value.map(|channel| channel.round().clamp(0.0, 255.0) as u8);
+    Color::rgba(red, green, blue, Color::alpha(original))
+}
+
+/// Sum of the weights of a `size` x `size` matrix by the pixels around the
+/// center, `at(dx, dy)` reads the pixel at that offset
+fn convolve(size: usize, weights: &[f32], at: impl Fn(isize, isize) -> [f32; 3]) -> [f32; 3] {
+    let radius = (size / 2) as isize;
+    let mut value = [0.0; 3];
+
+    for (i, weight) in weights.iter().enumerate() {
+        if *weight == 0.0 {
+            continue;
+        }
+
+        let color = at((i % size) as isize - radius, (i / size) as isize - radius);
+        (0..3).for_each(|c| value[c] += color[c] * weight);
+    }
+
+    value
+}
+
+/// Post-process layer: convolves the frame composited below it with a
+/// kernel (blur, sharpen, edges, emboss or a custom matrix)
+pub struct KernelConvolution {
+    pub kind: KernelKind,
+    pub radius: usize,     // Radio de los desenfoques
+    pub edges: EdgeMode,
+    pub matrix: Vec<Vec<f64>>, // Pesos del kernel `custom`
+    pub normalize: bool,   // Divide los pesos del kernel `custom` por su suma
+    pub executor: Executor,
+    kernel: Kernel,
+    source: Vec<u32>,      // Copia del frame de entrada, la salida se escribe encima
+    temp: Vec<[f32; 3]>,   // Primera pasada de los kernels separables
+}
+
+impl KernelConvolution {
+    pub fn new(kind: KernelKind, radius: usize, edges: EdgeMode, matrix: Vec<Vec<f64>>, normalize: bool, executor: Executor) -> Self {
+        KernelConvolution {
+            kernel: Kernel::from_params(kind, radius, &matrix, normalize),
+            kind,
+            radius,
+            edges,
+            matrix,
+            normalize,
+            executor,
+            source: Vec::new(),
+            temp: Vec::new(),
+        }
+    }
+
+    pub fn from_params(params: &ParamSet) -> Self {
+        Self::new(
+            params.text("kernel").parse().expect("Kernel validated by the schema"),
+            params.int("radius") as usize,
+            params.text("edges").parse().expect("Edge mode validated by the schema"),
+            params.matrix("matrix").to_vec(),
+            params.bool("normalize"),
+            params.text("executor").parse().expect("Executor validated by the schema"),
+        )
+    }
+
+    pub fn schema(executor: Executor) -> Vec<ParamSpec> {
+        vec![
+            ParamSpec::choice("kernel", "gaussian_blur", &KernelKind::NAMES, "Kernel applied to the frame below"),
+            ParamSpec::int("radius", 2, 1, (MAX_SIZE / 2) as i64, "Radius of the blurs"),
+            ParamSpec::choice("edges", "clamp", &EdgeMode::NAMES, "Pixels read outside of the frame"),
+            ParamSpec::kernel("matrix", &[&[0.0, -1.0, 0.0], &[-1.0, 5.0, -1.0], &[0.0, -1.0, 0.0]], MAX_SIZE,
+                "Weights of the custom kernel, a single row is applied to the rows and the columns"),
+            ParamSpec::bool("normalize", true, "Divides the weights of the custom kernel by their sum"),
+            Executor::spec(executor),
+        ]
+    }
+}
+
+impl Convolution for KernelConvolution {
+    fn name(&self) -> &str {
+        "Kernel"
+    }
+
+    fn transform(&mut self, pixels: &mut [u32], width: usize, height: usize, _ctx: &FrameContext) {
+        self.source.clear();
+        self.source.extend_from_slice(pixels);
+        self.kernel.apply(&self.source, pixels, &mut self.temp, (width, height), self.edges, self.executor);
+    }
+
+    fn reads_frame(&self) -> bool {
+        true
+    }
+
+    // La salida solo cambia con el frame de abajo, si otra capa cambia el
+    // frame se dibuja completo y esta capa con él
+    fn damage(&self, _width: usize, _height: usize, _ctx: &FrameContext) -> Damage {
+        Damage::None
+    }
+
+    fn params(&self) -> Vec<Param> {
+        params::with_values(Self::schema(self.executor), [
+            ParamValue::Text(self.kind.name().to_string()),
+            ParamValue::Int(self.radius as i64),
+            ParamValue::Text(self.edges.name().to_string()),
+            ParamValue::Matrix(self.matrix.clone()),
+            ParamValue::Bool(self.normalize),
+            ParamValue::Text(self.executor.name().to_string()),
+        ])
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        let value = params::validate(&Self::schema(self.executor), name, &value)?;
+
+        match name {
+            "kernel" => self.kind = value.as_str().unwrap_or_default().parse()?,
+            "radius" => self.radius = value.as_i64().unwrap_or_default() as usize,
+            "edges" => self.edges = value.as_str().unwrap_or_default().parse()?,
+            "matrix" => self.matrix = value.as_matrix().unwrap_or_default().to_vec(),
+            "normalize" => self.normalize = value.as_bool().unwrap_or_default(),
+            _ => self.executor = value.as_str().unwrap_or_default().parse()?,
+        }
+
+        self.kernel = Kernel::from_params(self.kind, self.radius, &self.matrix, self.normalize);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frame of gray pixels with the given values, opaque
+    fn gray(values: &[u8]) -> Vec<u32> {
+        values.iter().map(|&v| Color::rgb(v, v, v)).collect()
+    }
+
+    /// Red channel of each pixel
+    fn red(pixels: &[u32]) -> Vec<u8> {
+        pixels.iter().map(|pixel| (pixel >> 16) as u8).collect()
+    }
+
+    fn apply(kernel: &Kernel, src: &[u32], size: (usize, usize), edges: EdgeMode, executor: Executor) -> Vec<u32> {
+        let mut dst = vec![0; src.len()];
+        kernel.apply(src, &mut dst, &mut Vec::new(), size, edges, executor);
+        dst
+    }
+
+    #[test]
+    fn edge_modes() {
+        let clamp: Vec<_> = (-3..8).map(|i| EdgeMode::Clamp.index(i, 5)).collect();
+        let wrap: Vec<_> = (-3..8).map(|i| EdgeMode::Wrap.index(i, 5)).collect();
+        let mirror: Vec<_> = (-3..8).map(|i| EdgeMode::Mirror.index(i, 5)).collect();
+
+        assert_eq!(clamp, [0, 0, 0, 0, 1, 2, 3, 4, 4, 4, 4].map(Some));
+        assert_eq!(wrap, [2, 3, 4, 0, 1, 2, 3, 4, 0, 1, 2].map(Some));
+        assert_eq!(mirror, [3, 2, 1, 0, 1, 2, 3, 4, 3, 2, 1].map(Some));
+        assert_eq!(EdgeMode::Zero.index(-1, 5), None);
+        assert_eq!(EdgeMode::Zero.index(4, 5), Some(4));
+        assert_eq!(EdgeMode::Mirror.index(-2, 1), Some(0));
+    }
+
+    #[test]
+    fn box_blur_by_hand() {
+        // Fila de 5 píxeles, radio 1: (a + b + c) / 3 con cada modo de borde
+        //   clamp:  (0+0+30)/3=10  (0+30+60)/3=30  60  90  (120+120+90)/3=110
+        //   wrap:   (120+0+30)/3=50 ... (90+120+0)/3=70
+        //   mirror: (30+0+30)/3=20 ... (90+120+90)/3=100
+        //   zero:   (0+0+30)/3=10 ... (90+120+0)/3=70
+        let src = gray(&[0, 30, 60, 90, 120]);
+        let kernel = Kernel::box_blur(1);
+        let size = (5, 1);
+
+        // Una sola fila: la pasada vertical también lee fuera del frame
+        assert_eq!(red(&apply(&kernel, &src, size, EdgeMode::Clamp, Executor::Sequential)), [10, 30, 60, 90, 110]);
+        assert_eq!(red(&apply(&kernel, &src, size, EdgeMode::Wrap, Executor::Sequential)), [50, 30, 60, 90, 70]);
+        assert_eq!(red(&apply(&kernel, &src, size, EdgeMode::Mirror, Executor::Sequential)), [20, 30, 60, 90, 100]);
+
+        // Con ceros la pasada vertical suma dos filas negras: un tercio del valor
+        //   (10 30 60 90 70) / 3 = 3.33 10 20 30 23.33
+        assert_eq!(red(&apply(&kernel, &src, size, EdgeMode::Zero, Executor::Sequential)), [3, 10, 20, 30, 23]);
+    }
+
+    #[test]
+    fn sharpen_and_emboss_by_hand() {
+        // 3x3 con el centro en 100 y los vecinos en 20, bordes con clamp:
+        //   (1,1): 5*100 - 4*20 = 420 -> 255
+        //   (0,0): 5*20 - 4*20 = 20, los vecinos de fuera repiten la esquina
+        //   (1,0): 5*20 - 20 (arriba) - 20 - 20 - 100 (abajo) = -60 -> 0
+        let src = gray(&[20, 20, 20, 20, 100, 20, 20, 20, 20]);
+        let sharp = red(&apply(&Kernel::sharpen(), &src, (3, 3), EdgeMode::Clamp, Executor::Sequential));
+        assert_eq!(sharp[4], 255);
+        assert_eq!(sharp[0], 20);
+        assert_eq!(sharp[1], 0);
+
+        // emboss en el centro: la suma de pesos es 1 y los vecinos son
+        // iguales, así que 20 * (1 - 1) + 100 * 1 = 100
+        let embossed = red(&apply(&Kernel::emboss(), &src, (3, 3), EdgeMode::Clamp, Executor::Sequential));
+        assert_eq!(embossed[4], 100);
+        //   (0,0): -2*20 -1*20 +0 -1*20 +1*20 +1*20 +0 +1*20 +2*100 = 180
+        assert_eq!(embossed[0], 180);
+    }
+
+    #[test]
+    fn sobel_by_hand() {
+        // Escalón vertical: columnas 0, 0, 100, 100
+        //   gx en la columna 1: (100 - 0) * (1 + 2 + 1) = 400 -> 255
+        //   gx en la columna 0 con clamp: (0 - 0) * 4 = 0; gy = 0 en todo el frame
+        let src = gray(&[0, 0, 100, 100, 0, 0, 100, 100, 0, 0, 100, 100]);
+        let edges = red(&apply(&Kernel::sobel(), &src, (4, 3), EdgeMode::Clamp, Executor::Sequential));
+        assert_eq!(edges, [0, 255, 255, 0, 0, 255, 255, 0, 0, 255, 255, 0]);
+
+        // Escalón de 10: gx = 40 en las columnas del borde
+        let src = gray(&[0, 0, 10, 10, 0, 0, 10, 10, 0, 0, 10, 10]);
+        let edges = red(&apply(&Kernel::sobel(), &src, (4, 3), EdgeMode::Clamp, Executor::Sequential));
+        assert_eq!(edges, [0, 40, 40, 0, 0, 40, 40, 0, 0, 40, 40, 0]);
+    }
+
+    #[test]
+    fn custom_kernels() {
+        // Fila separable normalizada 1 2 1 / 4 sobre un punto de 160:
+        // la matriz es el producto 1 2 1 x 1 2 1 / 16
+        //   160 * 4/16 = 40 en el centro, 160 * 2/16 = 20 en los lados, 10 en las esquinas
+        let mut src = gray(&[0; 9]);
+        src[4] = Color::rgb(160, 160, 160);
+        let kernel = Kernel::custom(&[vec![1.0, 2.0, 1.0]], true);
+        assert_eq!(red(&apply(&kernel, &src, (3, 3), EdgeMode::Zero, Executor::Sequential)), [10, 20, 10, 20, 40, 20, 10, 20, 10]);
+
+        // Matriz sin normalizar que desplaza el frame un píxel a la izquierda
+        let shift = Kernel::custom(&[vec![0.0, 0.0, 0.0], vec![0.0, 0.0, 1.0], vec![0.0, 0.0, 0.0]], false);
+        let src = gray(&[1, 2, 3, 4, 5, 6]);
+        assert_eq!(red(&apply(&shift, &src, (3, 2), EdgeMode::Wrap, Executor::Sequential)), [2, 3, 1, 5, 6, 4]);
+    }
+
+    #[test]
+    fn alpha_is_kept() {
+        let src = vec![Color::rgba(200, 100, 50, 128); 4];
+        let blurred = apply(&Kernel::gaussian_blur(1), &src, (2, 2), EdgeMode::Clamp, Executor::Sequential);
+        assert!(blurred.iter().all(|&pixel| pixel == Color::rgba(200, 100, 50, 128)));
+    }
+
+    #[test]
+    fn executors_agree() {
+        let (width, height) = (97, 61);
+        let src: Vec<u32> = (0..width * height)
+            .map(|i| Color::rgb((i * 7 % 256) as u8, (i * 13 % 256) as u8, (i / width * 3) as u8))
+            .collect();
+
+        for kernel in [Kernel::gaussian_blur(3), Kernel::sharpen(), Kernel::sobel(), Kernel::emboss()] {
+            let expected = apply(&kernel, &src, (width, height), EdgeMode::Mirror, Executor::Sequential);
+
+            for executor in Executor::ALL {
+                assert!(apply(&kernel, &src, (width, height), EdgeMode::Mirror, executor) == expected, "{:?} with {:?}", kernel, executor);
+            }
+        }
+    }
+}
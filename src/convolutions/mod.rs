@@ -1,15 +1,50 @@
 pub mod base;
+pub mod blend;
+pub mod bloom;
+pub mod context;
+pub mod damage;
+pub mod effect;
 pub mod fire;
+pub mod kernel;
+pub mod noise_field;
+pub mod params;
+pub mod plasma;
+pub mod registry;
+pub mod resample;
 pub mod time;
 
+pub use blend::BlendMode;
+pub use context::{FrameContext, InputState};
+pub use damage::{Damage, Rect};
+pub use params::{Param, ParamSpec, ParamValue};
+pub use resample::{Filter, Resampler};
+
+use std::sync::atomic::AtomicU64;
+use std::time::{Duration, Instant};
+
 /// Color representation for MiniFB,
+/// the highest byte is the alpha of the layer surfaces
+/// (MiniFB ignores it when the frame is displayed)
 pub struct Color;
 
 impl Color {
-    /// Returns u32 representation of the RGB value
+    /// Color that lets the layers below visible
+    pub const TRANSPARENT: u32 = 0;
+
+    /// Returns u32 representation of the opaque RGB value
     pub fn rgb(red: u8, green: u8, blue: u8) -> u32 {
-        let (r, g, b) = (red as u32, green as u32, blue as u32);
-        (r << 16) | (g << 8) | b
+        Self::rgba(red, green, blue, 0xFF)
+    }
+
+    /// Returns u32 representation of the RGBA value
+    pub fn rgba(red: u8, green: u8, blue: u8, alpha: u8) -> u32 {
+        let (r, g, b, a) = (red as u32, green as u32, blue as u32, alpha as u32);
+        (a << 24) | (r << 16) | (g << 8) | b
+    }
+
+    /// Returns the alpha of the color
+    pub fn alpha(color: u32) -> u8 {
+        (color >> 24) as u8
     }
 
     pub fn hsv(hue: f64, saturation: f64, value: f64) -> u32 {
@@ -20,15 +55,55 @@ impl Color {
 
 /// Palette's representation for a
 /// pixel value
-struct Palette {
+#[derive(Debug, Clone, Default)]
+pub struct Palette {
     colors: Vec<u32>,
 }
 
 impl Palette {
+    /// Names of the palettes built by [`Palette::named`]
+    pub const NAMES: [&'static str; 4] = ["fire", "ocean", "aurora", "gray"];
+
     pub fn new() -> Palette {
         Palette { colors: vec![] }
     }
 
+    /// Palette of `size` colors interpolated between the RGB stops
+    pub fn gradient(stops: &[(u8, u8, u8)], size: usize) -> Palette {
+        let mut palette = Palette::new();
+        let segments = stops.len().saturating_sub(1).max(1) as f32;
+
+        for i in 0..size {
+            let t = i as f32 / (size - 1).max(1) as f32 * segments;
+            let index = (t as usize).min(stops.len().saturating_sub(2));
+            let (from, to) = (stops[index], stops[(index + 1).min(stops.len() - 1)]);
+            let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * (t - index as f32)).round() as u8;
+
+            palette.add_color(Color::rgb(mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2)));
+        }
+
+        palette
+    }
+
+    /// Palette of 255 colors by name, one of [`Palette::NAMES`]
+    pub fn named(name: &str) -> Result<Palette, String> {
+        match name {
+            "fire" => Ok(fire::gen_palette()),
+            "ocean" => Ok(Palette::gradient(&[(0, 8, 30), (0, 60, 120), (20, 160, 200), (220, 250, 255)], 255)),
+            "aurora" => Ok(Palette::gradient(&[(5, 5, 25), (40, 20, 110), (0, 170, 130), (180, 255, 120)], 255)),
+            "gray" => Ok(Palette::gradient(&[(0, 0, 0), (255, 255, 255)], 255)),
+            _ => Err(format!("unknown palette {:?} (expected one of {:?})", name, Palette::NAMES)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
     fn get(&self, index: usize) -> Option<&u32> {
         self.colors.get(index)
     }
@@ -42,13 +117,56 @@ impl Palette {
     }
 }
 
-/// Trait to get a convoluted buffer
-pub trait Convolution {
+/// Trait to get a convoluted buffer,
+/// it's `Send` so the frame can be rendered inside the thread pool of the app
+/// and `Sync` so the tiles of the frame can be drawn from several threads
+pub trait Convolution: Send + Sync {
     /// Function to get Convolution name
     fn name(&self) -> &str;
-    /// Function to implement the transformation of the main buffer
-    /// to be displayed
-    fn transform(&mut self, pixels: &mut [u32], width: usize, height: usize);
+    /// Function to draw the layer on its own RGBA surface, the surface
+    /// starts transparent and is composited over the layers below
+    fn transform(&mut self, pixels: &mut [u32], width: usize, height: usize, ctx: &FrameContext);
+
+    /// Function to know which part of the layer would be different from
+    /// the last frame, only that part is drawn again and the frames without
+    /// changes aren't rendered
+    fn damage(&self, _width: usize, _height: usize, _ctx: &FrameContext) -> Damage {
+        Damage::Full
+    }
+
+    /// Function to know if the layer is a filter of the layers below it:
+    /// `transform` gets the frame composited so far instead of a transparent
+    /// surface, and the result is composited over that frame
+    fn reads_frame(&self) -> bool {
+        false
+    }
+
+    /// Function called when the frame changes of size, the layers with
+    /// their own buffers must rebuild them for the new resolution
+    fn resize(&mut self, _width: usize, _height: usize) {}
+
+    /// Function called once per frame by the tiled renderer before drawing
+    /// any tile, returns `false` if the layer can only be drawn on the whole
+    /// frame with `transform` (e.g. it keeps state between pixels)
+    fn prepare_tiles(&mut self, _width: usize, _height: usize, _ctx: &FrameContext) -> bool {
+        false
+    }
+
+    /// Function to draw the band of rows that starts at `top` on its part of
+    /// the surface, it must give the same pixels as `transform`
+    fn transform_tile(&self, _pixels: &mut [u32], _top: usize, _width: usize, _height: usize) {}
+
+    /// Function to list the parameters that can be changed while running,
+    /// with their schema and current value
+    fn params(&self) -> Vec<Param> {
+        Vec::new()
+    }
+
+    /// Function to change a parameter while running, the value is
+    /// validated with the schema of the parameter
+    fn set_param(&mut self, name: &str, _value: ParamValue) -> Result<(), String> {
+        Err(format!("{} has no parameter {:?}", self.name(), name))
+    }
 }
 
 pub trait ConvolutionAdvanced: Convolution {
@@ -59,4 +177,211 @@ pub trait ConvolutionAdvanced: Convolution {
 pub enum ConvolutionType {
     Simple(Box<dyn Convolution>),
     Advanced(Box<dyn ConvolutionAdvanced>),
+}
+
+impl ConvolutionType {
+    pub fn name(&self) -> &str {
+        match self {
+            ConvolutionType::Simple(conv) => conv.name(),
+            ConvolutionType::Advanced(conv) => conv.name(),
+        }
+    }
+
+    pub fn transform(&mut self, pixels: &mut [u32], width: usize, height: usize, ctx: &FrameContext) {
+        match self {
+            ConvolutionType::Simple(conv) => conv.transform(pixels, width, height, ctx),
+            ConvolutionType::Advanced(conv) => conv.transform(pixels, width, height, ctx),
+        }
+    }
+
+    pub fn damage(&self, width: usize, height: usize, ctx: &FrameContext) -> Damage {
+        match self {
+            ConvolutionType::Simple(conv) => conv.damage(width, height, ctx),
+            ConvolutionType::Advanced(conv) => conv.damage(width, height, ctx),
+        }
+    }
+
+    pub fn reads_frame(&self) -> bool {
+        match self {
+            ConvolutionType::Simple(conv) => conv.reads_frame(),
+            ConvolutionType::Advanced(conv) => conv.reads_frame(),
+        }
+    }
+
+    pub fn prepare_tiles(&mut self, width: usize, height: usize, ctx: &FrameContext) -> bool {
+        match self {
+            ConvolutionType::Simple(conv) => conv.prepare_tiles(width, height, ctx),
+            ConvolutionType::Advanced(conv) => conv.prepare_tiles(width, height, ctx),
+        }
+    }
+
+    pub fn transform_tile(&self, pixels: &mut [u32], top: usize, width: usize, height: usize) {
+        match self {
+            ConvolutionType::Simple(conv) => conv.transform_tile(pixels, top, width, height),
+            ConvolutionType::Advanced(conv) => conv.transform_tile(pixels, top, width, height),
+        }
+    }
+
+    pub fn resize(&mut self, width: usize, height: usize) {
+        match self {
+            ConvolutionType::Simple(conv) => conv.resize(width, height),
+            ConvolutionType::Advanced(conv) => conv.resize(width, height),
+        }
+    }
+
+    pub fn params(&self) -> Vec<Param> {
+        match self {
+            ConvolutionType::Simple(conv) => conv.params(),
+            ConvolutionType::Advanced(conv) => conv.params(),
+        }
+    }
+
+    pub fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        match self {
+            ConvolutionType::Simple(conv) => conv.set_param(name, value),
+            ConvolutionType::Advanced(conv) => conv.set_param(name, value),
+        }
+    }
+}
+
+/// Layer of the stack, a convolution with its compositing options
+pub struct Layer {
+    pub convolution: ConvolutionType,
+    pub active: bool,
+    /// Opacity of the whole layer, between 0 and 1
+    pub opacity: f32,
+    pub blend: BlendMode,
+    /// Internal resolution relative to the frame, the layer is drawn at
+    /// that size and resampled to the frame with `filter`
+    pub scale: f32,
+    pub filter: Filter,
+    /// The layer is drawn again every `interval` frames, the other
+    /// frames reuse its surface
+    pub interval: u32,
+    /// Time of the last draw of the layer (without compositing)
+    pub cost: Duration,
+    /// Nanoseconds spent on the tiles of the frame, added by every thread
+    pub(crate) tile_cost: AtomicU64,
+    pub(crate) surface: Vec<u32>,
+    /// Size at which the convolution was drawn the last time
+    size: (usize, usize),
+    /// Surface at the internal resolution when the scale isn't 1
+    scaled: Vec<u32>,
+    resampler: Resampler,
+}
+
+impl Layer {
+    /// Opaque layer with normal blending
+    pub fn new(convolution: ConvolutionType, active: bool) -> Self {
+        Layer {
+            convolution,
+            active,
+            opacity: 1.0,
+            blend: BlendMode::Normal,
+            scale: 1.0,
+            filter: Filter::Nearest,
+            interval: 1,
+            cost: Duration::ZERO,
+            tile_cost: AtomicU64::new(0),
+            surface: Vec::new(),
+            size: (0, 0),
+            scaled: Vec::new(),
+            resampler: Resampler::default(),
+        }
+    }
+
+    /// Size of the frame at the internal resolution of the layer
+    pub fn scaled_size(&self, width: usize, height: usize) -> (usize, usize) {
+        if self.scale == 1.0 {
+            return (width, height);
+        }
+
+        let scaled = |size: usize| ((size as f32 * self.scale).round() as usize).max(1);
+        (scaled(width), scaled(height))
+    }
+
+    /// Function to know if the layer must be drawn on the frame `tick`
+    pub fn is_due(&self, tick: u64) -> bool {
+        tick.is_multiple_of(self.interval.max(1) as u64)
+    }
+
+    /// Part of the frame that changes, in pixels of the frame. The damage of
+    /// a layer with another scale is the whole frame
+    pub fn damage(&self, width: usize, height: usize, ctx: &FrameContext) -> Damage {
+        let (scaled_width, scaled_height) = self.scaled_size(width, height);
+
+        match self.convolution.damage(scaled_width, scaled_height, ctx) {
+            Damage::Rects(_) if (scaled_width, scaled_height) != (width, height) => Damage::Full,
+            damage => damage,
+        }
+    }
+
+    /// Draws the convolution on the surface of the layer, the surface is
+    /// allocated only the first time or when the size changes. `frame` is
+    /// the frame composited so far, the input of the layers that read it
+    pub fn render(&mut self, width: usize, height: usize, frame: &[u32], ctx: &FrameContext) {
+        let start = Instant::now();
+        let reads_frame = self.convolution.reads_frame();
+
+        if !self.allocate(width, height) && !reads_frame {
+            self.surface.fill(Color::TRANSPARENT);
+        }
+
+        let (scaled_width, scaled_height) = self.scaled_size(width, height);
+        self.notify_size(scaled_width, scaled_height);
+
+        if (scaled_width, scaled_height) == (width, height) {
+            if reads_frame {
+                self.surface.copy_from_slice(frame);
+            }
+            self.convolution.transform(&mut self.surface, width, height, ctx);
+        } else {
+            self.scaled.clear();
+            self.scaled.resize(scaled_width * scaled_height, Color::TRANSPARENT);
+            if reads_frame {
+                self.resampler.resample(self.filter, frame, (width, height), &mut self.scaled, (scaled_width, scaled_height));
+            }
+            self.convolution.transform(&mut self.scaled, scaled_width, scaled_height, ctx);
+            self.resampler.resample(self.filter, &self.scaled, (scaled_width, scaled_height), &mut self.surface, (width, height));
+        }
+
+        self.cost = start.elapsed();
+    }
+
+    /// Prepares the layer to be drawn by tiles, returns `false` if the layer
+    /// must be drawn on the whole frame with [`Layer::render`]
+    pub(crate) fn prepare_tiles(&mut self, width: usize, height: usize, ctx: &FrameContext) -> bool {
+        self.allocate(width, height);
+
+        if self.scaled_size(width, height) != (width, height) {
+            return false;
+        }
+
+        self.notify_size(width, height);
+        self.convolution.prepare_tiles(width, height, ctx)
+    }
+
+    /// Notifies the convolution when the size at which it's drawn changes
+    fn notify_size(&mut self, width: usize, height: usize) {
+        if self.size != (width, height) {
+            self.convolution.resize(width, height);
+            self.size = (width, height);
+        }
+    }
+
+    /// Allocates a transparent surface if the size changed,
+    /// returns `true` if it was allocated
+    pub(crate) fn allocate(&mut self, width: usize, height: usize) -> bool {
+        if self.surface.len() == width * height {
+            return false;
+        }
+
+        self.surface = vec![Color::TRANSPARENT; width * height];
+        true
+    }
+
+    /// Composites the last rendered surface over the frame
+    pub fn composite(&self, frame: &mut [u32]) {
+        blend::composite(frame, &self.surface, self.opacity, self.blend);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,375 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use noise::core::worley::{distance_functions, worley_3d, ReturnType};
+use noise::permutationtable::PermutationTable;
+use noise::{Fbm, MultiFractal, NoiseFn, OpenSimplex, Perlin, RidgedMulti, Vector3};
+use crate::convolutions::effect::PixelEffect;
+use crate::convolutions::params::{self, Param, ParamSet, ParamSpec, ParamValue};
+use crate::convolutions::{FrameContext, Palette};
+
+/// Offsets of the two samples that move the point with the domain warping,
+/// far from the original point so the samples aren't correlated
+const WARP_OFFSETS: [[f64; 2]; 2] = [[5.2, 1.3], [1.7, 9.2]];
+/// Points sampled to measure the range of a noise, inside a cube of
+/// `RANGE_SIZE` noise cells
+const RANGE_SAMPLES: usize = 16384;
+const RANGE_SIZE: f64 = 16.0;
+/// Steps of the sampled points along each axis, the R3 sequence
+const RANGE_STEPS: [f64; 3] = [0.819_172_513_396_164_4, 0.671_043_606_703_789_2, 0.549_700_477_901_970_5];
+
+/// Noise function of a [`NoiseField`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NoiseKind {
+    #[default]
+    Perlin,
+    OpenSimplex,
+    /// Distance to the nearest point of a cell (cellular noise)
+    Worley,
+    /// Fractal Brownian motion, octaves of Perlin noise
+    Fbm,
+    /// Ridged multifractal, octaves of Perlin noise with sharp crests
+    Ridged,
+}
+
+impl NoiseKind {
+    pub const ALL: [NoiseKind; 5] = [
+        NoiseKind::Perlin,
+        NoiseKind::OpenSimplex,
+        NoiseKind::Worley,
+        NoiseKind::Fbm,
+        NoiseKind::Ridged,
+    ];
+
+    pub const NAMES: [&'static str; 5] = ["perlin", "open_simplex", "worley", "fbm", "ridged"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NoiseKind::Perlin => Self::NAMES[0],
+            NoiseKind::OpenSimplex => Self::NAMES[1],
+            NoiseKind::Worley => Self::NAMES[2],
+            NoiseKind::Fbm => Self::NAMES[3],
+            NoiseKind::Ridged => Self::NAMES[4],
+        }
+    }
+}
+
+impl FromStr for NoiseKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NoiseKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| format!("unknown noise {:?} (expected one of {:?})", s, NoiseKind::NAMES))
+    }
+}
+
+impl fmt::Display for NoiseKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Parameters of the noise backgrounds, shared by the sequential and the parallel version
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoiseParams {
+    pub kind: NoiseKind,
+    pub seed: u32,
+    pub scale: f32,   // Celdas de ruido en la altura del frame
+    pub octaves: u32, // Capas de detalle de fbm y ridged
+    pub speed: f32,   // Avance en el tiempo del ruido por segundo
+    pub warp: f32,    // Fuerza de la deformación del dominio, 0 sin deformar
+    pub palette: String,
+}
+
+impl Default for NoiseParams {
+    fn default() -> Self {
+        NoiseParams {
+            kind: NoiseKind::Perlin,
+            seed: 0,
+            scale: 3.0,
+            octaves: 4,
+            speed: 0.2,
+            warp: 0.0,
+            palette: "ocean".to_string(),
+        }
+    }
+}
+
+impl NoiseParams {
+    pub fn schema() -> Vec<ParamSpec> {
+        vec![
+            ParamSpec::choice("kind", "perlin", &NoiseKind::NAMES, "Noise function"),
+            ParamSpec::int("seed", 0, 0, 65535, "Seed of the noise, each one gives a different field"),
+            ParamSpec::float("scale", 3.0, 0.5, 32.0, "Noise cells along the height of the frame"),
+            ParamSpec::int("octaves", 4, 1, 8, "Layers of detail of the fbm and ridged noises"),
+            ParamSpec::float("speed", 0.2, 0.0, 4.0, "Speed of the animation through the noise"),
+            ParamSpec::float("warp", 0.0, 0.0, 4.0, "Strength of the domain warping, 0 doesn't warp"),
+            ParamSpec::choice("palette", "ocean", &Palette::NAMES, "Palette the noise is mapped to"),
+        ]
+    }
+
+    pub fn from_params(params: &ParamSet) -> Self {
+        NoiseParams {
+            kind: params.text("kind").parse().expect("Noise validated by the schema"),
+            seed: params.int("seed") as u32,
+            scale: params.float("scale") as f32,
+            octaves: params.int("octaves") as u32,
+            speed: params.float("speed") as f32,
+            warp: params.float("warp") as f32,
+            palette: params.text("palette").to_string(),
+        }
+    }
+
+    pub fn params(&self) -> Vec<Param> {
+        params::with_values(Self::schema(), [
+            ParamValue::Text(self.kind.name().to_string()),
+            ParamValue::Int(self.seed as i64),
+            ParamValue::Float(self.scale as f64),
+            ParamValue::Int(self.octaves as i64),
+            ParamValue::Float(self.speed as f64),
+            ParamValue::Float(self.warp as f64),
+            ParamValue::Text(self.palette.clone()),
+        ])
+    }
+
+    pub fn set(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        let value = params::validate(&Self::schema(), name, &value)?;
+
+        match name {
+            "kind" => self.kind = value.as_str().unwrap_or_default().parse()?,
+            "seed" => self.seed = value.as_i64().unwrap_or_default() as u32,
+            "scale" => self.scale = value.as_f64().unwrap_or_default() as f32,
+            "octaves" => self.octaves = value.as_i64().unwrap_or_default() as u32,
+            "speed" => self.speed = value.as_f64().unwrap_or_default() as f32,
+            "warp" => self.warp = value.as_f64().unwrap_or_default() as f32,
+            _ => self.palette = value.as_str().unwrap_or_default().to_string(),
+        }
+
+        Ok(())
+    }
+}
+
+/// Noise function built from the parameters, `Worley` keeps only its
+/// permutation table because the generator of the crate isn't `Sync`
+enum Source {
+    Perlin(Perlin),
+    OpenSimplex(OpenSimplex),
+    Worley(PermutationTable),
+    Fbm(Fbm<Perlin>),
+    Ridged(RidgedMulti<Perlin>),
+}
+
+impl Source {
+    fn new(noise: &NoiseParams) -> Self {
+        let octaves = noise.octaves as usize;
+
+        match noise.kind {
+            NoiseKind::Perlin => Source::Perlin(Perlin::new(noise.seed)),
+            NoiseKind::OpenSimplex => Source::OpenSimplex(OpenSimplex::new(noise.seed)),
+            NoiseKind::Worley => Source::Worley(PermutationTable::new(noise.seed)),
+            NoiseKind::Fbm => Source::Fbm(Fbm::new(noise.seed).set_octaves(octaves)),
+            NoiseKind::Ridged => Source::Ridged(RidgedMulti::new(noise.seed).set_octaves(octaves)),
+        }
+    }
+
+    /// Value of the noise at `point`, its range depends on the kind, see
+    /// [`Source::range`]
+    fn get(&self, point: [f64; 3]) -> f64 {
+        match self {
+            Source::Perlin(noise) => noise.get(point),
+            Source::OpenSimplex(noise) => noise.get(point),
+            Source::Worley(table) => worley_3d(table, distance_functions::euclidean, ReturnType::Distance, Vector3::from(point)),
+            Source::Fbm(noise) => noise.get(point),
+            Source::Ridged(noise) => noise.get(point),
+        }
+    }
+
+    /// Lowest and highest value of the noise. The kinds don't share a
+    /// range: the Worley distance goes from -1 to about 1.1, OpenSimplex
+    /// stays about -0.55 to 0.55 and fbm and ridged change with the
+    /// octaves, so it's measured on a grid of points
+    fn range(&self) -> (f64, f64) {
+        let (mut min, mut max) = (f64::MAX, f64::MIN);
+
+        // Secuencia de Kronecker, cubre el espacio sin los huecos de una
+        // rejilla alineada con las celdas del ruido
+        for i in 0..RANGE_SAMPLES {
+            let point = RANGE_STEPS.map(|step| (i as f64 * step).fract() * RANGE_SIZE);
+            let value = self.get(point);
+            (min, max) = (min.min(value), max.max(value));
+        }
+
+        (min, max.max(min + f64::EPSILON))
+    }
+}
+
+/// Animated noise background: the third coordinate of the noise is the
+/// time, the value is mapped to a [`Palette`]. It's written once as a
+/// [`PixelEffect`], the sequential and parallel versions only change the
+/// [`Executor`](crate::convolutions::effect::Executor)
+pub struct NoiseField {
+    noise: NoiseParams,
+    source: Source,
+    range: (f64, f64), // Valores mínimo y máximo de `source`
+    palette: Palette,
+    time: f64,              // Tiempo del ruido, avanza según `speed`
+    last: Option<Duration>, // Tiempo de la app en el último frame dibujado
+}
+
+impl NoiseField {
+    pub fn new(noise: NoiseParams) -> Self {
+        let source = Source::new(&noise);
+
+        NoiseField {
+            range: source.range(),
+            source,
+            palette: Palette::named(&noise.palette).expect("Palette validated by the schema"),
+            noise,
+            time: 0.0,
+            last: None,
+        }
+    }
+
+    /// Value of the noise at `(x, y)`, between 0 and 1
+    fn value(&self, x: usize, y: usize, height: usize) -> f64 {
+        // Coordenadas relativas a la altura, así las celdas son cuadradas
+        let scale = self.noise.scale as f64 / height.max(1) as f64;
+        let (mut nx, mut ny) = (x as f64 * scale, y as f64 * scale);
+
+        // Deformación del dominio: el punto se mueve según otras dos
+        // muestras del mismo ruido
+        if self.noise.warp > 0.0 {
+            let warp = self.noise.warp as f64;
+            let [dx, dy] = WARP_OFFSETS.map(|[ox, oy]| self.source.get([nx + ox, ny + oy, self.time]));
+            nx += dx * warp;
+            ny += dy * warp;
+        }
+
+        let (min, max) = self.range;
+        ((self.source.get([nx, ny, self.time]) - min) / (max - min)).clamp(0.0, 1.0)
+    }
+}
+
+impl Default for NoiseField {
+    fn default() -> Self {
+        Self::new(NoiseParams::default())
+    }
+}
+
+impl PixelEffect for NoiseField {
+    fn name(&self) -> &str {
+        "Noise"
+    }
+
+    fn prepare(&mut self, _width: usize, _height: usize, ctx: &FrameContext) {
+        // Como en las ondas de fuego el tiempo avanza lo que pasó desde el
+        // último frame, así cambiar `speed` no hace saltar el ruido
+        let delta = self.last.map_or(0.0, |last| ctx.animation.saturating_sub(last).as_secs_f64());
+        self.last = Some(ctx.animation);
+        self.time += delta * self.noise.speed as f64;
+    }
+
+    fn pixel(&self, x: usize, y: usize, _width: usize, height: usize) -> u32 {
+        let value = self.value(x, y, height);
+        let index = (value * (self.palette.len() - 1) as f64).round() as usize;
+
+        *self.palette.get(index).expect("Color not found in pallete")
+    }
+
+    fn params(&self) -> Vec<Param> {
+        self.noise.params()
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        self.noise.set(name, value)?;
+
+        match name {
+            "palette" => self.palette = Palette::named(&self.noise.palette)?,
+            "kind" | "seed" | "octaves" => {
+                self.source = Source::new(&self.noise);
+                self.range = self.source.range();
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.time = 0.0;
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Local;
+    use super::*;
+
+    fn field(kind: NoiseKind, octaves: u32) -> NoiseField {
+        NoiseField::new(NoiseParams { kind, octaves, scale: 8.0, ..NoiseParams::default() })
+    }
+
+    #[test]
+    fn every_kind_covers_the_palette() {
+        for kind in NoiseKind::ALL {
+            for octaves in [1, 4, 8] {
+                let mut noise = field(kind, octaves);
+                let mut values = Vec::new();
+
+                for frame in 0..3 {
+                    noise.time = frame as f64 * 3.7;
+                    values.extend((0..64 * 64).map(|i| noise.value(i % 64, i / 64, 64)));
+                }
+
+                // Ni media paleta sin usar ni los extremos saturados
+                let low = values.iter().filter(|&&value| value < 0.15).count() as f64 / values.len() as f64;
+                let high = values.iter().filter(|&&value| value > 0.85).count() as f64 / values.len() as f64;
+                let saturated = values.iter().filter(|&&value| value == 0.0 || value == 1.0).count() as f64 / values.len() as f64;
+
+                assert!(low > 0.0 && high > 0.0, "{} with {} octaves doesn't reach both ends", kind, octaves);
+                assert!(saturated < 0.05, "{} with {} octaves saturates {:.3} of the pixels", kind, octaves, saturated);
+            }
+        }
+    }
+
+    #[test]
+    fn measures_the_range_of_each_kind() {
+        let range = |kind: NoiseKind, octaves: u32| field(kind, octaves).range;
+
+        // Worley es la distancia al punto más cercano, de 0 a algo más
+        // que 1, que la crate devuelve como `2 * distancia - 1`
+        let (min, max) = range(NoiseKind::Worley, 1);
+        assert!(min < -0.95 && (0.8..1.2).contains(&max), "worley {:?}", (min, max));
+
+        // OpenSimplex no pasa de la mitad de -1..1
+        let (min, max) = range(NoiseKind::OpenSimplex, 1);
+        assert!(min > -0.6 && max < 0.6, "open simplex {:?}", (min, max));
+
+        // Ridged crece con las octavas
+        assert!(range(NoiseKind::Ridged, 1).1 < range(NoiseKind::Ridged, 8).1);
+    }
+
+    #[test]
+    fn time_is_integrated() {
+        let mut noise = NoiseField::new(NoiseParams { speed: 0.5, ..NoiseParams::default() });
+        let mut ctx = FrameContext::new(Local::now());
+
+        noise.prepare(8, 8, &ctx);
+        ctx.animation = Duration::from_secs(2);
+        noise.prepare(8, 8, &ctx);
+        assert!((noise.time - 1.0).abs() < 1e-9);
+
+        // Al cambiar la velocidad el tiempo sigue desde donde estaba
+        noise.set_param("speed", ParamValue::Float(2.0)).expect("Speed not set");
+        ctx.animation = Duration::from_secs(3);
+        noise.prepare(8, 8, &ctx);
+        assert!((noise.time - 3.0).abs() < 1e-9);
+
+        noise.reset();
+        noise.prepare(8, 8, &ctx);
+        assert_eq!(noise.time, 0.0);
+    }
+}
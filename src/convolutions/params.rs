@@ -0,0 +1,457 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use serde::Deserialize;
+
+/// Value of a layer parameter
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum ParamValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+    /// Rows of numbers, e.g. the weights of a kernel
+    Matrix(Vec<Vec<f64>>),
+}
+
+impl ParamValue {
+    /// Numeric value of integers and floats
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            ParamValue::Int(value) => Some(*value as f64),
+            ParamValue::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            ParamValue::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            ParamValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            ParamValue::Text(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_matrix(&self) -> Option<&[Vec<f64>]> {
+        match self {
+            ParamValue::Matrix(rows) => Some(rows),
+            _ => None,
+        }
+    }
+
+    /// Color of a color parameter, `None` for `"random"`
+    pub fn as_color(&self) -> Option<u32> {
+        match self {
+            ParamValue::Text(value) => parse_color(value),
+            _ => None,
+        }
+    }
+
+    /// Text of a color parameter for a color, `"random"` for `None`
+    pub fn from_color(color: Option<u32>) -> ParamValue {
+        match color {
+            Some(color) => ParamValue::Text(format!("#{:06x}", color & 0xFF_FFFF)),
+            None => ParamValue::Text("random".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for ParamValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamValue::Bool(value) => write!(f, "{}", value),
+            ParamValue::Int(value) => write!(f, "{}", value),
+            ParamValue::Float(value) => write!(f, "{}", value),
+            ParamValue::Text(value) => write!(f, "{:?}", value),
+            ParamValue::Matrix(rows) => write!(f, "{:?}", rows),
+        }
+    }
+}
+
+/// Type and valid values of a parameter
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamKind {
+    Bool,
+    Int { min: i64, max: i64 },
+    Float { min: f64, max: f64 },
+    /// `"#rrggbb"` or `"random"`
+    Color,
+    /// One of the names
+    Choice(&'static [&'static str]),
+    /// Weights of a kernel: a single row (separable) or a square matrix,
+    /// with an odd size up to `max`
+    Kernel { max: usize },
+}
+
+impl fmt::Display for ParamKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamKind::Bool => write!(f, "bool"),
+            ParamKind::Int { min, max } => write!(f, "int {}..={}", min, max),
+            ParamKind::Float { min, max } => write!(f, "float {}..={}", min, max),
+            ParamKind::Color => write!(f, "color \"#rrggbb\" | \"random\""),
+            ParamKind::Choice(choices) => write!(f, "one of {}", choices.join(" | ")),
+            ParamKind::Kernel { max } => write!(f, "kernel 1xN or NxN, N odd <= {}", max),
+        }
+    }
+}
+
+/// Schema of a parameter accepted by a layer
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamSpec {
+    pub name: &'static str,
+    pub kind: ParamKind,
+    pub default: ParamValue,
+    pub help: &'static str,
+}
+
+impl ParamSpec {
+    pub fn bool(name: &'static str, default: bool, help: &'static str) -> Self {
+        ParamSpec { name, kind: ParamKind::Bool, default: ParamValue::Bool(default), help }
+    }
+
+    pub fn int(name: &'static str, default: i64, min: i64, max: i64, help: &'static str) -> Self {
+        ParamSpec { name, kind: ParamKind::Int { min, max }, default: ParamValue::Int(default), help }
+    }
+
+    pub fn float(name: &'static str, default: f64, min: f64, max: f64, help: &'static str) -> Self {
+        ParamSpec { name, kind: ParamKind::Float { min, max }, default: ParamValue::Float(default), help }
+    }
+
+    pub fn color(name: &'static str, default: &str, help: &'static str) -> Self {
+        ParamSpec { name, kind: ParamKind::Color, default: ParamValue::Text(default.to_string()), help }
+    }
+
+    pub fn choice(name: &'static str, default: &str, choices: &'static [&'static str], help: &'static str) -> Self {
+        ParamSpec { name, kind: ParamKind::Choice(choices), default: ParamValue::Text(default.to_string()), help }
+    }
+
+    pub fn kernel(name: &'static str, default: &[&[f64]], max: usize, help: &'static str) -> Self {
+        let rows = default.iter().map(|row| row.to_vec()).collect();
+        ParamSpec { name, kind: ParamKind::Kernel { max }, default: ParamValue::Matrix(rows), help }
+    }
+
+    /// Validates a value against the schema, integers given
+    /// to a float parameter are converted
+    pub fn check(&self, value: &ParamValue) -> Result<ParamValue, String> {
+        let name = self.name;
+
+        match (&self.kind, value) {
+            (ParamKind::Bool, ParamValue::Bool(_)) => Ok(value.clone()),
+            (ParamKind::Int { min, max }, ParamValue::Int(v)) => {
+                if v < min || v > max {
+                    return Err(format!("parameter {:?} = {} is out of range {}..={}", name, v, min, max));
+                }
+                Ok(value.clone())
+            }
+            (ParamKind::Float { min, max }, ParamValue::Int(_) | ParamValue::Float(_)) => {
+                let v = value.as_f64().unwrap_or_default();
+                if !(v >= *min && v <= *max) {
+                    return Err(format!("parameter {:?} = {} is out of range {}..={}", name, v, min, max));
+                }
+                Ok(ParamValue::Float(v))
+            }
+            (ParamKind::Color, ParamValue::Text(text)) => {
+                if text != "random" && parse_color(text).is_none() {
+                    return Err(format!("parameter {:?} = {:?} isn't a color like \"#rrggbb\" or \"random\"", name, text));
+                }
+                Ok(value.clone())
+            }
+            (ParamKind::Choice(choices), ParamValue::Text(text)) => {
+                if !choices.contains(&text.as_str()) {
+                    return Err(format!("parameter {:?} = {:?} must be one of {:?}", name, text, choices));
+                }
+                Ok(value.clone())
+            }
+            (ParamKind::Kernel { max }, ParamValue::Matrix(rows)) => {
+                let size = rows.first().map_or(0, |row| row.len());
+                let shape = rows.len() == 1 || rows.len() == size;
+
+                if !shape || size % 2 == 0 || size > *max || rows.iter().any(|row| row.len() != size) {
+                    return Err(format!("parameter {:?} must be a kernel 1xN or NxN with N odd <= {}, found {}", name, max, value));
+                }
+                Ok(value.clone())
+            }
+            (kind, value) => Err(format!("parameter {:?} must be {}, found {}", name, kind, value)),
+        }
+    }
+}
+
+impl ParamSpec {
+    /// Value one step up (`direction` > 0) or down from `value`, used to
+    /// change the parameter with the keyboard. Booleans are toggled, choices
+    /// are cycled and colors can't be stepped.
+    pub fn step(&self, value: &ParamValue, direction: i32) -> ParamValue {
+        let direction = direction.signum();
+
+        match (&self.kind, value) {
+            (ParamKind::Bool, ParamValue::Bool(value)) => ParamValue::Bool(!value),
+            (ParamKind::Int { min, max }, ParamValue::Int(value)) => {
+                let step = ((max - min) / 20).max(1);
+                ParamValue::Int((value + step * direction as i64).clamp(*min, *max))
+            }
+            (ParamKind::Float { min, max }, value) => {
+                let step = (max - min) / 20.0;
+                let value = value.as_f64().unwrap_or(*min);
+                ParamValue::Float((value + step * direction as f64).clamp(*min, *max))
+            }
+            (ParamKind::Choice(choices), ParamValue::Text(text)) => {
+                let index = choices.iter().position(|choice| choice == text).unwrap_or(0) as i32;
+                let next = (index + direction).rem_euclid(choices.len() as i32) as usize;
+                ParamValue::Text(choices[next].to_string())
+            }
+            (_, value) => value.clone(),
+        }
+    }
+}
+
+/// Parameter of a running layer with its current value
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub spec: ParamSpec,
+    pub value: ParamValue,
+}
+
+impl Param {
+    pub fn new(spec: ParamSpec, value: ParamValue) -> Self {
+        Param { spec, value }
+    }
+}
+
+/// Joins a schema with the current values, in the same order
+pub fn with_values<const N: usize>(specs: Vec<ParamSpec>, values: [ParamValue; N]) -> Vec<Param> {
+    specs.into_iter()
+        .zip(values)
+        .map(|(spec, value)| Param::new(spec, value))
+        .collect()
+}
+
+/// Finds the parameter `name` in `specs` and validates the new value
+pub fn validate(specs: &[ParamSpec], name: &str, value: &ParamValue) -> Result<ParamValue, String> {
+    specs.iter()
+        .find(|spec| spec.name == name)
+        .ok_or_else(|| format!("unknown parameter {:?}", name))?
+        .check(value)
+}
+
+/// Parameters of a layer validated against its schema, every
+/// parameter of the schema has a value
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParamSet {
+    values: BTreeMap<&'static str, ParamValue>,
+}
+
+impl ParamSet {
+    /// Validates `values` with `specs`, the missing parameters take the default
+    /// value and the unknown ones are reported
+    pub fn new(specs: &[ParamSpec], values: &BTreeMap<String, ParamValue>) -> Result<ParamSet, String> {
+        if let Some(unknown) = values.keys().find(|key| !specs.iter().any(|spec| spec.name == key.as_str())) {
+            let expected: Vec<&str> = specs.iter().map(|spec| spec.name).collect();
+            return Err(format!("unknown parameter {:?} (expected one of {:?})", unknown, expected));
+        }
+
+        let mut set = ParamSet::default();
+        for spec in specs {
+            let value = match values.get(spec.name) {
+                Some(value) => spec.check(value)?,
+                None => spec.default.clone(),
+            };
+            set.values.insert(spec.name, value);
+        }
+
+        Ok(set)
+    }
+
+    fn get(&self, name: &str) -> &ParamValue {
+        self.values.get(name).unwrap_or_else(|| panic!("parameter {:?} isn't in the schema", name))
+    }
+
+    pub fn bool(&self, name: &str) -> bool {
+        match self.get(name) {
+            ParamValue::Bool(value) => *value,
+            value => panic!("parameter {:?} isn't a bool: {}", name, value),
+        }
+    }
+
+    pub fn int(&self, name: &str) -> i64 {
+        match self.get(name) {
+            ParamValue::Int(value) => *value,
+            value => panic!("parameter {:?} isn't an int: {}", name, value),
+        }
+    }
+
+    pub fn float(&self, name: &str) -> f64 {
+        let value = self.get(name);
+        value.as_f64().unwrap_or_else(|| panic!("parameter {:?} isn't a float: {}", name, value))
+    }
+
+    pub fn text(&self, name: &str) -> &str {
+        let value = self.get(name);
+        value.as_str().unwrap_or_else(|| panic!("parameter {:?} isn't a text: {}", name, value))
+    }
+
+    pub fn matrix(&self, name: &str) -> &[Vec<f64>] {
+        let value = self.get(name);
+        value.as_matrix().unwrap_or_else(|| panic!("parameter {:?} isn't a matrix: {}", name, value))
+    }
+
+    /// Color of the parameter, `None` for `"random"`
+    pub fn color(&self, name: &str) -> Option<u32> {
+        match self.get(name) {
+            ParamValue::Text(value) => parse_color(value),
+            value => panic!("parameter {:?} isn't a color: {}", name, value),
+        }
+    }
+}
+
+/// Parses a `#rrggbb` color as an opaque pixel
+pub fn parse_color(raw: &str) -> Option<u32> {
+    let hex = raw.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    u32::from_str_radix(hex, 16).ok().map(|rgb| rgb | 0xFF00_0000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(value: &str) -> ParamValue {
+        ParamValue::Text(value.to_string())
+    }
+
+    #[test]
+    fn check_rejects_values_out_of_range() {
+        let int = ParamSpec::int("red", 0, 0, 255, "");
+        assert_eq!(int.check(&ParamValue::Int(255)), Ok(ParamValue::Int(255)));
+        assert!(int.check(&ParamValue::Int(256)).unwrap_err().contains("out of range 0..=255"));
+        assert!(int.check(&ParamValue::Int(-1)).is_err());
+
+        let float = ParamSpec::float("falloff", 1.0, 0.0, 1.0, "");
+        assert_eq!(float.check(&ParamValue::Float(0.5)), Ok(ParamValue::Float(0.5)));
+        assert!(float.check(&ParamValue::Float(1.5)).unwrap_err().contains("out of range"));
+        assert!(float.check(&ParamValue::Float(f64::NAN)).is_err());
+    }
+
+    #[test]
+    fn check_rejects_the_wrong_types() {
+        let cases = [
+            (ParamSpec::bool("f24", true, ""), ParamValue::Int(1)),
+            (ParamSpec::int("red", 0, 0, 255, ""), ParamValue::Float(1.0)),
+            (ParamSpec::int("red", 0, 0, 255, ""), text("max")),
+            (ParamSpec::float("falloff", 1.0, 0.0, 1.0, ""), ParamValue::Bool(true)),
+            (ParamSpec::color("color", "random", ""), ParamValue::Int(0xFF0000)),
+        ];
+
+        for (spec, value) in cases {
+            let error = spec.check(&value).unwrap_err();
+            assert!(error.contains(&format!("must be {}", spec.kind)), "{}", error);
+        }
+
+        let color = ParamSpec::color("color", "random", "");
+        assert_eq!(color.check(&text("random")), Ok(text("random")));
+        assert_eq!(color.check(&text("#ff8000")), Ok(text("#ff8000")));
+        assert!(color.check(&text("red")).unwrap_err().contains("isn't a color"));
+    }
+
+    #[test]
+    fn check_rejects_invalid_kernel_shapes() {
+        let spec = ParamSpec::kernel("kernel", &[&[1.0]], 7, "");
+        let matrix = |rows: &[&[f64]]| ParamValue::Matrix(rows.iter().map(|row| row.to_vec()).collect());
+
+        for valid in [matrix(&[&[1.0]]), matrix(&[&[1.0, 2.0, 1.0]]), matrix(&[&[0.0, -1.0, 0.0], &[-1.0, 5.0, -1.0], &[0.0, -1.0, 0.0]]), matrix(&[&[1.0; 7]])] {
+            assert_eq!(spec.check(&valid), Ok(valid.clone()));
+        }
+
+        let invalid = [
+            matrix(&[]),                                    // Vacío
+            matrix(&[&[]]),                                 // Fila vacía
+            matrix(&[&[1.0, 1.0]]),                         // Tamaño par
+            matrix(&[&[1.0; 9]]),                           // Más grande que `max`
+            matrix(&[&[1.0, 2.0, 1.0], &[1.0, 2.0, 1.0]]),  // Ni una fila ni cuadrada
+            matrix(&[&[1.0, 2.0, 1.0], &[1.0], &[1.0]]),    // Filas de distinto tamaño
+            matrix(&[&[1.0], &[1.0], &[1.0]]),              // Una columna
+        ];
+        for value in invalid {
+            let error = spec.check(&value).unwrap_err();
+            assert!(error.contains("must be a kernel 1xN or NxN with N odd <= 7"), "{}", error);
+        }
+
+        assert!(spec.check(&text("blur")).unwrap_err().contains("must be kernel 1xN or NxN"));
+    }
+
+    #[test]
+    fn integers_are_converted_to_floats() {
+        let spec = ParamSpec::float("falloff", 1.0, 0.0, 1.0, "");
+        assert_eq!(spec.check(&ParamValue::Int(1)), Ok(ParamValue::Float(1.0)));
+        assert!(spec.check(&ParamValue::Int(2)).is_err());
+
+        let specs = [spec];
+        let values = BTreeMap::from([("falloff".to_string(), ParamValue::Int(0))]);
+        let set = ParamSet::new(&specs, &values).expect("Valid parameters");
+        assert_eq!(set.float("falloff"), 0.0);
+    }
+
+    #[test]
+    fn step_is_clamped_to_the_range() {
+        let int = ParamSpec::int("red", 0, 0, 255, "");
+        assert_eq!(int.step(&ParamValue::Int(100), 1), ParamValue::Int(112));
+        assert_eq!(int.step(&ParamValue::Int(250), 1), ParamValue::Int(255));
+        assert_eq!(int.step(&ParamValue::Int(5), -3), ParamValue::Int(0));
+
+        let small = ParamSpec::int("count", 1, 1, 4, "");
+        assert_eq!(small.step(&ParamValue::Int(1), 1), ParamValue::Int(2));
+
+        let float = ParamSpec::float("falloff", 1.0, 0.0, 1.0, "");
+        assert_eq!(float.step(&ParamValue::Float(1.0), 1), ParamValue::Float(1.0));
+        assert_eq!(float.step(&ParamValue::Float(0.02), -1), ParamValue::Float(0.0));
+        assert_eq!(float.step(&ParamValue::Int(0), 1), ParamValue::Float(0.05));
+
+        let bool = ParamSpec::bool("f24", true, "");
+        assert_eq!(bool.step(&ParamValue::Bool(true), 1), ParamValue::Bool(false));
+        assert_eq!(bool.step(&ParamValue::Bool(false), -1), ParamValue::Bool(true));
+
+        let color = ParamSpec::color("color", "random", "");
+        assert_eq!(color.step(&text("#102030"), 1), text("#102030"));
+    }
+
+    #[test]
+    fn step_wraps_around_the_choices() {
+        let spec = ParamSpec::choice("mode", "a", &["a", "b", "c"], "");
+
+        assert_eq!(spec.step(&text("a"), 1), text("b"));
+        assert_eq!(spec.step(&text("c"), 1), text("a"));
+        assert_eq!(spec.step(&text("a"), -1), text("c"));
+        assert_eq!(spec.step(&text("b"), -5), text("a"));
+
+        assert_eq!(spec.check(&text("b")), Ok(text("b")));
+        assert!(spec.check(&text("d")).is_err());
+        assert!(spec.check(&ParamValue::Int(1)).is_err());
+    }
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(parse_color("#ff8000"), Some(0xFFFF_8000));
+        assert_eq!(parse_color("#000000"), Some(0xFF00_0000));
+        assert_eq!(parse_color("#A0b0C0"), Some(0xFFA0_B0C0));
+
+        for raw in ["ff8000", "#ff800", "#ff80000", "#gg8000", "#+f8000", "random", ""] {
+            assert_eq!(parse_color(raw), None, "{:?}", raw);
+        }
+    }
+}
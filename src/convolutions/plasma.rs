@@ -0,0 +1,340 @@
+use std::f64::consts::TAU;
+use std::time::Duration;
+use crate::convolutions::effect::PixelEffect;
+use crate::convolutions::params::{self, Param, ParamSet, ParamSpec, ParamValue};
+use crate::convolutions::{FrameContext, Palette};
+
+/// Parameters of the plasma, shared by the sequential and the parallel version
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlasmaParams {
+    pub scale: f32,  // Ondas en la altura del frame
+    pub motion: f32, // Velocidad de las ondas, 0 las deja quietas
+    pub cycle: f32,  // Vueltas de la paleta por segundo, negativo hacia atrás
+    pub palette: String,
+}
+
+impl Default for PlasmaParams {
+    fn default() -> Self {
+        PlasmaParams {
+            scale: 2.0,
+            motion: 0.3,
+            cycle: 0.2,
+            palette: "aurora".to_string(),
+        }
+    }
+}
+
+impl PlasmaParams {
+    pub fn schema() -> Vec<ParamSpec> {
+        vec![
+            ParamSpec::float("scale", 2.0, 0.25, 16.0, "Waves along the height of the frame"),
+            ParamSpec::float("motion", 0.3, 0.0, 4.0, "Speed of the waves, 0 keeps them still and only the palette moves"),
+            ParamSpec::float("cycle", 0.2, -4.0, 4.0, "Turns of the palette per second, negative goes backwards"),
+            ParamSpec::choice("palette", "aurora", &Palette::NAMES, "Palette the plasma is mapped to"),
+        ]
+    }
+
+    pub fn from_params(params: &ParamSet) -> Self {
+        PlasmaParams {
+            scale: params.float("scale") as f32,
+            motion: params.float("motion") as f32,
+            cycle: params.float("cycle") as f32,
+            palette: params.text("palette").to_string(),
+        }
+    }
+
+    pub fn params(&self) -> Vec<Param> {
+        params::with_values(Self::schema(), [
+            ParamValue::Float(self.scale as f64),
+            ParamValue::Float(self.motion as f64),
+            ParamValue::Float(self.cycle as f64),
+            ParamValue::Text(self.palette.clone()),
+        ])
+    }
+
+    pub fn set(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        let value = params::validate(&Self::schema(), name, &value)?;
+
+        match name {
+            "scale" => self.scale = value.as_f64().unwrap_or_default() as f32,
+            "motion" => self.motion = value.as_f64().unwrap_or_default() as f32,
+            "cycle" => self.cycle = value.as_f64().unwrap_or_default() as f32,
+            _ => self.palette = value.as_str().unwrap_or_default().to_string(),
+        }
+
+        Ok(())
+    }
+}
+
+/// Classic demoscene plasma: a sum of sines over x, y, the distance to the
+/// center and the time gives an index in a [`Palette`]. The animation
+/// rotates that index, so the colors are never computed, only looked up.
+/// The value of each pixel is cached and only recomputed when the waves
+/// move, the scale changes or the frame is resized. The sequential and
+/// parallel versions only change the
+/// [`Executor`](crate::convolutions::effect::Executor)
+pub struct Plasma {
+    plasma: PlasmaParams,
+    palette: Palette,
+    time: f64,               // Tiempo de las ondas, avanza según `motion`
+    last: Option<Duration>,  // Tiempo de las animaciones en el último frame dibujado
+    rotation: f64,           // Desplazamiento del índice en la paleta, en vueltas
+    size: (usize, usize),    // Tamaño para el que se calcularon `radial` y `field`
+    radial: Vec<[f64; 2]>,   // Seno y coseno de la onda radial de cada píxel, sin el tiempo
+    field: Vec<f32>,         // Valor de cada píxel, entre 0 y 1
+    field_time: Option<f64>, // Tiempo con el que se calculó `field`, None si hay que recalcularlo
+    columns: Vec<f64>,       // Onda sobre x de cada columna en el tiempo de `field`
+    rows: Vec<f64>,          // Onda sobre y de cada fila
+    diagonals: Vec<f64>,     // Onda diagonal de cada x + y
+}
+
+impl Plasma {
+    pub fn new(plasma: PlasmaParams) -> Self {
+        Plasma {
+            palette: Palette::named(&plasma.palette).expect("Palette validated by the schema"),
+            plasma,
+            time: 0.0,
+            last: None,
+            rotation: 0.0,
+            size: (0, 0),
+            radial: Vec::new(),
+            field: Vec::new(),
+            field_time: None,
+            columns: Vec::new(),
+            rows: Vec::new(),
+            diagonals: Vec::new(),
+        }
+    }
+
+    /// Coordinates of the column `x` and the row `y`, relative to the height
+    /// and centered so the waves are round with any size
+    fn coordinates(&self, width: usize, height: usize) -> (impl Fn(usize) -> f64, impl Fn(usize) -> f64) {
+        let scale = self.plasma.scale as f64 * TAU / height.max(1) as f64;
+        let (half_width, half_height) = (width as f64 / 2.0, height as f64 / 2.0);
+
+        (move |x| (x as f64 - half_width) * scale, move |y| (y as f64 - half_height) * scale)
+    }
+
+    /// Sine and cosine of the radial wave of each pixel, the part of the
+    /// plasma that doesn't depend on the time
+    fn update_radial(&mut self, width: usize, height: usize) {
+        let (px, py) = self.coordinates(width, height);
+        let radial = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let (sin, cos) = ((px(x) * px(x) + py(y) * py(y)).sqrt() * 0.9).sin_cos();
+                [sin, cos]
+            })
+            .collect();
+
+        self.radial = radial;
+    }
+
+    /// Value of every pixel at the current time. Each wave over x, y or the
+    /// diagonal is computed once per column, row or diagonal, and the radial
+    /// wave is shifted in time with the angle addition, so there are no
+    /// sines per pixel
+    fn update_field(&mut self, width: usize, height: usize) {
+        let t = self.time;
+        let (px, py) = self.coordinates(width, height);
+        // Las tablas se reutilizan entre frames, solo crecen con el tamaño
+        self.columns.clear();
+        self.columns.extend((0..width).map(|x| (px(x) + t).sin()));
+        self.rows.clear();
+        self.rows.extend((0..height).map(|y| (py(y) * 0.8 - t * 0.7).sin()));
+        // px + py solo depende de x + y
+        self.diagonals.clear();
+        self.diagonals.extend((0..width + height).map(|d| ((px(d) + py(0)) * 0.5 + t * 0.4).sin()));
+        let (shift_sin, shift_cos) = (t * 1.3).sin_cos();
+
+        self.field.resize(width * height, 0.0);
+
+        for (y, line) in self.field.chunks_mut(width.max(1)).enumerate() {
+            for (x, value) in line.iter_mut().enumerate() {
+                let [sin, cos] = self.radial[y * width + x];
+                // sin(r - 1.3t) = sin(r)·cos(1.3t) - cos(r)·sin(1.3t)
+                let sum = self.columns[x] + self.rows[y] + self.diagonals[x + y] + sin * shift_cos - cos * shift_sin;
+                *value = ((sum / 4.0) * 0.5 + 0.5) as f32;
+            }
+        }
+
+        self.field_time = Some(t);
+    }
+}
+
+impl Default for Plasma {
+    fn default() -> Self {
+        Self::new(PlasmaParams::default())
+    }
+}
+
+impl PixelEffect for Plasma {
+    fn name(&self) -> &str {
+        "Plasma"
+    }
+
+    fn prepare(&mut self, width: usize, height: usize, ctx: &FrameContext) {
+        // Como en WaveFire, el tiempo avanza lo que pasó desde el último
+        // frame, así no salta al cambiar `motion` o `cycle`
+        let delta = self.last.map_or(0.0, |last| ctx.animation.saturating_sub(last).as_secs_f64());
+        self.last = Some(ctx.animation);
+        self.time += delta * self.plasma.motion as f64;
+        self.rotation = (self.rotation + delta * self.plasma.cycle as f64).rem_euclid(1.0);
+
+        if self.size != (width, height) || self.radial.is_empty() {
+            self.size = (width, height);
+            self.update_radial(width, height);
+            self.field_time = None;
+        }
+
+        // Con `motion` a 0 las ondas quedan quietas y solo gira la paleta
+        if self.field_time != Some(self.time) {
+            self.update_field(width, height);
+        }
+    }
+
+    fn pixel(&self, x: usize, y: usize, width: usize, _height: usize) -> u32 {
+        // La paleta se recorre de ida y vuelta, así al girar el índice no hay
+        // salto entre su último color y el primero
+        let last = (self.palette.len() - 1).max(1);
+        let turn = 2 * last;
+        let value = self.field[y * width + x] as f64;
+        let position = ((value * last as f64 + self.rotation * turn as f64) as usize) % turn;
+        let index = if position <= last { position } else { turn - position };
+
+        *self.palette.get(index).expect("Color not found in pallete")
+    }
+
+    fn params(&self) -> Vec<Param> {
+        self.plasma.params()
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        self.plasma.set(name, value)?;
+
+        match name {
+            "palette" => self.palette = Palette::named(&self.plasma.palette)?,
+            // La onda radial depende de la escala, se recalcula en el próximo frame
+            "scale" => self.radial.clear(),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn resize(&mut self, _width: usize, _height: usize) {
+        self.radial.clear();
+    }
+
+    fn reset(&mut self) {
+        self.time = 0.0;
+        self.rotation = 0.0;
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Local;
+    use crate::convolutions::effect::tests::assert_executors_agree;
+    use crate::convolutions::effect::EffectConvolution;
+    use super::*;
+
+    /// Value of the plasma at `(x, y)` computed directly, with its four sines
+    fn value(plasma: &Plasma, x: usize, y: usize, width: usize, height: usize) -> f64 {
+        let scale = plasma.plasma.scale as f64 * TAU / height as f64;
+        let px = (x as f64 - width as f64 / 2.0) * scale;
+        let py = (y as f64 - height as f64 / 2.0) * scale;
+        let t = plasma.time;
+
+        let sum = (px + t).sin()
+            + (py * 0.8 - t * 0.7).sin()
+            + ((px + py) * 0.5 + t * 0.4).sin()
+            + ((px * px + py * py).sqrt() * 0.9 - t * 1.3).sin();
+
+        (sum / 4.0) * 0.5 + 0.5
+    }
+
+    fn assert_field(plasma: &Plasma, width: usize, height: usize) {
+        assert_eq!(plasma.field.len(), width * height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let expected = value(plasma, x, y, width, height);
+                let cached = plasma.field[y * width + x] as f64;
+                assert!((cached - expected).abs() < 1e-5, "{} instead of {} at {:?}", cached, expected, (x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn the_field_follows_the_sum_of_sines() {
+        let mut plasma = Plasma::new(PlasmaParams { motion: 1.5, ..PlasmaParams::default() });
+        let mut ctx = FrameContext::new(Local::now());
+
+        plasma.prepare(30, 20, &ctx);
+        assert_field(&plasma, 30, 20);
+
+        ctx.animation = Duration::from_millis(2700);
+        plasma.prepare(30, 20, &ctx);
+        assert_field(&plasma, 30, 20);
+
+        // Al cambiar la escala o el tamaño se recalcula todo
+        plasma.set_param("scale", ParamValue::Float(5.0)).expect("Scale not set");
+        plasma.prepare(30, 20, &ctx);
+        assert_field(&plasma, 30, 20);
+
+        plasma.prepare(17, 41, &ctx);
+        assert_field(&plasma, 17, 41);
+    }
+
+    #[test]
+    fn still_waves_only_rotate_the_palette() {
+        let mut plasma = Plasma::new(PlasmaParams { motion: 0.0, cycle: 0.25, ..PlasmaParams::default() });
+        let mut ctx = FrameContext::new(Local::now());
+
+        plasma.prepare(16, 16, &ctx);
+        let field = plasma.field.clone();
+        let first: Vec<u32> = (0..16 * 16).map(|i| plasma.pixel(i % 16, i / 16, 16, 16)).collect();
+
+        ctx.animation = Duration::from_secs(1);
+        plasma.prepare(16, 16, &ctx);
+
+        assert_eq!(plasma.field_time, Some(0.0));
+        assert!(plasma.field == field);
+        assert!((plasma.rotation - 0.25).abs() < 1e-9);
+        assert!((0..16 * 16).any(|i| plasma.pixel(i % 16, i / 16, 16, 16) != first[i]));
+    }
+
+    #[test]
+    fn time_is_integrated() {
+        let mut plasma = Plasma::new(PlasmaParams { motion: 0.5, cycle: 0.5, ..PlasmaParams::default() });
+        let mut ctx = FrameContext::new(Local::now());
+
+        plasma.prepare(8, 8, &ctx);
+        ctx.animation = Duration::from_secs(2);
+        plasma.prepare(8, 8, &ctx);
+        assert!((plasma.time - 1.0).abs() < 1e-9);
+
+        // Al cambiar la velocidad el tiempo y la paleta siguen desde donde estaban
+        plasma.set_param("motion", ParamValue::Float(2.0)).expect("Motion not set");
+        plasma.set_param("cycle", ParamValue::Float(0.25)).expect("Cycle not set");
+        ctx.animation = Duration::from_secs(3);
+        plasma.prepare(8, 8, &ctx);
+        assert!((plasma.time - 3.0).abs() < 1e-9);
+        assert!((plasma.rotation - 0.25).abs() < 1e-9);
+        assert_field(&plasma, 8, 8);
+
+        // Reiniciar vuelve a empezar desde el siguiente frame
+        plasma.reset();
+        ctx.animation = Duration::from_secs(5);
+        plasma.prepare(8, 8, &ctx);
+        assert_eq!((plasma.time, plasma.rotation), (0.0, 0.0));
+        assert_field(&plasma, 8, 8);
+    }
+
+    #[test]
+    fn every_executor_gives_the_same_plasma() {
+        assert_executors_agree(53, 37, 5, |executor| EffectConvolution::new(Plasma::default(), executor));
+    }
+}
@@ -0,0 +1,458 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use tracing::warn;
+use crate::convolutions::bloom::BloomConvolution;
+use crate::convolutions::base::{SingleColorConvolution, ToGreenConvolution};
+use crate::convolutions::effect::{EffectConvolution, Executor};
+use crate::convolutions::fire::{DoomFire, DoomParams, FireConvolution, HeatFireConvolution, WaveFire, WaveParams};
+use crate::convolutions::kernel::KernelConvolution;
+use crate::convolutions::noise_field::{NoiseField, NoiseParams};
+use crate::convolutions::plasma::{Plasma, PlasmaParams};
+use crate::convolutions::params::{ParamSet, ParamSpec, ParamValue};
+use crate::convolutions::time::TimeConvolution;
+use crate::convolutions::ConvolutionType;
+
+/// Function that builds a layer from its validated parameters
+/// and the size of the frame
+pub type Constructor = fn(&ParamSet, usize, usize) -> ConvolutionType;
+
+/// Entry of the registry: name, schema and constructor of a layer
+pub struct LayerInfo {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub params: Vec<ParamSpec>,
+    pub constructor: Constructor,
+    /// The layer uses the rayon pool
+    pub parallel: bool,
+    /// Sequential version of a parallel layer, baseline of its speedup
+    pub baseline: Option<&'static str>,
+}
+
+/// Every layer that can be built by name, e.g. from a scene file
+pub struct Registry {
+    layers: Vec<LayerInfo>,
+    /// Old names of some layers, kept so the old scenes keep working
+    aliases: Vec<(&'static str, &'static str)>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry { layers: Vec::new(), aliases: Vec::new() }
+    }
+
+    /// Registry with the layers of the app
+    pub fn builtin() -> Self {
+        let mut registry = Registry::new();
+
+        registry.register(LayerInfo {
+            name: "single_color",
+            description: "Fills the frame with a solid color",
+            params: SingleColorConvolution::schema(),
+            constructor: |params, _, _| ConvolutionType::Simple(Box::new(SingleColorConvolution {
+                red: params.int("red") as u8,
+                green: params.int("green") as u8,
+                blue: params.int("blue") as u8,
+            })),
+            parallel: false,
+            baseline: None,
+        });
+
+        registry.register(LayerInfo {
+            name: "to_green",
+            description: "Fills the frame with pure green",
+            params: vec![],
+            constructor: |_, _, _| ConvolutionType::Simple(Box::new(ToGreenConvolution)),
+            parallel: false,
+            baseline: None,
+        });
+
+        registry.register(LayerInfo {
+            name: "fire",
+            description: "Random warm noise on every pixel",
+            params: FireConvolution::schema(),
+            constructor: |params, _, _| ConvolutionType::Simple(Box::new(FireConvolution {
+                intensity: params.float("intensity") as f32,
+            })),
+            parallel: false,
+            baseline: None,
+        });
+
+        registry.register(LayerInfo {
+            name: "heat_fire",
+            description: "Fire simulation, the heat rises from the bottom, spreads and cools",
+            params: HeatFireConvolution::schema(),
+            constructor: |params, width, height| ConvolutionType::Advanced(Box::new(HeatFireConvolution::new(
+                params.float("base_intensity") as f32,
+                params.float("cooling") as f32,
+                params.float("diffusion") as f32,
+                width,
+                height,
+            ))),
+            parallel: false,
+            baseline: None,
+        });
+
+        registry.register(LayerInfo {
+            name: "doom_fire",
+            description: "Classic Doom fire that rises from a seed line at the bottom, sequential version",
+            params: Self::doom_fire_schema(Executor::Sequential),
+            constructor: Self::doom_fire,
+            parallel: false,
+            baseline: None,
+        });
+
+        registry.register(LayerInfo {
+            name: "doom_fire_par",
+            description: "Classic Doom fire that rises from a seed line at the bottom, parallel version (rayon)",
+            params: Self::doom_fire_schema(Executor::RayonRows),
+            constructor: Self::doom_fire,
+            parallel: true,
+            baseline: Some("doom_fire"),
+        });
+
+        registry.register(LayerInfo {
+            name: "wave_fire",
+            description: "Waves of fire, sequential version",
+            params: Self::wave_fire_schema(Executor::Sequential),
+            constructor: Self::wave_fire,
+            parallel: false,
+            baseline: None,
+        });
+
+        registry.register(LayerInfo {
+            name: "wave_fire_par",
+            description: "Waves of fire, parallel version (rayon)",
+            params: Self::wave_fire_schema(Executor::RayonPixels),
+            constructor: Self::wave_fire,
+            parallel: true,
+            baseline: Some("wave_fire"),
+        });
+
+        // Nombres del fuego de Doom antes de que se llamara así
+        registry.alias("rising_fire", "doom_fire");
+        registry.alias("rising_fire_par", "doom_fire_par");
+
+        registry.register(LayerInfo {
+            name: "noise",
+            description: "Animated noise background (perlin, open simplex, worley, fbm, ridged), sequential version",
+            params: Self::noise_schema(Executor::Sequential),
+            constructor: Self::noise,
+            parallel: false,
+            baseline: None,
+        });
+
+        registry.register(LayerInfo {
+            name: "noise_par",
+            description: "Animated noise background (perlin, open simplex, worley, fbm, ridged), parallel version (rayon)",
+            params: Self::noise_schema(Executor::RayonRows),
+            constructor: Self::noise,
+            parallel: true,
+            baseline: Some("noise"),
+        });
+
+        registry.register(LayerInfo {
+            name: "plasma",
+            description: "Demoscene plasma with palette cycling, sequential version",
+            params: Self::plasma_schema(Executor::Sequential),
+            constructor: Self::plasma,
+            parallel: false,
+            baseline: None,
+        });
+
+        registry.register(LayerInfo {
+            name: "plasma_par",
+            description: "Demoscene plasma with palette cycling, parallel version (rayon)",
+            params: Self::plasma_schema(Executor::RayonRows),
+            constructor: Self::plasma,
+            parallel: true,
+            baseline: Some("plasma"),
+        });
+
+        registry.register(LayerInfo {
+            name: "kernel",
+            description: "Convolves the frame below with a kernel (blur, sharpen, edges, emboss, custom), sequential version",
+            params: KernelConvolution::schema(Executor::Sequential),
+            constructor: |params, _, _| ConvolutionType::Simple(Box::new(KernelConvolution::from_params(params))),
+            parallel: false,
+            baseline: None,
+        });
+
+        registry.register(LayerInfo {
+            name: "kernel_par",
+            description: "Convolves the frame below with a kernel (blur, sharpen, edges, emboss, custom), parallel version (rayon)",
+            params: KernelConvolution::schema(Executor::RayonRows),
+            constructor: |params, _, _| ConvolutionType::Simple(Box::new(KernelConvolution::from_params(params))),
+            parallel: true,
+            baseline: Some("kernel"),
+        });
+
+        registry.register(LayerInfo {
+            name: "bloom",
+            description: "Glow of the bright parts of the frame below, blurred at several scales, sequential version",
+            params: BloomConvolution::schema(Executor::Sequential),
+            constructor: Self::bloom,
+            parallel: false,
+            baseline: None,
+        });
+
+        registry.register(LayerInfo {
+            name: "bloom_par",
+            description: "Glow of the bright parts of the frame below, blurred at several scales, parallel version (rayon)",
+            params: BloomConvolution::schema(Executor::RayonRows),
+            constructor: Self::bloom,
+            parallel: true,
+            baseline: Some("bloom"),
+        });
+
+        registry.register(LayerInfo {
+            name: "time",
+            description: "Current time centered on the frame",
+            params: TimeConvolution::schema(),
+            constructor: |params, _, _| ConvolutionType::Simple(Box::new(TimeConvolution::new(
+                params.bool("f24"),
+                params.color("color"),
+            ))),
+            parallel: false,
+            baseline: None,
+        });
+
+        registry
+    }
+
+    fn doom_fire_schema(executor: Executor) -> Vec<ParamSpec> {
+        let mut schema = DoomParams::schema();
+        schema.push(Executor::spec(executor));
+        schema
+    }
+
+    fn doom_fire(params: &ParamSet, width: usize, height: usize) -> ConvolutionType {
+        let executor = params.text("executor").parse().expect("Executor validated by the schema");
+        ConvolutionType::Advanced(Box::new(DoomFire::new(DoomParams::from_params(params), executor, width, height)))
+    }
+
+    fn wave_fire_schema(executor: Executor) -> Vec<ParamSpec> {
+        let mut schema = WaveParams::schema();
+        schema.push(Executor::spec(executor));
+        schema
+    }
+
+    fn wave_fire(params: &ParamSet, _width: usize, _height: usize) -> ConvolutionType {
+        let mut effect = WaveFire::new();
+        effect.wave = WaveParams::from_params(params);
+
+        let executor = params.text("executor").parse().expect("Executor validated by the schema");
+        ConvolutionType::Advanced(Box::new(EffectConvolution::new(effect, executor)))
+    }
+
+    fn noise_schema(executor: Executor) -> Vec<ParamSpec> {
+        let mut schema = NoiseParams::schema();
+        schema.push(Executor::spec(executor));
+        schema
+    }
+
+    fn noise(params: &ParamSet, _width: usize, _height: usize) -> ConvolutionType {
+        let effect = NoiseField::new(NoiseParams::from_params(params));
+
+        let executor = params.text("executor").parse().expect("Executor validated by the schema");
+        ConvolutionType::Advanced(Box::new(EffectConvolution::new(effect, executor)))
+    }
+
+    fn plasma_schema(executor: Executor) -> Vec<ParamSpec> {
+        let mut schema = PlasmaParams::schema();
+        schema.push(Executor::spec(executor));
+        schema
+    }
+
+    fn plasma(params: &ParamSet, _width: usize, _height: usize) -> ConvolutionType {
+        let effect = Plasma::new(PlasmaParams::from_params(params));
+
+        let executor = params.text("executor").parse().expect("Executor validated by the schema");
+        ConvolutionType::Advanced(Box::new(EffectConvolution::new(effect, executor)))
+    }
+
+    fn bloom(params: &ParamSet, _width: usize, _height: usize) -> ConvolutionType {
+        ConvolutionType::Simple(Box::new(BloomConvolution::new(
+            params.float("intensity") as f32,
+            params.float("threshold") as f32,
+            params.int("radius") as usize,
+            params.int("levels") as usize,
+            params.text("executor").parse().expect("Executor validated by the schema"),
+        )))
+    }
+
+    /// Adds a layer, a previous layer with the same name is replaced
+    pub fn register(&mut self, info: LayerInfo) {
+        self.layers.retain(|layer| layer.name != info.name);
+        self.layers.push(info);
+    }
+
+    /// Adds a deprecated name of the layer `name`
+    pub fn alias(&mut self, alias: &'static str, name: &'static str) {
+        self.aliases.retain(|(old, _)| *old != alias);
+        self.aliases.push((alias, name));
+    }
+
+    /// Layer called `name`, also by a deprecated name
+    pub fn get(&self, name: &str) -> Option<&LayerInfo> {
+        let name = self.resolve(name).unwrap_or(name);
+        self.layers.iter().find(|layer| layer.name == name)
+    }
+
+    /// Current name of a deprecated name
+    fn resolve(&self, alias: &str) -> Option<&'static str> {
+        self.aliases.iter().find(|(old, _)| *old == alias).map(|(_, name)| *name)
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.layers.iter().map(|layer| layer.name).collect()
+    }
+
+    /// Builds the layer `name` validating `values` against its schema
+    pub fn create(
+        &self,
+        name: &str,
+        values: &BTreeMap<String, ParamValue>,
+        width: usize,
+        height: usize,
+    ) -> Result<ConvolutionType, String> {
+        let info = self.get(name)
+            .ok_or_else(|| format!("unknown layer type {:?} (expected one of {:?})", name, self.names()))?;
+
+        if let Some(current) = self.resolve(name) {
+            warn!("The layer type {:?} is deprecated, use {:?}", name, current);
+        }
+
+        let params = ParamSet::new(&info.params, values)?;
+        Ok((info.constructor)(&params, width, height))
+    }
+
+    /// Human readable list of the layers and their parameters
+    pub fn describe(&self) -> String {
+        let mut text = String::new();
+
+        for layer in self.layers.iter() {
+            let _ = writeln!(text, "{}\n    {}", layer.name, layer.description);
+
+            for param in layer.params.iter() {
+                let _ = writeln!(
+                    text,
+                    "    - {} ({}, default {}): {}",
+                    param.name, param.kind, param.default, param.help,
+                );
+            }
+        }
+
+        for (alias, name) in self.aliases.iter() {
+            let _ = writeln!(text, "{}\n    Deprecated, same as {}", alias, name);
+        }
+
+        text
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(pairs: &[(&str, ParamValue)]) -> BTreeMap<String, ParamValue> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.clone())).collect()
+    }
+
+    fn create_error(name: &str, pairs: &[(&str, ParamValue)]) -> String {
+        match Registry::builtin().create(name, &values(pairs), 8, 8) {
+            Err(message) => message,
+            Ok(_) => panic!("{} was built with {:?}", name, pairs),
+        }
+    }
+
+    #[test]
+    fn creates_every_layer_with_the_defaults() {
+        let registry = Registry::builtin();
+
+        for name in registry.names() {
+            assert!(registry.create(name, &BTreeMap::new(), 8, 8).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_layer_types() {
+        let message = create_error("lava", &[]);
+        assert!(message.contains("unknown layer type \"lava\""), "{}", message);
+        assert!(message.contains("\"heat_fire\""), "the known types aren't listed: {}", message);
+    }
+
+    #[test]
+    fn rejects_unknown_and_invalid_params() {
+        let message = create_error("fire", &[("heat", ParamValue::Float(0.5))]);
+        assert!(message.contains("unknown parameter \"heat\""), "{}", message);
+        assert!(message.contains("\"intensity\""), "the parameters aren't listed: {}", message);
+
+        let message = create_error("fire", &[("intensity", ParamValue::Float(2.0))]);
+        assert!(message.contains("out of range"), "{}", message);
+
+        let message = create_error("single_color", &[("red", ParamValue::Text("max".to_string()))]);
+        assert!(message.contains("must be int"), "{}", message);
+
+        let message = create_error("time", &[("color", ParamValue::Text("#12345".to_string()))]);
+        assert!(message.contains("isn't a color"), "{}", message);
+    }
+
+    #[test]
+    fn later_registrations_replace_the_layer() {
+        let mut registry = Registry::builtin();
+        let count = registry.names().len();
+
+        registry.register(LayerInfo {
+            name: "to_green",
+            description: "Replaced",
+            params: vec![],
+            constructor: |_, _, _| ConvolutionType::Simple(Box::new(ToGreenConvolution)),
+            parallel: false,
+            baseline: None,
+        });
+
+        assert_eq!(registry.names().len(), count);
+        assert_eq!(registry.get("to_green").map(|info| info.description), Some("Replaced"));
+    }
+
+    #[test]
+    fn describes_the_layers_and_their_params() {
+        let text = Registry::builtin().describe();
+
+        for name in Registry::builtin().names() {
+            assert!(text.lines().any(|line| line == name), "{} isn't listed:\n{}", name, text);
+        }
+
+        assert!(text.contains("single_color\n    Fills the frame with a solid color\n"), "{}", text);
+        assert!(text.contains("    - red (int 0..=255, default 0): Red channel\n"), "{}", text);
+        assert!(text.contains("    - intensity (float 0.01..=1, default 1): Maximum brightness of the flames\n"), "{}", text);
+        assert!(text.contains("    - f24 (bool, default true): 24 hours format"), "{}", text);
+        assert!(text.contains("    - color (color \"#rrggbb\" | \"random\", default \"random\"): Color of the digits"), "{}", text);
+    }
+
+    #[test]
+    fn old_names_build_the_doom_fire() {
+        let registry = Registry::builtin();
+
+        for (alias, name) in [("rising_fire", "doom_fire"), ("rising_fire_par", "doom_fire_par")] {
+            assert_eq!(registry.get(alias).map(|info| info.name), Some(name));
+
+            let layer = registry.create(alias, &BTreeMap::new(), 8, 8).expect("Alias not built");
+            assert_eq!(layer.name(), "DoomFire");
+        }
+
+        // Los alias no se listan como capas pero sí se describen
+        assert!(!registry.names().contains(&"rising_fire"));
+        assert!(registry.describe().contains("rising_fire\n    Deprecated, same as doom_fire"));
+
+        let waves = registry.create("wave_fire_par", &BTreeMap::new(), 8, 8).expect("Wave fire not built");
+        assert_eq!(waves.name(), "WaveFire");
+        assert_eq!(registry.get("doom_fire_par").and_then(|info| info.baseline), Some("doom_fire"));
+    }
+}
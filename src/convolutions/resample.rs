@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use rayon::prelude::*;
+
+/// Smallest internal scale of a layer or of the frame
+pub const MIN_SCALE: f32 = 0.25;
+/// Largest internal scale of a layer or of the frame
+pub const MAX_SCALE: f32 = 2.0;
+/// Taps kept by a [`Resampler`], when there are more (e.g. after many
+/// resizes of the window) they are computed again
+const MAX_TAPS: usize = 32;
+
+/// Filter used to resample a surface drawn at another resolution
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Filter {
+    /// The nearest pixel, keeps the pixelated look
+    #[default]
+    Nearest,
+    /// Linear interpolation of the 2x2 nearest pixels
+    Bilinear,
+    /// Catmull-Rom interpolation of the 4x4 nearest pixels
+    Bicubic,
+}
+
+impl Filter {
+    pub const NAMES: [&'static str; 3] = ["nearest", "bilinear", "bicubic"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filter::Nearest => "nearest",
+            Filter::Bilinear => "bilinear",
+            Filter::Bicubic => "bicubic",
+        }
+    }
+
+    /// Source pixels on each side of a destination pixel, and the weight
+    /// of a source pixel at some distance
+    fn kernel(&self) -> (usize, fn(f32) -> f32) {
+        match self {
+            Filter::Nearest | Filter::Bilinear => (1, linear),
+            Filter::Bicubic => (2, cubic),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "nearest" => Ok(Filter::Nearest),
+            "bilinear" => Ok(Filter::Bilinear),
+            "bicubic" => Ok(Filter::Bicubic),
+            _ => Err(format!("unknown filter {:?} (expected one of {:?})", name, Filter::NAMES)),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Checks that a scale is inside `MIN_SCALE..=MAX_SCALE`
+pub fn check_scale(scale: f32) -> Result<f32, String> {
+    if (MIN_SCALE..=MAX_SCALE).contains(&scale) {
+        Ok(scale)
+    } else {
+        Err(format!("scale {} is out of range {}..={}", scale, MIN_SCALE, MAX_SCALE))
+    }
+}
+
+/// Resamples taking the nearest pixel
+pub fn nearest(src: &[u32], src_width: usize, src_height: usize, dst: &mut [u32], width: usize, height: usize) {
+    dst.par_chunks_mut(width).take(height).enumerate().for_each(|(y, row)| {
+        let sy = y * src_height / height;
+        let line = &src[sy * src_width..(sy + 1) * src_width];
+
+        for (x, pixel) in row.iter_mut().enumerate() {
+            *pixel = line[x * src_width / width];
+        }
+    });
+}
+
+/// Weight of a pixel at distance `t` for the bilinear filter
+fn linear(t: f32) -> f32 {
+    (1.0 - t.abs()).max(0.0)
+}
+
+/// Weight of a pixel at distance `t` for the Catmull-Rom filter
+fn cubic(t: f32) -> f32 {
+    let t = t.abs();
+
+    if t < 1.0 {
+        1.5 * t * t * t - 2.5 * t * t + 1.0
+    } else if t < 2.0 {
+        -0.5 * t * t * t + 2.5 * t * t - 4.0 * t + 2.0
+    } else {
+        0.0
+    }
+}
+
+/// Resamples surfaces with a [`Filter`], keeps the taps of the sizes it
+/// resampled so the same resample doesn't allocate them again each frame
+#[derive(Default)]
+pub struct Resampler {
+    taps: HashMap<(usize, usize, Filter), Taps>,
+}
+
+impl Resampler {
+    /// Resamples `src` (`src_width` x `src_height`) to fill `dst`
+    /// (`width` x `height`), the rows are resampled in parallel
+    pub fn resample(&mut self, filter: Filter, src: &[u32], (src_width, src_height): (usize, usize), dst: &mut [u32], (width, height): (usize, usize)) {
+        if filter == Filter::Nearest {
+            nearest(src, src_width, src_height, dst, width, height);
+            return;
+        }
+
+        if self.taps.len() + 2 > MAX_TAPS {
+            self.taps.clear();
+        }
+
+        for key in [(src_width, width, filter), (src_height, height, filter)] {
+            self.taps.entry(key).or_insert_with(|| Taps::new(key.0, key.1, filter));
+        }
+
+        let columns = &self.taps[&(src_width, width, filter)];
+        let rows = &self.taps[&(src_height, height, filter)];
+        interpolate(src, src_width, dst, (width, height), columns, rows);
+    }
+}
+
+/// Source pixels and their weights for each destination coordinate, the
+/// centers of the pixels are aligned and the borders are repeated
+struct Taps {
+    count: usize, // Pixels de origen de cada coordenada
+    taps: Vec<(usize, f32)>,
+}
+
+impl Taps {
+    fn new(src_size: usize, size: usize, filter: Filter) -> Self {
+        let (radius, kernel) = filter.kernel();
+        let ratio = src_size as f32 / size as f32;
+
+        let taps = (0..size)
+            .flat_map(|i| {
+                let center = (i as f32 + 0.5) * ratio - 0.5;
+                let first = center.floor() as isize - radius as isize + 1;
+
+                (first..first + 2 * radius as isize)
+                    .map(move |s| (s.clamp(0, src_size as isize - 1) as usize, kernel(center - s as f32)))
+            })
+            .collect();
+
+        Taps { count: 2 * radius, taps }
+    }
+
+    fn get(&self, i: usize) -> &[(usize, f32)] {
+        &self.taps[i * self.count..(i + 1) * self.count]
+    }
+}
+
+/// Separable interpolation of premultiplied colors: each color weighs as
+/// much as its alpha, so the transparent pixels don't darken the borders
+fn interpolate(src: &[u32], src_width: usize, dst: &mut [u32], (width, height): (usize, usize), columns: &Taps, rows: &Taps) {
+    dst.par_chunks_mut(width).take(height).enumerate().for_each(|(y, row)| {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let mut channels = [0f32; 4];
+
+            for &(sy, wy) in rows.get(y) {
+                let line = &src[sy * src_width..(sy + 1) * src_width];
+
+                for &(sx, wx) in columns.get(x) {
+                    let color = line[sx];
+                    let alpha = (color >> 24) as f32 * wx * wy;
+
+                    channels[0] += alpha;
+                    for (channel, value) in channels[1..].iter_mut().enumerate() {
+                        *value += ((color >> (16 - channel * 8)) & 0xFF) as f32 * alpha;
+                    }
+                }
+            }
+
+            let [alpha, red, green, blue] = channels;
+            *pixel = if alpha <= 0.0 {
+                0
+            } else {
+                [red, green, blue].iter().fold(alpha.round().min(255.0) as u32, |color, value| {
+                    (color << 8) | (value / alpha).round().clamp(0.0, 255.0) as u32
+                })
+            };
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [Filter; 3] = [Filter::Nearest, Filter::Bilinear, Filter::Bicubic];
+
+    /// Surface with a different color in every pixel
+    fn pattern(width: usize, height: usize) -> Vec<u32> {
+        (0..width * height)
+            .map(|i| 0x80000000 | ((i * 37 % 256) << 16) as u32 | ((i * 91 % 256) << 8) as u32 | (i * 13 % 256) as u32)
+            .collect()
+    }
+
+    #[test]
+    fn same_size_is_a_copy() {
+        let mut resampler = Resampler::default();
+        let src = pattern(13, 7);
+
+        for filter in FILTERS {
+            let mut dst = vec![0; src.len()];
+            resampler.resample(filter, &src, (13, 7), &mut dst, (13, 7));
+            assert_eq!(dst, src, "{} changed the surface", filter);
+        }
+    }
+
+    #[test]
+    fn constant_surfaces_stay_constant() {
+        let mut resampler = Resampler::default();
+
+        for color in [0xFF4080C0, 0x80C86420, 0x00000000] {
+            for filter in FILTERS {
+                for (src_size, size) in [((16, 9), (41, 23)), ((41, 23), (16, 9)), ((5, 5), (6, 4))] {
+                    let src = vec![color; src_size.0 * src_size.1];
+                    let mut dst = vec![1; size.0 * size.1];
+                    resampler.resample(filter, &src, src_size, &mut dst, size);
+                    assert!(dst.iter().all(|&pixel| pixel == color), "{} changed {:08X}", filter, color);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn transparent_pixels_dont_darken_the_borders() {
+        let mut resampler = Resampler::default();
+        // Mitad roja opaca, mitad transparente
+        let src: Vec<u32> = (0..8 * 4).map(|i| if i % 8 < 4 { 0xFFFF0000 } else { 0x00000000 }).collect();
+
+        for filter in [Filter::Bilinear, Filter::Bicubic] {
+            let mut dst = vec![0; 32 * 16];
+            resampler.resample(filter, &src, (8, 4), &mut dst, (32, 16));
+
+            let row = &dst[..32];
+            assert!(row.iter().any(|&pixel| (1..255).contains(&(pixel >> 24))), "{} has no soft edge", filter);
+            assert!(row.iter().all(|&pixel| pixel >> 24 == 0 || pixel & 0xFFFFFF == 0xFF0000), "{} darkened the edge", filter);
+            assert!(row.windows(2).all(|pair| pair[0] >> 24 >= pair[1] >> 24), "{} alpha isn't monotonic", filter);
+        }
+    }
+
+    #[test]
+    fn keeps_the_taps_of_each_size() {
+        let mut resampler = Resampler::default();
+        let src = pattern(20, 10);
+        let mut dst = vec![0; 10 * 5];
+
+        for _ in 0..3 {
+            resampler.resample(Filter::Bilinear, &src, (20, 10), &mut dst, (10, 5));
+        }
+        assert_eq!(resampler.taps.len(), 2);
+
+        // Los mismos tamaños con otro filtro tienen sus propios taps
+        resampler.resample(Filter::Bicubic, &src, (20, 10), &mut dst, (10, 5));
+        assert_eq!(resampler.taps.len(), 4);
+        assert_eq!(resampler.taps[&(20, 10, Filter::Bicubic)].get(3).len(), 4);
+    }
+}
@@ -1,17 +1,93 @@
+use std::fmt::Write;
+use minifb_fonts::font6x8::{self, CHAR_BITMAP_PADDING, CHAR_HEIGHT, CHAR_WIDTH};
+use minifb_fonts::FbFontRenderer;
 use rand::Rng;
-use crate::convolutions::{Color, Convolution};
+use crate::convolutions::params::{self, Param, ParamSpec, ParamValue};
+use crate::convolutions::{Color, Convolution, Damage, FrameContext, Rect};
 
 pub struct TimeConvolution {
     pub f24: bool, // Si se usa formato de 24 horas o no
-    pub color: Option<u32>
+    pub color: Option<u32>,
+    text: String,
+    glyphs: Vec<u32>, // Buffer del tamaño del texto, se reutiliza entre frames
+    renderer: Option<FbFontRenderer>,
+    origin: (usize, usize), // Esquina superior izquierda del texto en el frame
 }
 
 impl TimeConvolution {
+    pub fn new(f24: bool, color: Option<u32>) -> Self {
+        TimeConvolution {
+            f24,
+            color,
+            text: String::new(),
+            glyphs: Vec::new(),
+            renderer: None,
+            origin: (0, 0),
+        }
+    }
+
+    pub fn schema() -> Vec<ParamSpec> {
+        vec![
+            ParamSpec::bool("f24", true, "24 hours format, 12 hours with AM/PM otherwise"),
+            ParamSpec::color("color", "random", "Color of the digits, random on every frame by default"),
+        ]
+    }
+
+    fn format(&self) -> &'static str {
+        if self.f24 {
+            "%H:%M:%S" // 24 horas
+        } else {
+            "%I:%M:%S%p" // 12 horas con AM/PM
+        }
+    }
+
+    /// Position of the text centered on the frame
+    fn origin(len: usize, width: usize, height: usize) -> (usize, usize) {
+        let left = (width + 7).saturating_sub(len * 5) / 2;
+        let top = height.saturating_sub(7) / 2;
+
+        (left, top)
+    }
+
     fn random_color(&self) -> u32 {
         let mut rng = rand::thread_rng();
 
         Color::hsv(rng.gen_range(0.0..=360.), 0.8, 0.6)
     }
+
+    /// Draws `self.text` on the glyph buffer, the renderer is created
+    /// again only when the length of the text changes
+    fn draw_glyphs(&mut self, color: u32) {
+        let width = self.text.len() * CHAR_WIDTH;
+        let height = CHAR_HEIGHT + CHAR_BITMAP_PADDING;
+
+        let renderer = match &mut self.renderer {
+            Some(renderer) if renderer.buf_width == width => renderer,
+            renderer => {
+                self.glyphs.resize(width * height, Color::TRANSPARENT);
+                renderer.insert(font6x8::new_renderer(width, height, color))
+            }
+        };
+
+        renderer.set_color(color);
+        self.glyphs.fill(Color::TRANSPARENT);
+        renderer.draw_text(&mut self.glyphs, 0, 0, self.text.as_str());
+    }
+}
+
+/// Writer that compares the text written on it with `last`
+struct SameText<'a> {
+    last: &'a str,
+    len: usize,
+    same: bool,
+}
+
+impl Write for SameText<'_> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.same &= self.last.get(self.len..self.len + s.len()) == Some(s);
+        self.len += s.len();
+        Ok(())
+    }
 }
 
 impl Convolution for TimeConvolution {
@@ -19,36 +95,93 @@ impl Convolution for TimeConvolution {
         "Time"
     }
 
-    fn transform(&mut self, pixels: &mut [u32], width: usize, height: usize) {
-        let time = chrono::Local::now();
+    fn transform(&mut self, pixels: &mut [u32], width: usize, height: usize, ctx: &FrameContext) {
+        self.prepare_tiles(width, height, ctx);
+        self.transform_tile(pixels, 0, width, height);
+    }
 
-        let time_string = if self.f24 {
-            time.format("%H:%M:%S").to_string() // 24 horas
-        } else {
-            time.format("%I:%M:%S%p").to_string() // 12 horas con AM/PM
-        };
+    // Con color aleatorio cambia en cada frame, si no solo cuando cambia el
+    // texto y solo en la zona del texto anterior y del nuevo
+    fn damage(&self, width: usize, height: usize, ctx: &FrameContext) -> Damage {
+        if self.color.is_none() {
+            return Damage::Full;
+        }
+
+        // Se compara con el texto anterior sin formatear en un String nuevo
+        let mut text = SameText { last: &self.text, len: 0, same: true };
+        write!(text, "{}", ctx.now.format(self.format())).expect("The time can't be formatted");
+        if text.same && text.len == self.text.len() {
+            return Damage::None;
+        }
+
+        let (left, top) = Self::origin(text.len, width, height);
+        let current = Rect::new(left, top, text.len * CHAR_WIDTH, CHAR_HEIGHT + CHAR_BITMAP_PADDING);
+        let (left, top) = self.origin;
+        let last = Rect::new(left, top, self.text.len() * CHAR_WIDTH, CHAR_HEIGHT + CHAR_BITMAP_PADDING);
+
+        Damage::Rects(vec![current.union(&last)])
+    }
+
+    fn prepare_tiles(&mut self, width: usize, height: usize, ctx: &FrameContext) -> bool {
+        self.text.clear();
+        write!(self.text, "{}", ctx.now.format(self.format())).expect("The time can't be formatted");
 
-        let mut new_buffer = vec![0; width * height];
         let color: u32 = match &self.color {
             Some(c) => *c,
             None => self.random_color(),
         };
 
-        let font_render = minifb_fonts::font6x8::new_renderer(
-            width,
-            height,
-            color,
-        );
+        self.draw_glyphs(color);
+
+        self.origin = Self::origin(self.text.len(), width, height);
 
-        font_render.draw_text(&mut new_buffer,
-                              (width - time_string.len() * 5 + 7) / 2,
-                              (height - 7) / 2,
-                              time_string.as_str());
+        true
+    }
+
+    fn transform_tile(&self, pixels: &mut [u32], top: usize, width: usize, _height: usize) {
+        let text_width = self.text.len() * CHAR_WIDTH;
+        let (left, text_top) = self.origin;
+        let rows = pixels.len() / width;
 
-        for i in 0..pixels.len() {
-            if new_buffer[i] != 0 {
-                pixels[i] = new_buffer[i];
+        if text_width == 0 {
+            return;
+        }
+
+        // Copiamos solo los píxeles del texto dentro de la franja
+        for (y, row) in self.glyphs.chunks_exact(text_width).enumerate() {
+            let py = text_top + y;
+            if py < top {
+                continue;
+            }
+            if py >= top + rows {
+                break;
+            }
+
+            let line = &mut pixels[(py - top) * width..(py - top + 1) * width];
+            for (x, glyph) in row.iter().enumerate() {
+                let px = left + x;
+                if px < width && *glyph != Color::TRANSPARENT {
+                    line[px] = *glyph;
+                }
             }
         }
     }
-}
\ No newline at end of file
+
+    fn params(&self) -> Vec<Param> {
+        params::with_values(Self::schema(), [
+            ParamValue::Bool(self.f24),
+            ParamValue::from_color(self.color),
+        ])
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        let value = params::validate(&Self::schema(), name, &value)?;
+
+        match name {
+            "f24" => self.f24 = value.as_bool().unwrap_or(self.f24),
+            _ => self.color = value.as_color(),
+        }
+
+        Ok(())
+    }
+}
@@ -1,10 +1,19 @@
 use std::env;
+use std::process;
+use crate::cli::{Command, Options};
+use crate::convolutions::registry::Registry;
 
 pub mod app;
+pub mod bench;
+pub mod cli;
+pub mod clock;
 pub mod convolutions;
-
-const WIDTH: usize = 480;
-const HEIGHT: usize = 360;
+pub mod pacing;
+pub mod pipeline;
+pub mod quality;
+pub mod scene;
+pub mod target;
+pub mod triple_buffer;
 
 /// # Widget clock with procedural background
 /// Program that show the hour and have a beautiful and customizable background
@@ -18,15 +27,38 @@ fn main() {
 
     let args: Vec<String> = env::args().collect();
 
-    let width = args.get(1)
-        .and_then(|s| s.parse::<usize>().ok())
-        .unwrap_or(WIDTH);
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            process::exit(2);
+        }
+    };
+
+    match &options.command {
+        Command::Run => {}
+        Command::ListLayers => {
+            print!("{}", Registry::builtin().describe());
+            return;
+        }
+        Command::Bench(bench) | Command::Sweep(bench) => {
+            let result = match &options.command {
+                Command::Sweep(_) => bench::sweep_report(bench),
+                _ => bench::report(bench),
+            };
 
-    let height = args.get(2)
-        .and_then(|s| s.parse::<usize>().ok())
-        .unwrap_or(HEIGHT);
+            if let Err(error) = result {
+                eprintln!("error: {}", error);
+                process::exit(1);
+            }
+            return;
+        }
+    }
 
     tracing::info!("starting app");
-    app::run(width, height);
+    if let Err(error) = app::run(&options) {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
     tracing::info!("Closed app");
 }
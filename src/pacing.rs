@@ -0,0 +1,214 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Frames per second used when none is given
+pub const DEFAULT_FPS: f64 = 60.0;
+
+/// Time before the deadline that is waited spinning instead of sleeping,
+/// the sleep of the OS can wake up late by about a millisecond
+const SPIN: Duration = Duration::from_micros(1500);
+
+/// How often the loop of the app runs, in every mode the frames are
+/// presented only when their content changes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FramePacing {
+    /// A frame every `1 / fps` seconds
+    Fixed(f64),
+    /// As fast as possible
+    Unlimited,
+    /// The input and the changes are checked `fps` times per second, the
+    /// same as [`FramePacing::Fixed`] since the frames that don't change
+    /// are never presented. Kept for `--on-change`
+    OnChange(f64),
+}
+
+impl Default for FramePacing {
+    fn default() -> Self {
+        FramePacing::Fixed(DEFAULT_FPS)
+    }
+}
+
+impl FramePacing {
+    /// Time between two iterations of the loop, `None` if unlimited
+    pub fn period(&self) -> Option<Duration> {
+        match self {
+            FramePacing::Fixed(fps) | FramePacing::OnChange(fps) => Some(Duration::from_secs_f64(1.0 / fps)),
+            FramePacing::Unlimited => None,
+        }
+    }
+}
+
+/// Parses `unlimited` or the frames per second
+impl FromStr for FramePacing {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value == "unlimited" {
+            return Ok(FramePacing::Unlimited);
+        }
+
+        match value.parse::<f64>() {
+            Ok(fps) if fps.is_finite() && fps > 0.0 => Ok(FramePacing::Fixed(fps)),
+            _ => Err(format!("invalid frame rate {:?} (expected a positive number or unlimited)", value)),
+        }
+    }
+}
+
+impl fmt::Display for FramePacing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FramePacing::Fixed(fps) => write!(f, "{} fps", fps),
+            FramePacing::Unlimited => write!(f, "unlimited"),
+            FramePacing::OnChange(fps) => write!(f, "on change (checked at {} fps)", fps),
+        }
+    }
+}
+
+/// Waits between the iterations of the loop and measures the frames
+/// that were really rendered
+pub struct Pacer {
+    pub pacing: FramePacing,
+    deadline: Instant,
+    started: Instant,
+    frames: u64,
+    // Ventana de un segundo para los fps logrados
+    window_start: Instant,
+    window_frames: u64,
+    fps: f64,
+}
+
+impl Pacer {
+    pub fn new(pacing: FramePacing) -> Self {
+        let now = Instant::now();
+
+        Pacer {
+            pacing,
+            deadline: now,
+            started: now,
+            frames: 0,
+            window_start: now,
+            window_frames: 0,
+            fps: 0.0,
+        }
+    }
+
+    /// Restarts the timers, called when the loop starts
+    pub fn start(&mut self) {
+        *self = Pacer::new(self.pacing);
+    }
+
+    /// Counts a rendered frame, returns `true` when the achieved frame
+    /// rate of the last second is updated
+    pub fn frame_rendered(&mut self) -> bool {
+        self.frames += 1;
+        self.window_frames += 1;
+        self.update_fps()
+    }
+
+    fn update_fps(&mut self) -> bool {
+        let elapsed = self.window_start.elapsed();
+        if elapsed < Duration::from_secs(1) {
+            return false;
+        }
+
+        self.fps = self.window_frames as f64 / elapsed.as_secs_f64();
+        self.window_start = Instant::now();
+        self.window_frames = 0;
+        true
+    }
+
+    /// Frames per second achieved during the last second
+    pub fn fps(&self) -> f64 {
+        self.fps
+    }
+
+    /// Frames rendered since the start
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Time since the start
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Average frames per second since the start
+    pub fn average_fps(&self) -> f64 {
+        self.frames as f64 / self.started.elapsed().as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// Sleeps until the next iteration, returns `true` when the achieved
+    /// frame rate is updated (e.g. it drops to 0 while nothing changes)
+    pub fn wait(&mut self) -> bool {
+        let Some(period) = self.pacing.period() else {
+            return self.update_fps();
+        };
+
+        // Si el frame tardó más que el periodo no se intenta recuperar
+        // el tiempo perdido, el siguiente empieza ya
+        let now = Instant::now();
+        self.deadline = (self.deadline + period).max(now);
+
+        let remaining = self.deadline - now;
+        if remaining > SPIN {
+            std::thread::sleep(remaining - SPIN);
+        }
+        while Instant::now() < self.deadline {
+            std::thread::yield_now();
+        }
+
+        self.update_fps()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn periods() {
+        assert_eq!(FramePacing::Fixed(60.0).period(), Some(Duration::from_secs_f64(1.0 / 60.0)));
+        assert_eq!(FramePacing::Fixed(4.0).period(), Some(Duration::from_millis(250)));
+        assert_eq!(FramePacing::Fixed(0.5).period(), Some(Duration::from_secs(2)));
+        assert_eq!(FramePacing::Unlimited.period(), None);
+        assert_eq!(FramePacing::OnChange(10.0).period(), Some(Duration::from_millis(100)));
+        assert_eq!(FramePacing::default(), FramePacing::Fixed(DEFAULT_FPS));
+    }
+
+    #[test]
+    fn parses_frame_rates() {
+        assert_eq!("unlimited".parse(), Ok(FramePacing::Unlimited));
+        assert_eq!("30".parse(), Ok(FramePacing::Fixed(30.0)));
+        assert_eq!("29.97".parse(), Ok(FramePacing::Fixed(29.97)));
+
+        for raw in ["0", "-60", "0.0", "NaN", "inf", "", "fast", "Unlimited"] {
+            assert!(raw.parse::<FramePacing>().is_err(), "{:?} should be rejected", raw);
+        }
+
+        assert_eq!(FramePacing::Fixed(30.0).to_string(), "30 fps");
+        assert_eq!(FramePacing::Unlimited.to_string(), "unlimited");
+        assert_eq!(FramePacing::OnChange(60.0).to_string(), "on change (checked at 60 fps)");
+    }
+
+    #[test]
+    fn waits_the_period() {
+        let mut pacer = Pacer::new(FramePacing::Fixed(200.0));
+        pacer.start();
+
+        let start = Instant::now();
+        for _ in 0..5 {
+            pacer.frame_rendered();
+            pacer.wait();
+        }
+
+        // Cinco periodos de 5 ms, nunca antes
+        assert!(start.elapsed() >= Duration::from_millis(25));
+        assert_eq!(pacer.frames(), 5);
+
+        let mut unlimited = Pacer::new(FramePacing::Unlimited);
+        let start = Instant::now();
+        unlimited.wait();
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+}
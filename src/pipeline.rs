@@ -0,0 +1,649 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use rayon::prelude::*;
+use tracing::{info, trace};
+use crate::convolutions::{blend, BlendMode, Color, ConvolutionType, Damage, Filter, FrameContext, Layer, Resampler};
+
+/// Pixels of each tile of the tiled renderer, a band of rows of every
+/// surface fits in the L2 cache of a core
+pub const TILE_PIXELS: usize = 16 * 1024;
+
+/// Part of a layer that draws a tile: the convolution (`None` if the
+/// surface is reused), its compositing options, the band of its surface
+/// and the time spent drawing the layer, in nanoseconds
+type TilePart<'a> = (Option<&'a ConvolutionType>, f32, BlendMode, &'a mut [u32], &'a AtomicU64);
+
+/// Frame pipeline of the app
+///
+/// Owns the layer stack and every buffer needed to render it: each layer
+/// keeps its own surface between frames and the frame is composited on a
+/// back buffer that is swapped with the presented one, so the pixel buffers
+/// are only allocated again when the size changes.
+///
+/// With `tiled` the frame is split in bands of rows and each band runs the
+/// whole stack of active layers in the thread pool; the layers that can't
+/// be drawn by tiles are drawn on the whole frame in their place of the stack.
+/// The list of tiles borrows the layers, so it's built again on each frame;
+/// it grows with the number of layers and bands, not with the pixels.
+///
+/// The layers that read the frame (filters such as the kernels) get the
+/// frame composited by the layers below them as input.
+///
+/// The layers report the part of the frame that changed ([`Damage`]): if
+/// nothing changed the frame isn't rendered, and if only some rectangles
+/// changed just the rows they cover are drawn again on the presented frame.
+/// Like the list of tiles, the damaged rectangles and rows are built on
+/// each frame and grow with the number of layers, not with the pixels.
+///
+/// With a `scale` different from 1 the whole stack is drawn at that
+/// resolution and resampled to the size of the frame with `filter`.
+pub struct Pipeline {
+    pub layers: Vec<Layer>,
+    pub tiled: bool,
+    pub filter: Filter,
+    scale: f32,
+    /// The stack or the size changed since the last frame
+    dirty: bool,
+    /// Number of calls to `render`, to update the layers with an interval
+    tick: u64,
+    /// Size at which the layers are drawn
+    render_width: usize,
+    render_height: usize,
+    front: Vec<u32>,
+    back: Vec<u32>,
+    /// Size of the presented frame
+    width: usize,
+    height: usize,
+    /// Resampled frame, empty if the scale is 1
+    output: Vec<u32>,
+    resampler: Resampler,
+}
+
+impl Pipeline {
+    pub fn new(width: usize, height: usize) -> Self {
+        Pipeline {
+            layers: Vec::new(),
+            tiled: false,
+            filter: Filter::Nearest,
+            scale: 1.0,
+            dirty: true,
+            tick: 0,
+            render_width: width,
+            render_height: height,
+            front: vec![Color::rgb(0, 0, 0); width * height],
+            back: vec![Color::rgb(0, 0, 0); width * height],
+            width,
+            height,
+            output: Vec::new(),
+            resampler: Resampler::default(),
+        }
+    }
+
+    /// Width of the presented frame
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Height of the presented frame
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Changes the internal resolution of the whole stack
+    pub fn set_scale(&mut self, scale: f32) {
+        if scale != self.scale {
+            info!("Rendering the frame at {}x", scale);
+            self.scale = scale;
+            self.allocate();
+        }
+    }
+
+    /// Changes the size of the frame, the frame buffers are allocated again
+    /// and the layers are notified the next time they are drawn
+    pub fn resize(&mut self, width: usize, height: usize) {
+        if (width, height) == (self.width, self.height) {
+            return;
+        }
+
+        info!("Resizing frame to {}x{}", width, height);
+        self.width = width;
+        self.height = height;
+        self.allocate();
+    }
+
+    fn allocate(&mut self) {
+        let (width, height) = (self.width, self.height);
+
+        if self.scale == 1.0 {
+            (self.render_width, self.render_height) = (width, height);
+            self.output = Vec::new();
+        } else {
+            let scaled = |size: usize| ((size as f32 * self.scale).round() as usize).max(1);
+            (self.render_width, self.render_height) = (scaled(width), scaled(height));
+            self.output = vec![Color::rgb(0, 0, 0); width * height];
+        }
+
+        self.front = vec![Color::rgb(0, 0, 0); self.render_width * self.render_height];
+        self.back = vec![Color::rgb(0, 0, 0); self.render_width * self.render_height];
+        self.dirty = true;
+    }
+
+    /// Marks the frame to be rendered again, e.g. after toggling a layer
+    /// or changing a parameter
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Function to know if a layer is drawn again in the current frame,
+    /// all of them are after a change of the stack
+    fn is_due(&self, layer: &Layer) -> bool {
+        self.dirty || layer.is_due(self.tick)
+    }
+
+    /// Part of the next frame that would be different from the last one
+    pub fn damage(&self, ctx: &FrameContext) -> Damage {
+        if self.dirty {
+            return Damage::Full;
+        }
+
+        self.layers.iter()
+            .filter(|layer| layer.active && self.is_due(layer))
+            .fold(Damage::None, |damage, layer| {
+                damage.merge(layer.damage(self.render_width, self.render_height, ctx))
+            })
+    }
+
+    /// Last completed frame, at the size of the presented frame
+    pub fn frame(&self) -> &[u32] {
+        if self.output.is_empty() {
+            &self.front
+        } else {
+            &self.output
+        }
+    }
+
+    /// Renders the changes of the active layers, returns `false` if the
+    /// frame didn't change and there is nothing new to present
+    pub fn render(&mut self, ctx: &FrameContext) -> bool {
+        if !self.render_layers(ctx) {
+            return false;
+        }
+
+        if !self.output.is_empty() {
+            self.resampler.resample(self.filter, &self.front, (self.render_width, self.render_height), &mut self.output, (self.width, self.height));
+        }
+
+        true
+    }
+
+    fn render_layers(&mut self, ctx: &FrameContext) -> bool {
+        self.tick += 1;
+
+        let rows = match self.damage(ctx) {
+            Damage::None => return false,
+            Damage::Full => None,
+            damage => Some(damage.rows(self.render_height)),
+        };
+
+        if rows.as_ref().is_some_and(|rows| rows.is_empty()) {
+            return false;
+        }
+
+        if let Some(rows) = rows {
+            if self.render_rows(&rows, ctx) {
+                return true;
+            }
+        }
+
+        self.render_full(ctx);
+        true
+    }
+
+    /// Renders the active layers on the back buffer and swaps it
+    /// with the front one, the layers that aren't due keep their surface
+    fn render_full(&mut self, ctx: &FrameContext) {
+        if self.tiled {
+            self.render_tiled(ctx);
+        } else {
+            self.render_sequential(ctx);
+        }
+
+        std::mem::swap(&mut self.front, &mut self.back);
+        self.dirty = false;
+    }
+
+    fn render_sequential(&mut self, ctx: &FrameContext) {
+        let (width, height) = (self.render_width, self.render_height);
+        let (dirty, tick) = (self.dirty, self.tick);
+
+        // Cada frame parte de negro opaco
+        self.back.fill(Color::rgb(0, 0, 0));
+
+        for layer in self.layers.iter_mut() {
+            if !layer.active {
+                continue
+            }
+
+            if dirty || layer.is_due(tick) {
+                trace!("Applying {:?} to buffer", layer.convolution.name());
+                layer.render(width, height, &self.back, ctx);
+            }
+
+            layer.composite(&mut self.back);
+        }
+    }
+
+    /// Draws again only some rows directly on the front buffer, the rest of
+    /// the frame and of the surfaces is kept from the last frame. Returns
+    /// `false` if some active layer can't be drawn by parts
+    fn render_rows(&mut self, rows: &[Range<usize>], ctx: &FrameContext) -> bool {
+        let (width, height) = (self.render_width, self.render_height);
+        let tick = self.tick;
+
+        // El tiempo de cada capa es el de preparar y dibujar todas las filas
+        for layer in self.layers.iter_mut() {
+            if !layer.active || !layer.is_due(tick) {
+                continue;
+            }
+
+            let start = Instant::now();
+            if layer.surface.len() != width * height || !layer.prepare_tiles(width, height, ctx) {
+                return false;
+            }
+            layer.cost = start.elapsed();
+        }
+
+        for range in rows {
+            let pixels = range.start * width..range.end * width;
+            let frame = &mut self.front[pixels.clone()];
+            frame.fill(Color::rgb(0, 0, 0));
+
+            for layer in self.layers.iter_mut().filter(|layer| layer.active) {
+                let due = layer.is_due(tick);
+                let surface = &mut layer.surface[pixels.clone()];
+
+                if due {
+                    let start = Instant::now();
+                    surface.fill(Color::TRANSPARENT);
+                    layer.convolution.transform_tile(surface, range.start, width, height);
+                    layer.cost += start.elapsed();
+                }
+
+                blend::composite(frame, surface, layer.opacity, layer.blend);
+            }
+        }
+
+        true
+    }
+
+    fn render_tiled(&mut self, ctx: &FrameContext) {
+        let (width, height) = (self.render_width, self.render_height);
+        let (dirty, tick) = (self.dirty, self.tick);
+        let band = (TILE_PIXELS / width.max(1)).max(1) * width;
+
+        if band == 0 {
+            return;
+        }
+
+        self.back.par_chunks_mut(band).for_each(|tile| tile.fill(Color::rgb(0, 0, 0)));
+
+        let mut start = 0;
+        while start < self.layers.len() {
+            // Grupo de capas consecutivas que se dibujan por tiles, las que
+            // no toca dibujar solo se componen
+            let mut end = start;
+            while end < self.layers.len() {
+                let layer = &mut self.layers[end];
+                let due = dirty || layer.is_due(tick);
+                let begin = Instant::now();
+
+                if layer.active && due {
+                    if !layer.prepare_tiles(width, height, ctx) {
+                        break;
+                    }
+                    // Los tiles le suman su tiempo
+                    layer.cost = begin.elapsed();
+                }
+                end += 1;
+            }
+
+            if end > start {
+                render_tiles(&mut self.layers[start..end], &mut self.back, band, (width, height), (dirty, tick));
+            }
+
+            // La capa que no se puede dibujar por tiles ocupa todo el frame
+            if let Some(layer) = self.layers.get_mut(end) {
+                trace!("Applying {:?} to buffer", layer.convolution.name());
+
+                layer.render(width, height, &self.back, ctx);
+                self.back.par_chunks_mut(band)
+                    .zip(layer.surface.par_chunks(band))
+                    .for_each(|(tile, surface)| blend::composite(tile, surface, layer.opacity, layer.blend));
+            }
+
+            start = end + 1;
+        }
+    }
+}
+
+/// Draws a group of tileable layers, every tile of the frame composites
+/// its band of each layer in order. The time of the tiles is added to the
+/// cost of the layers, which already has the time to prepare them
+fn render_tiles(layers: &mut [Layer], frame: &mut [u32], band: usize, (width, height): (usize, usize), (dirty, tick): (bool, u64)) {
+    let count = frame.len().div_ceil(band);
+    let mut tiles: Vec<Vec<TilePart>> = (0..count).map(|_| Vec::with_capacity(layers.len())).collect();
+
+    for layer in layers.iter_mut().filter(|layer| layer.active) {
+        trace!("Applying {:?} to buffer by tiles", layer.convolution.name());
+
+        let due = dirty || layer.is_due(tick);
+        let Layer { convolution, opacity, blend, surface, tile_cost, .. } = layer;
+        let convolution = due.then_some(&*convolution);
+        *tile_cost.get_mut() = 0;
+
+        for (tile, part) in tiles.iter_mut().zip(surface.chunks_mut(band)) {
+            tile.push((convolution, *opacity, *blend, part, &*tile_cost));
+        }
+    }
+
+    tiles.into_par_iter()
+        .zip(frame.par_chunks_mut(band))
+        .enumerate()
+        .for_each(|(index, (parts, tile))| {
+            let top = index * band / width;
+
+            for (convolution, opacity, mode, surface, cost) in parts {
+                if let Some(convolution) = convolution {
+                    let start = Instant::now();
+                    surface.fill(Color::TRANSPARENT);
+                    convolution.transform_tile(surface, top, width, height);
+                    cost.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                }
+                blend::composite(tile, surface, opacity, mode);
+            }
+        });
+
+    // Los tiles corren a la vez en los hilos del pool, el tiempo de la capa
+    // en el frame es la suma de sus tiles repartida entre los hilos
+    let workers = rayon::current_num_threads().clamp(1, count.max(1)) as u64;
+
+    for layer in layers.iter_mut().filter(|layer| layer.active && (dirty || layer.is_due(tick))) {
+        layer.cost += Duration::from_nanos(*layer.tile_cost.get_mut() / workers);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use chrono::{Local, TimeZone};
+    use super::*;
+    use crate::convolutions::registry::Registry;
+    use crate::convolutions::{Convolution, ParamValue};
+
+    /// Layer that depends on the position of the pixels and can't be
+    /// drawn by tiles, to check the layers drawn on the whole frame
+    struct Gradient;
+
+    impl Convolution for Gradient {
+        fn name(&self) -> &str {
+            "Gradient"
+        }
+
+        fn transform(&mut self, pixels: &mut [u32], width: usize, height: usize, _ctx: &FrameContext) {
+            for (i, pixel) in pixels.iter_mut().enumerate() {
+                let (x, y) = (i % width, i / width);
+                *pixel = Color::rgba((x * 255 / width) as u8, (y * 255 / height) as u8, 90, (x + y) as u8);
+            }
+        }
+    }
+
+    fn layer(registry: &Registry, name: &str, params: &[(&str, ParamValue)], width: usize, height: usize) -> Layer {
+        let values: BTreeMap<String, ParamValue> = params.iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        let convolution = registry.create(name, &values, width, height).expect("Layer not created");
+
+        Layer::new(convolution, true)
+    }
+
+    fn stack(width: usize, height: usize) -> Vec<Layer> {
+        let registry = Registry::builtin();
+
+        let mut waves = layer(&registry, "wave_fire", &[], width, height);
+        waves.opacity = 0.7;
+        waves.blend = BlendMode::Screen;
+
+        let mut fire = layer(&registry, "doom_fire_par", &[("wind", ParamValue::Float(0.5))], width, height);
+        fire.blend = BlendMode::Add;
+
+        let mut gradient = Layer::new(ConvolutionType::Simple(Box::new(Gradient)), true);
+        gradient.blend = BlendMode::Overlay;
+
+        let mut plasma = layer(&registry, "plasma_par", &[("cycle", ParamValue::Float(-0.5))], width, height);
+        plasma.opacity = 0.5;
+
+        let mut blur = layer(&registry, "kernel_par", &[("edges", ParamValue::Text("mirror".to_string()))], width, height);
+        blur.opacity = 0.8;
+
+        let mut hidden = layer(&registry, "to_green", &[], width, height);
+        hidden.active = false;
+
+        let mut time = layer(&registry, "time", &[("color", ParamValue::Text("#ffffff".to_string()))], width, height);
+        time.blend = BlendMode::Add;
+
+        vec![
+            layer(&registry, "single_color", &[("red", ParamValue::Int(20))], width, height),
+            waves,
+            layer(&registry, "wave_fire_par", &[("margin", ParamValue::Float(0.2))], width, height),
+            fire,
+            gradient,
+            plasma,
+            blur,
+            hidden,
+            time,
+            layer(&registry, "bloom_par", &[("threshold", ParamValue::Float(0.4))], width, height),
+        ]
+    }
+
+    #[test]
+    fn tiled_equals_sequential() {
+        let mut ctx = FrameContext::new(Local.with_ymd_and_hms(2024, 1, 1, 12, 34, 56).unwrap());
+
+        // Tamaños que no son múltiplos del tile ni del texto
+        for (width, height) in [(7, 5), (203, 157), (1080, 61)] {
+            let mut sequential = Pipeline::new(width, height);
+            sequential.layers = stack(width, height);
+
+            let mut tiled = Pipeline::new(width, height);
+            tiled.layers = stack(width, height);
+            tiled.tiled = true;
+
+            for frame in 0..3 {
+                ctx.frame = frame;
+                ctx.now += chrono::Duration::milliseconds(250);
+                ctx.animation += std::time::Duration::from_millis(250);
+
+                sequential.render(&ctx);
+                tiled.render(&ctx);
+
+                assert!(sequential.frame() == tiled.frame(), "frame {} differs at {}x{}", frame, width, height);
+            }
+        }
+    }
+
+    const FRAMES: u32 = 300;
+
+    /// Layer that records the sizes it's resized to
+    struct Probe {
+        sizes: Arc<Mutex<Vec<(usize, usize)>>>,
+    }
+
+    impl Convolution for Probe {
+        fn name(&self) -> &str {
+            "Probe"
+        }
+
+        fn transform(&mut self, _pixels: &mut [u32], _width: usize, _height: usize, _ctx: &FrameContext) {}
+
+        fn resize(&mut self, width: usize, height: usize) {
+            self.sizes.lock().expect("Sizes").push((width, height));
+        }
+    }
+
+    #[test]
+    fn resize_reallocates_the_frame_and_the_layers() {
+        let registry = Registry::builtin();
+        let ctx = FrameContext::new(Local.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap());
+        let (sizes, hidden_sizes) = (Arc::new(Mutex::new(Vec::new())), Arc::new(Mutex::new(Vec::new())));
+
+        let mut pipeline = Pipeline::new(16, 8);
+        for name in ["single_color", "heat_fire", "doom_fire", "doom_fire_par", "wave_fire"] {
+            pipeline.layers.push(layer(&registry, name, &[], 16, 8));
+        }
+        let probe = Probe { sizes: sizes.clone() };
+        pipeline.layers.push(Layer::new(ConvolutionType::Simple(Box::new(probe)), true));
+        let hidden = Probe { sizes: hidden_sizes.clone() };
+        pipeline.layers.push(Layer::new(ConvolutionType::Simple(Box::new(hidden)), false));
+
+        // Cada capa recibe el tamaño al que se dibuja la primera vez
+        pipeline.render(&ctx);
+        pipeline.resize(16, 8);
+        pipeline.render(&ctx);
+        assert_eq!(*sizes.lock().unwrap(), [(16, 8)], "the same size isn't a resize");
+
+        // Las capas con estado indexan sus buffers con el tamaño nuevo,
+        // sin el aviso de `resize` el frame se saldría del buffer
+        pipeline.resize(40, 30);
+        pipeline.render(&ctx);
+
+        assert_eq!((pipeline.width(), pipeline.height()), (40, 30));
+        assert_eq!(pipeline.frame().len(), 40 * 30);
+        for layer in pipeline.layers.iter().filter(|layer| layer.active) {
+            assert_eq!(layer.surface.len(), 40 * 30, "{}", layer.convolution.name());
+        }
+        assert_eq!(*sizes.lock().unwrap(), [(16, 8), (40, 30)]);
+
+        // Las capas inactivas se avisan cuando se vuelven a dibujar
+        assert!(hidden_sizes.lock().unwrap().is_empty());
+        pipeline.resize(4, 2);
+        pipeline.layers.last_mut().expect("Hidden probe").active = true;
+        pipeline.render(&ctx);
+        assert_eq!(pipeline.frame().len(), 4 * 2);
+        assert_eq!(*sizes.lock().unwrap(), [(16, 8), (40, 30), (4, 2)]);
+        assert_eq!(*hidden_sizes.lock().unwrap(), [(4, 2)]);
+    }
+
+    #[test]
+    fn damaged_rows_equal_full_frame() {
+        let registry = Registry::builtin();
+        let (width, height) = (120, 90);
+        let start = Local.with_ymd_and_hms(2024, 1, 1, 12, 34, 56).unwrap();
+
+        let clock = |registry: &Registry| {
+            let mut time = layer(registry, "time", &[("color", ParamValue::Text("#ffffff".to_string()))], width, height);
+            time.blend = BlendMode::Screen;
+
+            vec![layer(registry, "single_color", &[("blue", ParamValue::Int(80))], width, height), time]
+        };
+
+        let mut partial = Pipeline::new(width, height);
+        partial.layers = clock(&registry);
+
+        let mut ctx = FrameContext::new(start);
+        assert!(partial.render(&ctx));
+        assert!(partial.damage(&ctx).is_none());
+        assert!(!partial.render(&ctx));
+
+        // 12:34:56 -> 12:35:00, solo cambian las filas del texto
+        ctx.now += chrono::Duration::seconds(4);
+        assert!(matches!(partial.damage(&ctx), Damage::Rects(_)));
+        assert!(partial.render(&ctx));
+
+        let mut full = Pipeline::new(width, height);
+        full.layers = clock(&registry);
+        full.render_full(&ctx);
+
+        assert!(partial.frame() == full.frame());
+    }
+
+    #[test]
+    fn tiles_and_damaged_rows_measure_the_cost() {
+        let registry = Registry::builtin();
+        let (width, height) = (120, 90);
+        let mut ctx = FrameContext::new(Local.with_ymd_and_hms(2024, 1, 1, 12, 34, 56).unwrap());
+
+        let mut tiled = Pipeline::new(width, height);
+        tiled.layers = vec![layer(&registry, "wave_fire", &[], width, height)];
+        tiled.tiled = true;
+        assert!(tiled.render(&ctx));
+        assert!(tiled.layers[0].cost > Duration::ZERO);
+
+        // Solo cambian las filas de la hora, su capa mide el tiempo de esas filas
+        let mut partial = Pipeline::new(width, height);
+        partial.layers = vec![layer(&registry, "time", &[("color", ParamValue::Text("#ffffff".to_string()))], width, height)];
+        partial.render(&ctx);
+        partial.layers[0].cost = Duration::ZERO;
+
+        ctx.now += chrono::Duration::seconds(4);
+        assert!(matches!(partial.damage(&ctx), Damage::Rects(_)));
+        assert!(partial.render(&ctx));
+        assert!(partial.layers[0].cost > Duration::ZERO);
+    }
+
+    /// Stack of the app: dark background, optionally the parallel wave
+    /// fire, and the time on top
+    fn app_stack(width: usize, height: usize, fire: bool) -> Vec<Layer> {
+        let registry = Registry::builtin();
+        let background = BTreeMap::from([
+            ("red".to_string(), ParamValue::Int(20)),
+            ("green".to_string(), ParamValue::Int(20)),
+            ("blue".to_string(), ParamValue::Int(20)),
+        ]);
+        let time = BTreeMap::from([("color".to_string(), ParamValue::Text("#ffffff".to_string()))]);
+
+        let mut layers = vec![registry.create("single_color", &background, width, height)];
+        if fire {
+            layers.push(registry.create("wave_fire_par", &BTreeMap::new(), width, height));
+        }
+        layers.push(registry.create("time", &time, width, height));
+
+        layers.into_iter()
+            .map(|convolution| Layer::new(convolution.expect("Known layer"), true))
+            .collect()
+    }
+
+    /// Mean time of a whole frame at 1080x720, run it with
+    /// `cargo test --release -- --ignored --nocapture frame_time`
+    #[test]
+    #[ignore]
+    fn frame_time_at_1080x720() {
+        let (width, height) = (1080, 720);
+
+        for fire in [false, true] {
+            let mut pipeline = Pipeline::new(width, height);
+            pipeline.layers = app_stack(width, height, fire);
+            let mut ctx = FrameContext::new(Local::now());
+
+            // El primer frame reserva las superficies, no se mide
+            pipeline.render_full(&ctx);
+
+            // Frames completos, sin saltar los que no cambian
+            let start = Instant::now();
+            for frame in 1..=FRAMES {
+                ctx.frame = frame as u64;
+                ctx.now += chrono::Duration::milliseconds(16);
+                pipeline.render_full(&ctx);
+            }
+            let mean: Duration = start.elapsed() / FRAMES;
+
+            eprintln!("frame time at {}x{} (fire: {}): {:.3} ms", width, height, fire, mean.as_secs_f64() * 1000.0);
+        }
+    }
+}
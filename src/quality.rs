@@ -0,0 +1,281 @@
+use std::time::Duration;
+use crate::convolutions::resample::MIN_SCALE;
+use crate::convolutions::{Layer, ParamValue};
+
+/// Largest number of frames between two draws of a layer
+pub const MAX_INTERVAL: u32 = 4;
+
+/// Rendered frames over the budget before lowering the quality
+const OVER_FRAMES: u32 = 10;
+/// Rendered frames with headroom before restoring the quality
+const UNDER_FRAMES: u32 = 120;
+/// Part of the budget that must be free to restore the quality
+const HEADROOM: f64 = 0.5;
+/// Layers cheaper than `budget / MIN_SHARE` keep their quality
+const MIN_SHARE: u32 = 10;
+/// Weight of the last frame in the average of the frame time
+const SMOOTHING: f64 = 0.2;
+
+/// Change made to a layer to lower the cost of the frames, it keeps
+/// what is needed to undo it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Adjustment {
+    /// The layer changed from the sequential executor to a parallel one
+    Parallel { layer: usize, executor: String },
+    /// The internal resolution of the layer was halved
+    Scale { layer: usize, previous: f32 },
+    /// The layer is drawn every `interval` frames
+    Interval { layer: usize, previous: u32 },
+}
+
+/// Controller of the quality of the layers, keeps the time of the frames
+/// under a budget.
+///
+/// When the average frame time exceeds the budget it changes the most
+/// expensive active layer: first to parallel execution, then to half of
+/// its resolution and last to fewer updates per second. The cheap layers
+/// are never changed. When there is headroom again the changes are undone
+/// in reverse order.
+pub struct QualityController {
+    pub budget: Duration,
+    average: f64,
+    over: u32,
+    under: u32,
+    adjustments: Vec<Adjustment>,
+}
+
+impl QualityController {
+    pub fn new(budget: Duration) -> Self {
+        QualityController {
+            budget,
+            average: 0.0,
+            over: 0,
+            under: 0,
+            adjustments: Vec::new(),
+        }
+    }
+
+    /// Changes made and not restored yet
+    pub fn adjustments(&self) -> &[Adjustment] {
+        &self.adjustments
+    }
+
+    /// Registers the time of a rendered frame, returns a description of
+    /// the change if the quality of a layer was changed
+    pub fn update(&mut self, frame_time: Duration, layers: &mut [Layer]) -> Option<String> {
+        let budget = self.budget.as_secs_f64();
+        self.average += (frame_time.as_secs_f64() - self.average) * SMOOTHING;
+
+        if self.average > budget {
+            self.over += 1;
+            self.under = 0;
+        } else if self.average < budget * HEADROOM {
+            self.under += 1;
+            self.over = 0;
+        } else {
+            self.over = 0;
+            self.under = 0;
+        }
+
+        if self.over >= OVER_FRAMES {
+            self.over = 0;
+            return self.lower(layers);
+        }
+
+        if self.under >= UNDER_FRAMES {
+            self.under = 0;
+            return self.restore(layers);
+        }
+
+        None
+    }
+
+    fn lower(&mut self, layers: &mut [Layer]) -> Option<String> {
+        // Capas activas de la más costosa a la más barata, las baratas
+        // no se tocan porque no cambiarían el tiempo del frame
+        let mut order: Vec<usize> = (0..layers.len())
+            .filter(|&i| layers[i].active && layers[i].cost >= self.budget / MIN_SHARE)
+            .collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(layers[i].cost));
+
+        for index in order {
+            if let Some(adjustment) = Self::lower_layer(index, &mut layers[index]) {
+                let message = describe(&adjustment, &layers[index], "lowered");
+                self.adjustments.push(adjustment);
+                return Some(message);
+            }
+        }
+
+        None
+    }
+
+    fn lower_layer(index: usize, layer: &mut Layer) -> Option<Adjustment> {
+        let executor = layer.convolution.params().into_iter()
+            .find(|param| param.spec.name == "executor")
+            .and_then(|param| param.value.as_str().map(str::to_string));
+
+        if executor.as_deref() == Some("sequential")
+            && layer.convolution.set_param("executor", ParamValue::Text("rayon_rows".to_string())).is_ok()
+        {
+            return Some(Adjustment::Parallel { layer: index, executor: "sequential".to_string() });
+        }
+
+        if layer.scale > MIN_SCALE {
+            let previous = layer.scale;
+            layer.scale = (layer.scale / 2.0).max(MIN_SCALE);
+            return Some(Adjustment::Scale { layer: index, previous });
+        }
+
+        if layer.interval < MAX_INTERVAL {
+            let previous = layer.interval;
+            layer.interval = (layer.interval * 2).min(MAX_INTERVAL);
+            return Some(Adjustment::Interval { layer: index, previous });
+        }
+
+        None
+    }
+
+    fn restore(&mut self, layers: &mut [Layer]) -> Option<String> {
+        let adjustment = self.adjustments.pop()?;
+
+        let index = match &adjustment {
+            Adjustment::Parallel { layer, executor } => {
+                let _ = layers[*layer].convolution.set_param("executor", ParamValue::Text(executor.clone()));
+                *layer
+            }
+            Adjustment::Scale { layer, previous } => {
+                layers[*layer].scale = *previous;
+                *layer
+            }
+            Adjustment::Interval { layer, previous } => {
+                layers[*layer].interval = *previous;
+                *layer
+            }
+        };
+
+        Some(describe(&adjustment, &layers[index], "restored"))
+    }
+}
+
+fn describe(adjustment: &Adjustment, layer: &Layer, action: &str) -> String {
+    let name = layer.convolution.name();
+
+    match adjustment {
+        Adjustment::Parallel { .. } => format!("{} {}: executor {}", action, name, layer.convolution.params()
+            .into_iter()
+            .find(|param| param.spec.name == "executor")
+            .and_then(|param| param.value.as_str().map(str::to_string))
+            .unwrap_or_default()),
+        Adjustment::Scale { .. } => format!("{} {}: scale {}", action, name, layer.scale),
+        Adjustment::Interval { .. } => format!("{} {}: every {} frames", action, name, layer.interval),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use super::*;
+    use crate::convolutions::registry::Registry;
+
+    const BUDGET: Duration = Duration::from_millis(10);
+    /// Frame time between the headroom and the budget
+    const BETWEEN: Duration = Duration::from_micros(7500);
+
+    /// A sequential wave fire that costs `cost` per frame
+    fn layer(cost: Duration) -> Layer {
+        let convolution = Registry::builtin().create("wave_fire", &BTreeMap::new(), 64, 48).expect("Layer not created");
+        let mut layer = Layer::new(convolution, true);
+        layer.cost = cost;
+        layer
+    }
+
+    fn executor(layer: &Layer) -> String {
+        layer.convolution.params().into_iter()
+            .find(|param| param.spec.name == "executor")
+            .and_then(|param| param.value.as_str().map(str::to_string))
+            .unwrap_or_default()
+    }
+
+    /// Registers `frames` frames of `frame_time`, returns the changes made
+    fn run(quality: &mut QualityController, layers: &mut [Layer], frame_time: Duration, frames: u32) -> Vec<String> {
+        (0..frames).filter_map(|_| quality.update(frame_time, layers)).collect()
+    }
+
+    /// Frames over the budget until the quality is lowered, then frames
+    /// between the headroom and the budget until the average settles
+    fn lower(quality: &mut QualityController, layers: &mut [Layer]) -> Option<String> {
+        let change = (0..OVER_FRAMES * 4).find_map(|_| quality.update(BUDGET * 2, layers));
+        assert!(run(quality, layers, BETWEEN, OVER_FRAMES * 2).is_empty());
+        change
+    }
+
+    #[test]
+    fn lowers_the_most_expensive_layer_in_order() {
+        let mut quality = QualityController::new(BUDGET);
+        let mut layers = vec![layer(Duration::from_millis(2)), layer(Duration::from_micros(500)), layer(Duration::from_millis(12))];
+
+        assert_eq!(lower(&mut quality, &mut layers).as_deref(), Some("lowered WaveFire: executor rayon_rows"));
+        assert_eq!(quality.adjustments(), [Adjustment::Parallel { layer: 2, executor: "sequential".to_string() }]);
+        assert_eq!(executor(&layers[2]), "rayon_rows");
+
+        (0..4).for_each(|_| assert!(lower(&mut quality, &mut layers).is_some()));
+        assert_eq!(&quality.adjustments()[1..], [
+            Adjustment::Scale { layer: 2, previous: 1.0 },
+            Adjustment::Scale { layer: 2, previous: 0.5 },
+            Adjustment::Interval { layer: 2, previous: 1 },
+            Adjustment::Interval { layer: 2, previous: 2 },
+        ]);
+        assert_eq!((layers[2].scale, layers[2].interval), (MIN_SCALE, MAX_INTERVAL));
+
+        // Agotada la más costosa sigue la siguiente, la barata no se toca
+        (0..5).for_each(|_| assert!(lower(&mut quality, &mut layers).is_some()));
+        assert_eq!(lower(&mut quality, &mut layers), None);
+        assert_eq!(executor(&layers[0]), "rayon_rows");
+        assert_eq!((layers[0].scale, layers[0].interval), (MIN_SCALE, MAX_INTERVAL));
+        assert_eq!(executor(&layers[1]), "sequential");
+        assert_eq!((layers[1].scale, layers[1].interval), (1.0, 1));
+        assert_eq!(quality.adjustments().len(), 10);
+    }
+
+    #[test]
+    fn restores_in_reverse_order() {
+        let mut quality = QualityController::new(BUDGET);
+        let mut layers = vec![layer(Duration::from_millis(8))];
+
+        lower(&mut quality, &mut layers);
+        lower(&mut quality, &mut layers);
+        assert_eq!(quality.adjustments().len(), 2);
+
+        // La media tarda unos frames en bajar del margen
+        let restored = run(&mut quality, &mut layers, Duration::ZERO, UNDER_FRAMES + OVER_FRAMES);
+        assert_eq!(restored, ["restored WaveFire: scale 1"]);
+        assert_eq!(executor(&layers[0]), "rayon_rows");
+
+        assert_eq!(run(&mut quality, &mut layers, Duration::ZERO, UNDER_FRAMES), ["restored WaveFire: executor sequential"]);
+        assert!(quality.adjustments().is_empty());
+        assert_eq!((layers[0].scale, layers[0].interval), (1.0, 1));
+
+        // Sin cambios que deshacer no pasa nada
+        assert!(run(&mut quality, &mut layers, Duration::ZERO, UNDER_FRAMES * 2).is_empty());
+    }
+
+    #[test]
+    fn keeps_the_quality_between_the_headroom_and_the_budget() {
+        let mut quality = QualityController::new(BUDGET);
+        let mut layers = vec![layer(Duration::from_millis(8))];
+
+        lower(&mut quality, &mut layers);
+
+        // Entre el margen y el presupuesto no se baja ni se restaura
+        assert!(run(&mut quality, &mut layers, BETWEEN, UNDER_FRAMES * 5).is_empty());
+
+        // Unos frames lentos o rápidos sueltos tampoco cambian la calidad
+        for _ in 0..UNDER_FRAMES {
+            assert!(run(&mut quality, &mut layers, BUDGET * 4, 1).is_empty());
+            assert!(run(&mut quality, &mut layers, BETWEEN, 10).is_empty());
+            assert!(run(&mut quality, &mut layers, Duration::ZERO, 1).is_empty());
+            assert!(run(&mut quality, &mut layers, BETWEEN, 10).is_empty());
+        }
+        assert_eq!(quality.adjustments().len(), 1);
+    }
+}
@@ -0,0 +1,350 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use serde::Deserialize;
+use crate::convolutions::params::ParamValue;
+use crate::convolutions::registry::Registry;
+use crate::convolutions::resample;
+use crate::convolutions::{BlendMode, Filter, Layer};
+
+/// Error found while loading a scene file
+#[derive(Debug)]
+pub enum SceneError {
+    /// The file can't be read
+    Io(String, std::io::Error),
+    /// The file isn't valid TOML or JSON
+    Parse(String),
+    /// A layer has an invalid type or parameter
+    Layer {
+        index: usize,
+        kind: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(path, error) => write!(f, "can't read the scene {:?}: {}", path, error),
+            SceneError::Parse(message) => write!(f, "invalid scene: {}", message),
+            SceneError::Layer { index, kind, message } => {
+                write!(f, "layer #{} ({:?}): {}", index + 1, kind, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+/// Description of a layer of the scene
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LayerSpec {
+    /// Name of the convolution, e.g. `doom_fire`
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default = "default_visible")]
+    pub visible: bool,
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+    #[serde(default)]
+    pub blend: Option<String>,
+    /// Internal resolution of the layer relative to the frame
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    /// Filter to resample the layer: nearest, bilinear or bicubic
+    #[serde(default)]
+    pub filter: Option<String>,
+    #[serde(default)]
+    pub params: BTreeMap<String, ParamValue>,
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+/// Layer stack loaded from a TOML or JSON file, the layers are drawn
+/// in the order of the file
+///
+/// ```toml
+/// threads = 4
+/// tiled = true
+/// scale = 0.5
+/// filter = "bilinear"
+///
+/// [[layer]]
+/// type = "single_color"
+/// params = { red = 20, green = 20, blue = 20 }
+///
+/// [[layer]]
+/// type = "time"
+/// opacity = 0.8
+/// blend = "screen"
+/// params = { f24 = true, color = "#ffffff" }
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scene {
+    /// Threads of the rayon pool, all the cores by default
+    #[serde(default)]
+    pub threads: Option<usize>,
+    /// Render the frame by tiles instead of layer by layer
+    #[serde(default)]
+    pub tiled: bool,
+    /// Internal resolution of the whole stack relative to the frame
+    #[serde(default)]
+    pub scale: Option<f32>,
+    /// Filter to resample the whole stack
+    #[serde(default)]
+    pub filter: Option<String>,
+    #[serde(rename = "layer", default)]
+    pub layers: Vec<LayerSpec>,
+}
+
+impl Scene {
+    /// Loads a scene, the format is chosen by the extension
+    /// (`.json` for JSON, TOML otherwise)
+    pub fn load(path: &Path) -> Result<Scene, SceneError> {
+        let name = path.display().to_string();
+        let content = fs::read_to_string(path).map_err(|error| SceneError::Io(name, error))?;
+
+        let is_json = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+        if is_json {
+            Self::from_json(&content)
+        } else {
+            Self::from_toml(&content)
+        }
+    }
+
+    pub fn from_toml(content: &str) -> Result<Scene, SceneError> {
+        toml::from_str(content).map_err(|error| SceneError::Parse(error.to_string()))
+    }
+
+    pub fn from_json(content: &str) -> Result<Scene, SceneError> {
+        serde_json::from_str(content).map_err(|error| SceneError::Parse(error.to_string()))
+    }
+
+    /// Builds every layer for a frame of `width` x `height`
+    pub fn build(&self, registry: &Registry, width: usize, height: usize) -> Result<Vec<Layer>, SceneError> {
+        if self.layers.is_empty() {
+            return Err(SceneError::Parse("the scene has no layers".to_string()));
+        }
+
+        if self.threads == Some(0) {
+            return Err(SceneError::Parse("the number of threads must be greater than 0".to_string()));
+        }
+
+        if let Some(scale) = self.scale {
+            resample::check_scale(scale).map_err(SceneError::Parse)?;
+        }
+
+        if let Some(filter) = &self.filter {
+            filter.parse::<Filter>().map_err(SceneError::Parse)?;
+        }
+
+        self.layers
+            .iter()
+            .enumerate()
+            .map(|(index, spec)| {
+                spec.build(registry, width, height).map_err(|message| SceneError::Layer {
+                    index,
+                    kind: spec.kind.clone(),
+                    message,
+                })
+            })
+            .collect()
+    }
+}
+
+impl LayerSpec {
+    fn build(&self, registry: &Registry, width: usize, height: usize) -> Result<Layer, String> {
+        if !(0.0..=1.0).contains(&self.opacity) {
+            return Err(format!("opacity {} is out of range 0..=1", self.opacity));
+        }
+
+        let blend = match &self.blend {
+            Some(blend) => blend.parse()?,
+            None => BlendMode::Normal,
+        };
+
+        let scale = resample::check_scale(self.scale)?;
+        let filter = match &self.filter {
+            Some(filter) => filter.parse()?,
+            None => Filter::Nearest,
+        };
+
+        let convolution = registry.create(&self.kind, &self.params, width, height)?;
+
+        let mut layer = Layer::new(convolution, self.visible);
+        layer.opacity = self.opacity;
+        layer.blend = blend;
+        layer.scale = scale;
+        layer.filter = filter;
+        Ok(layer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const README: &str = include_str!("../README.md");
+
+    /// Bloques TOML del README que describen capas
+    fn readme_scenes() -> Vec<&'static str> {
+        README.split("```toml\n")
+            .skip(1)
+            .filter_map(|block| block.split("```").next())
+            .filter(|block| block.contains("[[layer]]"))
+            .collect()
+    }
+
+    fn build(scene: &Scene) -> Result<Vec<Layer>, SceneError> {
+        scene.build(&Registry::builtin(), 64, 48)
+    }
+
+    /// Error of a scene with one layer, given as a TOML fragment
+    fn layer_error(layer: &str) -> String {
+        let scene = Scene::from_toml(&format!("[[layer]]\n{}", layer)).expect("Valid TOML");
+
+        match build(&scene) {
+            Err(SceneError::Layer { index: 0, message, .. }) => message,
+            other => panic!("expected an error of the layer, got {:?}", other.map(|layers| layers.len())),
+        }
+    }
+
+    #[test]
+    fn readme_and_bundled_scenes_build() {
+        let scenes = readme_scenes();
+        assert!(!scenes.is_empty(), "the README examples weren't found");
+
+        for content in scenes {
+            let scene = Scene::from_toml(content).unwrap_or_else(|error| panic!("{}\n{}", error, content));
+            build(&scene).unwrap_or_else(|error| panic!("{}\n{}", error, content));
+        }
+
+        let default = Scene::from_toml(include_str!("../scenes/default.toml")).expect("Valid default scene");
+        assert!(build(&default).is_ok());
+
+        let glow = Scene::from_json(include_str!("../scenes/glow.json")).expect("Valid glow scene");
+        assert!(build(&glow).is_ok());
+    }
+
+    #[test]
+    fn toml_and_json_give_the_same_scene() {
+        let toml = Scene::from_toml(r##"
+            threads = 2
+            tiled = true
+            scale = 0.5
+            filter = "bilinear"
+
+            [[layer]]
+            type = "single_color"
+            params = { red = 20, green = 20, blue = 20 }
+
+            [[layer]]
+            type = "heat_fire"
+            opacity = 0.5
+            blend = "add"
+            scale = 0.25
+            filter = "bicubic"
+            params = { base_intensity = 0.8, diffusion = 1 }
+
+            [[layer]]
+            type = "kernel_par"
+            params = { kernel = "custom", matrix = [[1, 2, 1]], normalize = false }
+
+            [[layer]]
+            type = "time"
+            visible = false
+            params = { f24 = true, color = "#ffffff" }
+        "##).expect("Valid TOML");
+
+        let json = Scene::from_json(r##"{
+            "threads": 2,
+            "tiled": true,
+            "scale": 0.5,
+            "filter": "bilinear",
+            "layer": [
+                { "type": "single_color", "params": { "red": 20, "green": 20, "blue": 20 } },
+                {
+                    "type": "heat_fire", "opacity": 0.5, "blend": "add", "scale": 0.25, "filter": "bicubic",
+                    "params": { "base_intensity": 0.8, "diffusion": 1 }
+                },
+                { "type": "kernel_par", "params": { "kernel": "custom", "matrix": [[1, 2, 1]], "normalize": false } },
+                { "type": "time", "visible": false, "params": { "f24": true, "color": "#ffffff" } }
+            ]
+        }"##).expect("Valid JSON");
+
+        assert_eq!(toml, json);
+
+        let layers = build(&toml).expect("Valid layers");
+        assert_eq!(layers.len(), 4);
+        assert_eq!(layers[1].blend, BlendMode::Add);
+        assert_eq!(layers[1].opacity, 0.5);
+        assert_eq!(layers[1].scale, 0.25);
+        assert_eq!(layers[1].filter, Filter::Bicubic);
+        assert_eq!(layers[2].convolution.name(), "Kernel");
+        assert!(!layers[3].active);
+    }
+
+    #[test]
+    fn rejects_invalid_layers() {
+        let cases = [
+            ("type = \"single_color\"\nopacity = 1.5", "opacity"),
+            ("type = \"single_color\"\nopacity = -0.1", "opacity"),
+            ("type = \"single_color\"\nscale = 0.1", "scale"),
+            ("type = \"single_color\"\nscale = 4.0", "scale"),
+            ("type = \"single_color\"\nfilter = \"lanczos\"", "filter"),
+            ("type = \"single_color\"\nblend = \"darken\"", "darken"),
+            ("type = \"sparkles\"", "unknown layer type"),
+            ("type = \"single_color\"\nparams = { alpha = 3 }", "alpha"),
+            ("type = \"single_color\"\nparams = { red = 300 }", "red"),
+            ("type = \"single_color\"\nparams = { red = \"dark\" }", "red"),
+            ("type = \"heat_fire\"\nparams = { cooling = 2.0 }", "cooling"),
+            ("type = \"time\"\nparams = { color = \"white\" }", "color"),
+        ];
+
+        for (layer, expected) in cases {
+            let message = layer_error(layer);
+            assert!(message.contains(expected), "{:?}: {:?} doesn't mention {:?}", layer, message, expected);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_scenes() {
+        let layer = "[[layer]]\ntype = \"single_color\"\n";
+
+        for header in ["threads = 0", "scale = 0.1", "scale = 3.0", "filter = \"lanczos\""] {
+            let scene = Scene::from_toml(&format!("{}\n{}", header, layer)).expect("Valid TOML");
+            assert!(matches!(build(&scene), Err(SceneError::Parse(_))), "{:?} should be rejected", header);
+        }
+
+        let empty = Scene::from_toml("threads = 2").expect("Valid TOML");
+        assert!(matches!(build(&empty), Err(SceneError::Parse(_))));
+
+        // Campos desconocidos y JSON roto se rechazan al leer
+        assert!(Scene::from_toml("[[layer]]\ntype = \"time\"\ncolour = \"red\"").is_err());
+        assert!(Scene::from_toml("frames = 3").is_err());
+        assert!(Scene::from_json("{ \"layer\": [ { \"type\": \"time\" } ").is_err());
+    }
+
+    #[test]
+    fn errors_name_the_layer() {
+        let scene = Scene::from_toml("[[layer]]\ntype = \"single_color\"\n\n[[layer]]\ntype = \"time\"\nopacity = 2.0")
+            .expect("Valid TOML");
+
+        let error = build(&scene).map(|layers| layers.len()).unwrap_err();
+        assert!(matches!(error, SceneError::Layer { index: 1, .. }));
+        assert!(error.to_string().starts_with("layer #2 (\"time\")"), "{}", error);
+    }
+}
@@ -0,0 +1,160 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use tracing::info;
+use crate::target::{RenderTarget, TargetError};
+
+/// Image format of the dumped frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Ppm,
+    Png,
+}
+
+impl ImageFormat {
+    pub fn extension(&self) -> &str {
+        match self {
+            ImageFormat::Ppm => "ppm",
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ppm" => Ok(ImageFormat::Ppm),
+            "png" => Ok(ImageFormat::Png),
+            other => Err(format!("unknown image format {:?} (expected ppm or png)", other)),
+        }
+    }
+}
+
+/// Render target without display, it renders a fixed number of frames
+/// and writes each one as an image file inside `output`
+pub struct HeadlessTarget {
+    frames: usize,
+    rendered: usize,
+    output: PathBuf,
+    format: ImageFormat,
+}
+
+impl HeadlessTarget {
+    pub fn new(frames: usize, output: PathBuf, format: ImageFormat) -> Result<Self, TargetError> {
+        fs::create_dir_all(&output).map_err(|error| TargetError::Io(output.clone(), error))?;
+
+        Ok(HeadlessTarget {
+            frames,
+            rendered: 0,
+            output,
+            format,
+        })
+    }
+
+    fn frame_path(&self) -> PathBuf {
+        self.output.join(format!("frame_{:05}.{}", self.rendered, self.format.extension()))
+    }
+
+    /// Converts the 0RGB buffer of MiniFB to packed RGB bytes
+    fn to_rgb(buffer: &[u32]) -> Vec<u8> {
+        buffer.iter()
+            .flat_map(|pixel| [(pixel >> 16) as u8, (pixel >> 8) as u8, *pixel as u8])
+            .collect()
+    }
+
+    fn write_ppm(file: File, rgb: &[u8], width: usize, height: usize) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(file);
+        write!(writer, "P6\n{} {}\n255\n", width, height)?;
+        writer.write_all(rgb)?;
+        writer.flush()
+    }
+
+    fn write_png(file: File, rgb: &[u8], width: usize, height: usize) -> Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(rgb)
+    }
+}
+
+impl RenderTarget for HeadlessTarget {
+    fn is_open(&self) -> bool {
+        self.rendered < self.frames
+    }
+
+    fn records_frames(&self) -> bool {
+        true
+    }
+
+    fn present(&mut self, buffer: &[u32], width: usize, height: usize) -> Result<(), TargetError> {
+        let path = self.frame_path();
+        let file = File::create(&path).map_err(|error| TargetError::Io(path.clone(), error))?;
+        let rgb = Self::to_rgb(buffer);
+
+        match self.format {
+            ImageFormat::Ppm => Self::write_ppm(file, &rgb, width, height)
+                .map_err(|error| TargetError::Io(path.clone(), error))?,
+            ImageFormat::Png => match Self::write_png(file, &rgb, width, height) {
+                Ok(()) => {}
+                Err(png::EncodingError::IoError(error)) => return Err(TargetError::Io(path, error)),
+                Err(error) => return Err(TargetError::Frame(error.to_string())),
+            },
+        }
+
+        info!("Frame written to {:?}", path);
+        self.rendered += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty directory for the frames of a test
+    fn output(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("widget-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        path
+    }
+
+    #[test]
+    fn writes_the_frames_as_ppm() {
+        let path = output("ppm");
+        let mut target = HeadlessTarget::new(2, path.clone(), ImageFormat::Ppm).expect("Target not created");
+
+        // 2x1 píxeles, el canal alfa no se escribe
+        target.present(&[0xFF10_2030, 0x00FF_8001], 2, 1).expect("Frame not written");
+        assert!(target.is_open());
+
+        let file = fs::read(path.join("frame_00000.ppm")).expect("Frame not found");
+        let mut expected = b"P6\n2 1\n255\n".to_vec();
+        expected.extend_from_slice(&[0x10, 0x20, 0x30, 0xFF, 0x80, 0x01]);
+        assert_eq!(file, expected);
+
+        target.present(&[0; 2], 2, 1).expect("Frame not written");
+        assert!(!target.is_open());
+        assert!(path.join("frame_00001.ppm").exists());
+
+        fs::remove_dir_all(&path).expect("Output not removed");
+    }
+
+    #[test]
+    fn reports_the_output_that_cant_be_written() {
+        // Un archivo en el lugar del directorio de salida
+        let path = output("blocked");
+        fs::write(&path, b"").expect("File not created");
+
+        match HeadlessTarget::new(1, path.join("frames"), ImageFormat::Ppm) {
+            Err(TargetError::Io(failed, _)) => assert_eq!(failed, path.join("frames")),
+            other => panic!("expected an io error, got {:?}", other.err()),
+        }
+
+        fs::remove_file(&path).expect("File not removed");
+    }
+}
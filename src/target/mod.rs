@@ -0,0 +1,76 @@
+pub mod headless;
+pub mod window;
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use minifb::{Key, KeyRepeat};
+use crate::convolutions::InputState;
+
+/// Error of a render target
+#[derive(Debug)]
+pub enum TargetError {
+    /// A file or directory of the output can't be written
+    Io(PathBuf, io::Error),
+    /// The frame can't be encoded or shown
+    Frame(String),
+}
+
+impl fmt::Display for TargetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetError::Io(path, error) => write!(f, "can't write {:?}: {}", path, error),
+            TargetError::Frame(message) => write!(f, "can't present the frame: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for TargetError {}
+
+/// Destination of the rendered frames.
+///
+/// The same layer stack can be presented on a real window or written to
+/// disk, the input methods have a default implementation for targets
+/// without a keyboard.
+pub trait RenderTarget {
+    /// Function to know if the target still accepts frames
+    fn is_open(&self) -> bool;
+    /// Function to present a finished frame
+    fn present(&mut self, buffer: &[u32], width: usize, height: usize) -> Result<(), TargetError>;
+
+    /// Function to process the events of the target without presenting
+    /// a new frame, used when the frame didn't change
+    fn poll(&mut self) {}
+
+    /// Function to know if the target records every frame, so the frames
+    /// without changes must be presented too
+    fn records_frames(&self) -> bool {
+        false
+    }
+
+    /// Function to get the current size of the target, `None` if it
+    /// can't change
+    fn size(&self) -> Option<(usize, usize)> {
+        None
+    }
+
+    fn is_key_down(&self, _key: Key) -> bool {
+        false
+    }
+
+    fn is_key_pressed(&self, _key: Key, _repeat: KeyRepeat) -> bool {
+        false
+    }
+
+    fn is_key_released(&self, _key: Key) -> bool {
+        false
+    }
+
+    /// Function to show a short status text, e.g. the selected parameter
+    fn set_status(&mut self, _status: &str) {}
+
+    /// Function to sample the keyboard and mouse state
+    fn input(&self) -> InputState {
+        InputState::default()
+    }
+}
@@ -0,0 +1,86 @@
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode};
+use crate::convolutions::InputState;
+use crate::target::{RenderTarget, TargetError};
+
+/// Render target backed by a MiniFB window
+pub struct MinifbTarget {
+    window: minifb::Window,
+    title: String,
+}
+
+impl MinifbTarget {
+    pub fn new(title: &str, width: usize, height: usize) -> Self {
+        let mut window = minifb::Window::new(
+            title,
+            width,
+            height,
+            minifb::WindowOptions {
+                borderless: true,
+                title: true,
+                resize: true,
+                scale: minifb::Scale::FitScreen,
+                scale_mode: minifb::ScaleMode::Stretch,
+                topmost: true,
+                transparency: false,
+                none: false,
+            },
+        ).expect("The window can't be created");
+
+        // El ritmo de los frames lo lleva la app (ver `pacing`)
+        window.set_target_fps(0);
+
+        MinifbTarget {
+            window,
+            title: title.to_string(),
+        }
+    }
+}
+
+impl RenderTarget for MinifbTarget {
+    fn is_open(&self) -> bool {
+        self.window.is_open()
+            && !self.window.is_key_down(Key::Escape)
+            && !self.window.is_key_down(Key::Q)
+    }
+
+    fn present(&mut self, buffer: &[u32], width: usize, height: usize) -> Result<(), TargetError> {
+        self.window.update_with_buffer(buffer, width, height)
+            .map_err(|error| TargetError::Frame(error.to_string()))
+    }
+
+    fn poll(&mut self) {
+        self.window.update();
+    }
+
+    fn size(&self) -> Option<(usize, usize)> {
+        Some(self.window.get_size())
+    }
+
+    fn is_key_down(&self, key: Key) -> bool {
+        self.window.is_key_down(key)
+    }
+
+    fn is_key_pressed(&self, key: Key, repeat: KeyRepeat) -> bool {
+        self.window.is_key_pressed(key, repeat)
+    }
+
+    fn is_key_released(&self, key: Key) -> bool {
+        self.window.is_key_released(key)
+    }
+
+    fn set_status(&mut self, status: &str) {
+        self.window.set_title(&format!("{} | {}", self.title, status));
+    }
+
+    fn input(&self) -> InputState {
+        InputState {
+            keys: self.window.get_keys(),
+            mouse: self.window.get_mouse_pos(MouseMode::Discard),
+            buttons: [
+                self.window.get_mouse_down(MouseButton::Left),
+                self.window.get_mouse_down(MouseButton::Middle),
+                self.window.get_mouse_down(MouseButton::Right),
+            ],
+        }
+    }
+}
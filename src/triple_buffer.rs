@@ -0,0 +1,215 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// Finished frame shared between the render thread and the main thread
+#[derive(Debug, Clone, Default)]
+pub struct Frame {
+    pub pixels: Vec<u32>,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Frame in the middle of the triple buffer
+struct Slot {
+    frame: Frame,
+    /// The frame wasn't taken by the reader yet
+    fresh: bool,
+    /// One of the sides was dropped
+    closed: bool,
+}
+
+struct Shared {
+    slot: Mutex<Slot>,
+    changed: Condvar,
+    /// The writer waits for the reader instead of replacing frames
+    lossless: bool,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Slot> {
+        self.slot.lock().expect("The frame buffer is poisoned")
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.changed.notify_all();
+    }
+}
+
+/// Creates the two sides of a triple buffer.
+///
+/// The writer draws on its own buffer and swaps it with the one in the
+/// middle, the reader swaps its buffer with the middle one when there is a
+/// new frame, so none of them waits for the other and the reader always
+/// gets the latest frame. With `lossless` the writer waits until the reader
+/// took the last frame, e.g. to record every frame.
+pub fn triple_buffer(lossless: bool) -> (FrameWriter, FrameReader) {
+    let shared = Arc::new(Shared {
+        slot: Mutex::new(Slot { frame: Frame::default(), fresh: false, closed: false }),
+        changed: Condvar::new(),
+        lossless,
+    });
+
+    let writer = FrameWriter { back: Frame::default(), shared: shared.clone() };
+    let reader = FrameReader { front: Frame::default(), shared };
+
+    (writer, reader)
+}
+
+/// Side of the render thread
+pub struct FrameWriter {
+    back: Frame,
+    shared: Arc<Shared>,
+}
+
+impl FrameWriter {
+    pub fn lossless(&self) -> bool {
+        self.shared.lossless
+    }
+
+    /// Copies a finished frame and publishes it, returns `false` if the
+    /// reader was dropped
+    pub fn publish(&mut self, pixels: &[u32], width: usize, height: usize) -> bool {
+        self.back.pixels.clear();
+        self.back.pixels.extend_from_slice(pixels);
+        self.back.width = width;
+        self.back.height = height;
+
+        let mut slot = self.shared.lock();
+        while self.shared.lossless && slot.fresh && !slot.closed {
+            slot = self.shared.changed.wait(slot).expect("The frame buffer is poisoned");
+        }
+
+        if slot.closed {
+            return false;
+        }
+
+        std::mem::swap(&mut slot.frame, &mut self.back);
+        slot.fresh = true;
+        self.shared.changed.notify_all();
+        true
+    }
+}
+
+impl Drop for FrameWriter {
+    fn drop(&mut self) {
+        self.shared.close();
+    }
+}
+
+/// Side of the main thread
+pub struct FrameReader {
+    front: Frame,
+    shared: Arc<Shared>,
+}
+
+impl FrameReader {
+    /// Takes the latest frame if there is a new one, with `lossless`
+    /// it waits for the next frame
+    pub fn latest(&mut self) -> Option<&Frame> {
+        let mut slot = self.shared.lock();
+        while self.shared.lossless && !slot.fresh && !slot.closed {
+            slot = self.shared.changed.wait(slot).expect("The frame buffer is poisoned");
+        }
+
+        if !slot.fresh {
+            return None;
+        }
+
+        std::mem::swap(&mut slot.frame, &mut self.front);
+        slot.fresh = false;
+        self.shared.changed.notify_all();
+        Some(&self.front)
+    }
+}
+
+impl Drop for FrameReader {
+    fn drop(&mut self) {
+        self.shared.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::*;
+
+    #[test]
+    fn the_reader_gets_the_latest_frame() {
+        let (mut writer, mut reader) = triple_buffer(false);
+        assert!(reader.latest().is_none());
+
+        // El escritor no espera, los frames no leídos se reemplazan
+        for value in 1..=3 {
+            assert!(writer.publish(&[value; 6], 3, 2));
+        }
+
+        let frame = reader.latest().expect("No frame published");
+        assert_eq!((frame.pixels.as_slice(), frame.width, frame.height), ([3; 6].as_slice(), 3, 2));
+        assert!(reader.latest().is_none());
+
+        writer.publish(&[4; 2], 1, 2);
+        assert_eq!(reader.latest().map(|frame| frame.pixels.clone()), Some(vec![4; 2]));
+    }
+
+    #[test]
+    fn lossless_keeps_every_frame() {
+        let (mut writer, mut reader) = triple_buffer(true);
+
+        let render = thread::spawn(move || {
+            for value in 0..100 {
+                assert!(writer.publish(&[value; 4], 2, 2));
+            }
+        });
+
+        // El lector espera cada frame y el escritor a que se lea el anterior
+        for value in 0..100 {
+            let frame = reader.latest().expect("Frame lost");
+            assert_eq!(frame.pixels, [value; 4]);
+        }
+
+        render.join().expect("The writer panicked");
+        assert!(reader.latest().is_none());
+    }
+
+    #[test]
+    fn frames_are_never_torn() {
+        let (mut writer, mut reader) = triple_buffer(false);
+        let (width, height) = (64, 64);
+
+        let render = thread::spawn(move || {
+            for value in 1..=2000 {
+                writer.publish(&vec![value; width * height], width, height);
+            }
+        });
+
+        // Cada frame leído es entero de una sola publicación y no retrocede
+        let mut last = 0;
+        loop {
+            let finished = render.is_finished();
+
+            if let Some(frame) = reader.latest() {
+                let value = frame.pixels[0];
+                assert_eq!(frame.pixels.len(), width * height);
+                assert!(frame.pixels.iter().all(|&pixel| pixel == value), "torn frame");
+                assert!(value > last);
+                last = value;
+            } else if finished {
+                break;
+            }
+        }
+
+        render.join().expect("The writer panicked");
+        assert_eq!(last, 2000);
+    }
+
+    #[test]
+    fn dropping_a_side_closes_the_buffer() {
+        let (mut writer, reader) = triple_buffer(true);
+        drop(reader);
+        assert!(!writer.publish(&[1], 1, 1));
+
+        let (writer, mut reader) = triple_buffer(true);
+        drop(writer);
+        assert!(reader.latest().is_none());
+    }
+}